@@ -0,0 +1,228 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, ErrorKind, Write};
+use std::path::PathBuf;
+
+use rspotify::{prelude::*, AuthCodeSpotify, Config, Credentials, OAuth};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+
+use crate::error::CoolioError;
+use crate::service::io::Interactor;
+use crate::settings::Spotify;
+
+const NOT_LOGGED_IN: &str = "not logged in to Spotify, run `coolio auth login` first";
+
+// how long to wait for the authorization in the browser
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthCommand {
+    Login,
+    Status,
+    Logout,
+}
+
+pub fn new_client(conf: &Spotify) -> AuthCodeSpotify {
+    let creds = Credentials::new(&conf.client_id, &conf.client_secret);
+
+    let oauth = OAuth {
+        redirect_uri: conf.redirect_uri.clone(),
+        scopes: HashSet::from_iter(conf.scopes.clone()),
+        ..Default::default()
+    };
+
+    let conf = Config {
+        cache_path: PathBuf::from(&conf.token_cache),
+        token_cached: true,
+        token_refreshing: true,
+        ..Config::default()
+    };
+    AuthCodeSpotify::with_config(creds, oauth, conf)
+}
+
+/// Loads the cached token into the client, refreshing it if it has expired.
+pub async fn load_token(spotify: &mut AuthCodeSpotify) -> Result<(), CoolioError> {
+    let token = match spotify.read_token_cache(true).await {
+        Ok(Some(token)) => token,
+        // a missing or unreadable cache, or one without all the scopes
+        _ => return Err(NOT_LOGGED_IN.into()),
+    };
+
+    let expired = token.is_expired();
+    *spotify
+        .token
+        .lock()
+        .await
+        .map_err(|_| "token lock failed")? = Some(token);
+    if expired {
+        match spotify.refetch_token().await? {
+            Some(token) => {
+                *spotify
+                    .token
+                    .lock()
+                    .await
+                    .map_err(|_| "token lock failed")? = Some(token)
+            }
+            None => return Err(NOT_LOGGED_IN.into()),
+        }
+        spotify.write_token_cache().await?;
+    }
+    Ok(())
+}
+
+/// Splits an `http://host[:port]/path` redirect URI into the address to
+/// listen on and the path of the callback.
+pub fn callback_address(redirect_uri: &str) -> Result<(String, String), CoolioError> {
+    let rest = redirect_uri
+        .strip_prefix("http://")
+        .ok_or("the redirect uri has to be a local http address")?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return Err("the redirect uri doesn't have a host".into());
+    }
+
+    let addr = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    Ok((addr, path.to_string()))
+}
+
+fn query_param(target: &str, name: &str) -> Option<String> {
+    let (_, query) = target.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v.to_string())
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) -> Result<(), CoolioError> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+/// Waits for the browser to be redirected to the callback and returns the
+/// authorization code it carries.
+pub async fn receive_code(
+    spotify: &AuthCodeSpotify,
+    listener: &TcpListener,
+    redirect_uri: &str,
+    path: &str,
+) -> Result<String, CoolioError> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let mut buf = vec![0; 8192];
+        let n = stream.read(&mut buf).await?;
+        let request = String::from_utf8_lossy(&buf[..n]);
+
+        // the request line is `GET <target> HTTP/1.1`
+        let target = request
+            .lines()
+            .next()
+            .and_then(|l| l.split_whitespace().nth(1))
+            .unwrap_or_default()
+            .to_string();
+        if target.split('?').next() != Some(path) {
+            // browsers also ask for things like the favicon
+            respond(&mut stream, "404 Not Found", "").await?;
+            continue;
+        }
+
+        let url = format!("{}{}", redirect_uri.trim_end_matches(path), target);
+        if let Some(code) = spotify.parse_response_code(&url) {
+            respond(
+                &mut stream,
+                "200 OK",
+                "coolio is authorized, you can close this window.",
+            )
+            .await?;
+            return Ok(code);
+        }
+
+        respond(
+            &mut stream,
+            "400 Bad Request",
+            "coolio authorization failed.",
+        )
+        .await?;
+        return Err(match query_param(&target, "error") {
+            Some(e) => format!("Spotify authorization failed: {}", e).into(),
+            None => "Spotify authorization failed: no valid code in the callback".into(),
+        });
+    }
+}
+
+pub async fn login<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+    conf: &Spotify,
+    int: &mut Interactor<'a, R, W>,
+) -> Result<(), CoolioError> {
+    let mut spotify = new_client(conf);
+    let (addr, path) = callback_address(&conf.redirect_uri)?;
+    let listener = TcpListener::bind(&addr)
+        .await
+        .map_err(|e| format!("can't listen for the callback on {}: {}", addr, e))?;
+
+    let url = spotify.get_authorize_url(false)?;
+    int.authorize_url(&url)?;
+
+    let code = timeout(
+        LOGIN_TIMEOUT,
+        receive_code(&spotify, &listener, &conf.redirect_uri, &path),
+    )
+    .await
+    .map_err(|_| "timed out waiting for the Spotify authorization")??;
+
+    spotify.request_token(&code).await?;
+    spotify.write_token_cache().await?;
+
+    let me = spotify.current_user().await?;
+    int.auth_status(Some(me.id.as_ref()))
+}
+
+pub async fn status<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+    conf: &Spotify,
+    int: &mut Interactor<'a, R, W>,
+) -> Result<(), CoolioError> {
+    let mut spotify = new_client(conf);
+    if load_token(&mut spotify).await.is_err() {
+        return int.auth_status(None);
+    }
+    let me = spotify.current_user().await?;
+    int.auth_status(Some(me.id.as_ref()))
+}
+
+pub fn logout<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+    conf: &Spotify,
+    int: &mut Interactor<'a, R, W>,
+) -> Result<(), CoolioError> {
+    match fs::remove_file(&conf.token_cache) {
+        Ok(()) => int.logged_out(true),
+        Err(e) if e.kind() == ErrorKind::NotFound => int.logged_out(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn run<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+    command: AuthCommand,
+    conf: &Spotify,
+    int: &mut Interactor<'a, R, W>,
+) -> Result<(), CoolioError> {
+    match command {
+        AuthCommand::Login => login(conf, int).await,
+        AuthCommand::Status => status(conf, int).await,
+        AuthCommand::Logout => logout(conf, int),
+    }
+}
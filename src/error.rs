@@ -1,13 +1,33 @@
-use std::{error, fmt, io};
+use std::{error, fmt, io, time::Duration};
 
 use chrono::ParseError;
 use clap::Error as ClapError;
 use config::ConfigError;
+use regex::Error as RegexError;
+use rspotify::http::HttpError;
 use rspotify::model::IdError;
+use rspotify::ClientError;
+use rusqlite::Error as SqliteError;
+
+/// How a failed request to Spotify can be retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    /// Rate limited, possibly with the time after which to retry.
+    RateLimited(Option<Duration>),
+    /// Server or network errors, which may go away on their own.
+    Transient,
+}
 
 #[derive(Debug)]
 pub struct CoolioError {
     msg: String,
+    retry: Option<Retry>,
+}
+
+impl CoolioError {
+    pub fn retry(&self) -> Option<Retry> {
+        self.retry
+    }
 }
 
 impl fmt::Display for CoolioError {
@@ -22,14 +42,49 @@ impl From<tokio_postgres::Error> for CoolioError {
     fn from(e: tokio_postgres::Error) -> Self {
         CoolioError {
             msg: format!("Db error received: {}", e),
+            retry: None,
         }
     }
 }
 
-impl From<rspotify::ClientError> for CoolioError {
-    fn from(e: rspotify::ClientError) -> Self {
+impl From<SqliteError> for CoolioError {
+    fn from(e: SqliteError) -> Self {
+        CoolioError {
+            msg: format!("Db error received: {}", e),
+            retry: None,
+        }
+    }
+}
+
+fn retry_for(e: &ClientError) -> Option<Retry> {
+    match e {
+        ClientError::Http(e) => match e.as_ref() {
+            HttpError::StatusCode(response) => match response.status().as_u16() {
+                429 => Some(Retry::RateLimited(
+                    response
+                        .headers()
+                        .get("retry-after")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.trim().parse::<u64>().ok())
+                        .map(Duration::from_secs),
+                )),
+                500..=599 => Some(Retry::Transient),
+                _ => None,
+            },
+            HttpError::Client(e) if e.is_timeout() || e.is_connect() || e.is_request() => {
+                Some(Retry::Transient)
+            }
+            HttpError::Client(_) => None,
+        },
+        _ => None,
+    }
+}
+
+impl From<ClientError> for CoolioError {
+    fn from(e: ClientError) -> Self {
         CoolioError {
             msg: format!("Spotify API error received: {}", e),
+            retry: retry_for(&e),
         }
     }
 }
@@ -38,6 +93,7 @@ impl From<IdError> for CoolioError {
     fn from(e: IdError) -> Self {
         CoolioError {
             msg: format!("Spotify id extract error received: {}", e),
+            retry: None,
         }
     }
 }
@@ -46,19 +102,35 @@ impl From<ConfigError> for CoolioError {
     fn from(e: ConfigError) -> Self {
         CoolioError {
             msg: format!("Config error received: {}", e),
+            retry: None,
+        }
+    }
+}
+
+impl From<RegexError> for CoolioError {
+    fn from(e: RegexError) -> Self {
+        CoolioError {
+            msg: format!("Pattern error: {}", e),
+            retry: None,
         }
     }
 }
 
 impl From<&str> for CoolioError {
     fn from(s: &str) -> Self {
-        CoolioError { msg: s.to_string() }
+        CoolioError {
+            msg: s.to_string(),
+            retry: None,
+        }
     }
 }
 
 impl From<String> for CoolioError {
     fn from(s: String) -> Self {
-        CoolioError { msg: s }
+        CoolioError {
+            msg: s,
+            retry: None,
+        }
     }
 }
 
@@ -66,6 +138,7 @@ impl From<io::Error> for CoolioError {
     fn from(e: io::Error) -> Self {
         CoolioError {
             msg: format!("IO error received: {}", e),
+            retry: None,
         }
     }
 }
@@ -74,6 +147,7 @@ impl From<ParseError> for CoolioError {
     fn from(e: ParseError) -> Self {
         CoolioError {
             msg: format!("Parsing time error: {}", e),
+            retry: None,
         }
     }
 }
@@ -82,6 +156,25 @@ impl From<csv::Error> for CoolioError {
     fn from(e: csv::Error) -> Self {
         CoolioError {
             msg: format!("Persisting to file error: {}", e),
+            retry: None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for CoolioError {
+    fn from(e: serde_json::Error) -> Self {
+        CoolioError {
+            msg: format!("Parsing json error: {}", e),
+            retry: None,
+        }
+    }
+}
+
+impl From<cron::error::Error> for CoolioError {
+    fn from(e: cron::error::Error) -> Self {
+        CoolioError {
+            msg: format!("Schedule error: {}", e),
+            retry: None,
         }
     }
 }
@@ -90,6 +183,7 @@ impl From<ClapError> for CoolioError {
     fn from(e: ClapError) -> Self {
         CoolioError {
             msg: format!("{}", e),
+            retry: None,
         }
     }
 }
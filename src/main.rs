@@ -1,3 +1,4 @@
+mod auth;
 mod builder;
 mod error;
 mod models;
@@ -9,24 +10,53 @@ mod storage;
 use error::CoolioError;
 use parser::Parser;
 use std::env;
+use std::io::{stdin, stdout, BufReader};
 
 use builder::{new_spotify, new_storage};
+use service::io::Interactor;
 use service::Service;
 use settings::Settings;
+use storage::copy::{copy, StorageCommand};
 
 async fn execute() -> Result<(), CoolioError> {
     let parser = Parser::new(env::args_os())?;
     let settings = Settings::new()?;
-    let spotify = new_spotify(settings.spotify).await;
+
+    // logging in can't need a client that is already logged in
+    if let Some(command) = parser.auth_command() {
+        let w = &mut stdout();
+        let mut int = Interactor::new(BufReader::new(stdin()), w);
+        return auth::run(command, &settings.spotify, &mut int).await;
+    }
+
+    if let Some(command) = parser.storage_command() {
+        let w = &mut stdout();
+        let mut int = Interactor::new(BufReader::new(stdin()), w);
+        return match command {
+            StorageCommand::Copy { from, to, merge } => {
+                if from == to {
+                    return Err("can't copy a storage into itself".into());
+                }
+                let from = new_storage(settings.storage_section(&from)?).await?;
+                let to = new_storage(settings.storage_section(&to)?).await?;
+                let summary = copy(&from, &to, merge).await?;
+                int.copy_summary(&summary)
+            }
+        };
+    }
+
+    let spotify = new_spotify(&settings.spotify, settings.retry).await?;
     let storage = new_storage(settings.storage).await?;
-    let service = Service::new(&spotify, &storage);
+    let service = Service::new(&spotify, &storage).with_default_rules(settings.rules);
 
-    parser.parse(&service).await
+    parser.parse(&service, &settings.daemon).await
 }
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    // the daemon reports its runs through the log
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("coolio=info"))
+        .init();
 
     if let Err(e) = execute().await {
         println!("{}", e)
@@ -1,6 +1,8 @@
+use std::fmt;
 use std::str::FromStr;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
 
 use crate::error::CoolioError;
 
@@ -8,6 +10,44 @@ use crate::error::CoolioError;
 pub struct Listen {
     pub song_id: String,
     pub time: DateTime<Utc>,
+    pub ms_played: Option<u32>,
+    pub skipped: Option<bool>,
+    pub platform: Option<String>,
+    pub context: Option<String>,
+    pub track: Option<TrackMetadata>,
+}
+
+impl Listen {
+    /// A track can't be played twice at the same moment, so this identifies a listen.
+    pub fn key(&self) -> (String, DateTime<Utc>) {
+        (self.song_id.clone(), self.time)
+    }
+
+    /// Fills whatever this listen is missing from another record of the same listen.
+    pub fn merge(&mut self, other: Listen) {
+        self.ms_played = self.ms_played.or(other.ms_played);
+        self.skipped = self.skipped.or(other.skipped);
+        self.platform = self.platform.take().or(other.platform);
+        self.context = self.context.take().or(other.context);
+        self.track = self.track.take().or(other.track);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackMetadata {
+    pub name: String,
+    pub artist_ids: Vec<String>,
+    pub artist_names: Vec<String>,
+    pub album_id: Option<String>,
+    pub album_name: Option<String>,
+    pub duration_ms: u32,
+}
+
+/// Period in which listens may have been played but couldn't be recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gap {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -16,6 +56,336 @@ pub struct Playlist {
     pub name: String,
     pub artists: Vec<String>,
     pub automated: bool,
+    // whether updates may add tracks that are already in the playlist
+    pub allow_duplicates: bool,
+    // none when the playlist uses the default rules from the settings
+    pub rules: Option<ExclusionRules>,
+    pub retention: Retention,
+}
+
+/// How long tracks are kept in a playlist, updates remove the ones past it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Retention {
+    pub max_tracks: Option<usize>,
+    // days since the track was added
+    pub max_age_days: Option<u32>,
+}
+
+/// Changes to the retention of a playlist, zero removing the limit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionEdit {
+    pub max_tracks: Option<usize>,
+    pub max_age_days: Option<u32>,
+}
+
+impl Retention {
+    pub fn is_limited(&self) -> bool {
+        self.max_tracks.is_some() || self.max_age_days.is_some()
+    }
+
+    pub fn apply(&mut self, edit: &RetentionEdit) {
+        if let Some(tracks) = edit.max_tracks {
+            self.max_tracks = Some(tracks).filter(|t| *t > 0);
+        }
+        if let Some(days) = edit.max_age_days {
+            self.max_age_days = Some(days).filter(|d| *d > 0);
+        }
+    }
+}
+
+/// Tracks that playlist updates leave out, like live versions and remixes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExclusionRules {
+    // case insensitive regexes matched against the track and album names
+    pub patterns: Vec<String>,
+    pub min_duration_secs: Option<u32>,
+    pub max_duration_secs: Option<u32>,
+    pub skip_explicit: bool,
+}
+
+/// Changes to the exclusion rules of a playlist.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RulesEdit {
+    // goes back to the default rules
+    pub reset: bool,
+    pub exclude: Vec<String>,
+    pub include: Vec<String>,
+    // zero removes the limit
+    pub min_duration_secs: Option<u32>,
+    pub max_duration_secs: Option<u32>,
+    pub skip_explicit: Option<bool>,
+}
+
+impl RulesEdit {
+    pub fn is_empty(&self) -> bool {
+        *self == RulesEdit::default()
+    }
+}
+
+impl ExclusionRules {
+    pub fn apply(&mut self, edit: &RulesEdit) {
+        self.patterns.retain(|p| !edit.include.contains(p));
+        for p in &edit.exclude {
+            if !self.patterns.contains(p) {
+                self.patterns.push(p.clone());
+            }
+        }
+        if let Some(secs) = edit.min_duration_secs {
+            self.min_duration_secs = Some(secs).filter(|s| *s > 0);
+        }
+        if let Some(secs) = edit.max_duration_secs {
+            self.max_duration_secs = Some(secs).filter(|s| *s > 0);
+        }
+        if let Some(skip) = edit.skip_explicit {
+            self.skip_explicit = skip;
+        }
+    }
+}
+
+/// How far playlist updates got with a linked artist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkCheckpoint {
+    pub last_checked: DateTime<Utc>,
+    // albums that were already considered for the playlist
+    pub albums: Vec<String>,
+}
+
+/// How an artist is picked from the results of searching for it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArtistChoice {
+    // asks which of the results to take
+    #[default]
+    Prompt,
+    // takes the first result
+    First,
+    // takes the only result, or the one named exactly as searched, failing otherwise
+    Unique,
+}
+
+/// The kinds of releases Spotify groups an artist's albums in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReleaseType {
+    Album,
+    Single,
+    Compilation,
+    AppearsOn,
+}
+
+impl fmt::Display for ReleaseType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReleaseType::Album => write!(f, "album"),
+            ReleaseType::Single => write!(f, "single"),
+            ReleaseType::Compilation => write!(f, "compilation"),
+            ReleaseType::AppearsOn => write!(f, "appears_on"),
+        }
+    }
+}
+
+impl FromStr for ReleaseType {
+    type Err = CoolioError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "album" => Ok(ReleaseType::Album),
+            "single" => Ok(ReleaseType::Single),
+            "compilation" => Ok(ReleaseType::Compilation),
+            "appears_on" => Ok(ReleaseType::AppearsOn),
+            _ => Err(format!("Unknown release type {}", s).into()),
+        }
+    }
+}
+
+impl ReleaseType {
+    /// Parses a comma separated list of release types.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, CoolioError> {
+        let mut types = Vec::new();
+        for t in s.split(',') {
+            let t = t.trim().parse()?;
+            if !types.contains(&t) {
+                types.push(t);
+            }
+        }
+        Ok(types)
+    }
+}
+
+/// How playlist updates treat a linked artist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkOptions {
+    pub release_types: Vec<ReleaseType>,
+    // popular tracks added while the artist has nothing in the playlist
+    pub seed: usize,
+    // whether tracks where the artist is only featured are added
+    pub include_features: bool,
+}
+
+impl Default for LinkOptions {
+    fn default() -> Self {
+        LinkOptions {
+            release_types: vec![ReleaseType::Album, ReleaseType::Single],
+            seed: 5,
+            include_features: true,
+        }
+    }
+}
+
+/// Changes to the options of a link, the missing ones are kept as they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkEdit {
+    pub release_types: Option<Vec<ReleaseType>>,
+    pub seed: Option<usize>,
+    pub include_features: Option<bool>,
+}
+
+/// What linking artists to a playlist was asked for: how the artists are
+/// picked and the options they are linked with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkRequest {
+    pub choice: ArtistChoice,
+    pub edit: LinkEdit,
+}
+
+impl LinkOptions {
+    pub fn apply(&mut self, edit: &LinkEdit) {
+        if let Some(types) = &edit.release_types {
+            self.release_types = types.clone();
+        }
+        if let Some(seed) = edit.seed {
+            self.seed = seed;
+        }
+        if let Some(features) = edit.include_features {
+            self.include_features = features;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdditionReason {
+    // popular tracks of an artist that has nothing in the playlist yet
+    Seed,
+    // released after the artist's last track was added
+    NewRelease,
+}
+
+impl fmt::Display for AdditionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdditionReason::Seed => write!(f, "popular track"),
+            AdditionReason::NewRelease => write!(f, "new release"),
+        }
+    }
+}
+
+/// A track a playlist update adds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedAddition {
+    pub playlist: String,
+    pub artist: String,
+    pub album: Option<String>,
+    pub track: String,
+    pub reason: AdditionReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalReason {
+    // more tracks than the playlist keeps, and among the oldest
+    MaxTracks,
+    // added longer ago than the playlist keeps tracks for
+    MaxAge,
+}
+
+impl fmt::Display for RemovalReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemovalReason::MaxTracks => write!(f, "over max tracks"),
+            RemovalReason::MaxAge => write!(f, "past max age"),
+        }
+    }
+}
+
+/// A track a playlist update removes for the retention of the playlist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedRemoval {
+    pub playlist: String,
+    pub artist: String,
+    pub track: String,
+    pub reason: RemovalReason,
+}
+
+/// The tracks a playlist update adds and removes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlannedChanges {
+    pub additions: Vec<PlannedAddition>,
+    pub removals: Vec<PlannedRemoval>,
+}
+
+impl fmt::Display for LinkOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let types = self
+            .release_types
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<String>>();
+        write!(f, "types: {}, seed: {}", types.join(","), self.seed)?;
+        if !self.include_features {
+            write!(f, ", without features")?;
+        }
+        Ok(())
+    }
+}
+
+/// A change `coolio sync` makes to bring the playlists in line with the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncAction {
+    CreatePlaylist {
+        playlist: String,
+    },
+    // the playlist is in Spotify already, it just isn't automated
+    AutomatePlaylist {
+        playlist: String,
+    },
+    Link {
+        playlist: String,
+        artist_id: String,
+        artist: String,
+        options: LinkOptions,
+    },
+    EditLink {
+        playlist: String,
+        artist_id: String,
+        artist: String,
+        options: LinkOptions,
+    },
+    Unlink {
+        playlist: String,
+        artist_id: String,
+        artist: String,
+    },
+}
+
+impl fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncAction::CreatePlaylist { playlist } => write!(f, "+ create {}", playlist),
+            SyncAction::AutomatePlaylist { playlist } => write!(f, "+ automate {}", playlist),
+            SyncAction::Link {
+                playlist,
+                artist,
+                options,
+                ..
+            } => write!(f, "+ link {} to {} ({})", artist, playlist, options),
+            SyncAction::EditLink {
+                playlist,
+                artist,
+                options,
+                ..
+            } => write!(f, "~ change {} in {} ({})", artist, playlist, options),
+            SyncAction::Unlink {
+                playlist, artist, ..
+            } => write!(f, "- unlink {} from {}", artist, playlist),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -26,6 +396,54 @@ pub enum ThrowbackPeriod {
     Days(usize),
 }
 
+#[derive(Debug, Default)]
+pub struct HistoryWindow {
+    pub period: Option<ThrowbackPeriod>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl HistoryWindow {
+    pub fn contains(&self, time: &DateTime<Utc>) -> bool {
+        let (from, to) = self.bounds();
+        if let Some(from) = from {
+            if *time < from {
+                return false;
+            }
+        }
+        if let Some(to) = to {
+            if *time >= to {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn bounds(&self) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        let start_of_day = |d: NaiveDate| {
+            DateTime::<Utc>::from_naive_utc_and_offset(d.and_time(NaiveTime::MIN), Utc)
+        };
+        let from = match &self.period {
+            Some(p) => Some(Utc::now() - p.duration()),
+            None => self.from.map(start_of_day),
+        };
+        // the end date is inclusive
+        let to = self.to.map(|d| start_of_day(d) + Duration::days(1));
+        (from, to)
+    }
+}
+
+impl ThrowbackPeriod {
+    pub fn duration(&self) -> Duration {
+        match self {
+            ThrowbackPeriod::Days(d) => Duration::days(*d as i64),
+            ThrowbackPeriod::Weeks(w) => Duration::weeks(*w as i64),
+            ThrowbackPeriod::Months(m) => Duration::days((m * 30) as i64),
+            ThrowbackPeriod::Years(y) => Duration::days((y * 365) as i64),
+        }
+    }
+}
+
 impl FromStr for ThrowbackPeriod {
     type Err = CoolioError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
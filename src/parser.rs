@@ -1,10 +1,80 @@
 use std::ffi::OsString;
 use std::io::{stdin, stdout, BufReader, Stdin, Stdout};
 
+use crate::auth::AuthCommand;
+use crate::error::CoolioError;
+use crate::models::{
+    ArtistChoice, HistoryWindow, LinkEdit, LinkRequest, ReleaseType, RetentionEdit, RulesEdit,
+    ThrowbackPeriod,
+};
 use crate::service::io::Interactor;
 use crate::service::ServiceTrait;
-use crate::{error::CoolioError, models::ThrowbackPeriod};
+use crate::settings::Daemon;
+use crate::storage::copy::StorageCommand;
+use chrono::NaiveDate;
 use clap::{app_from_crate, arg, App, AppSettings, ArgMatches};
+use regex::Regex;
+
+/// Adds the flags setting the options of a link.
+fn link_options(app: App) -> App {
+    app.arg(
+        arg!(-t --types [TYPES] "release types to add, comma separated")
+            .validator(ReleaseType::parse_list),
+    )
+    .arg(arg!(-s --seed [SEED] "number of songs to seed").validator(|x| x.parse::<usize>()))
+    .arg(arg!(--features "adds tracks the artist is only featured on"))
+    .arg(
+        arg!(--"no-features" "skips tracks the artist is only featured on")
+            .conflicts_with("features"),
+    )
+}
+
+fn link_request(matches: &ArgMatches) -> LinkRequest {
+    let choice = if matches.is_present("first") {
+        ArtistChoice::First
+    } else if matches.is_present("non-interactive") {
+        ArtistChoice::Unique
+    } else {
+        ArtistChoice::Prompt
+    };
+    LinkRequest {
+        choice,
+        edit: link_edit(matches),
+    }
+}
+
+fn link_edit(matches: &ArgMatches) -> LinkEdit {
+    LinkEdit {
+        release_types: matches
+            .value_of("types")
+            .map(|x| ReleaseType::parse_list(x).unwrap()),
+        seed: matches.value_of_t("seed").ok(),
+        include_features: if matches.is_present("features") {
+            Some(true)
+        } else if matches.is_present("no-features") {
+            Some(false)
+        } else {
+            None
+        },
+    }
+}
+
+fn rules_edit(matches: &ArgMatches) -> RulesEdit {
+    let values = |name: &str| -> Vec<String> {
+        matches
+            .values_of(name)
+            .map(|v| v.map(String::from).collect())
+            .unwrap_or_default()
+    };
+    RulesEdit {
+        reset: matches.is_present("reset"),
+        exclude: values("exclude"),
+        include: values("include"),
+        min_duration_secs: matches.value_of_t("min-duration").ok(),
+        max_duration_secs: matches.value_of_t("max-duration").ok(),
+        skip_explicit: matches.value_of("explicit").map(|x| x == "skip"),
+    }
+}
 
 #[derive(Debug)]
 pub struct Parser {
@@ -22,6 +92,43 @@ impl Parser {
                     .setting(AppSettings::SubcommandRequiredElseHelp)
                     .about("History of listened tracks")
                     .subcommand(App::new("update").about("Updates the recent history"))
+                    .subcommand(
+                        App::new("import")
+                            .about("Imports history from a Spotify data export")
+                            .arg(arg!(<DIR> "directory of the extracted export")),
+                    )
+                    .subcommand(
+                        App::new("backfill")
+                            .about("Fills in track metadata for listens recorded without it"),
+                    )
+                    .subcommand(
+                        App::new("gaps")
+                            .about("Lists periods in which listens couldn't be recorded"),
+                    )
+                    .subcommand(
+                        App::new("dedupe").about("Removes listens that were recorded twice"),
+                    )
+                    .subcommand(
+                        App::new("stats")
+                            .about("Shows top tracks, artists and albums of the history")
+                            .arg(
+                                arg!(-p --period [PERIOD] "period to look back on")
+                                    .validator(|x| x.parse::<ThrowbackPeriod>())
+                                    .conflicts_with_all(&["from", "to"]),
+                            )
+                            .arg(
+                                arg!(--from [FROM] "start date of the window (YYYY-MM-DD)")
+                                    .validator(|x| x.parse::<NaiveDate>()),
+                            )
+                            .arg(
+                                arg!(--to [TO] "end date of the window (YYYY-MM-DD)")
+                                    .validator(|x| x.parse::<NaiveDate>()),
+                            )
+                            .arg(
+                                arg!(-s --size [SIZE] "number of top entries to show")
+                                    .validator(|x| x.parse::<usize>()),
+                            ),
+                    )
                     .subcommand(
                         App::new("throwback")
                             .about("Create a playlist of throwback songs")
@@ -46,16 +153,34 @@ impl Parser {
                             .about("Creates an automated playlist")
                             .arg(arg!(<PLAYLIST> "name of the playlist")),
                     )
-                    .subcommand(
+                    .subcommand(link_options(
                         App::new("link")
                             .about("Links the artist to an automated playlist")
                             .arg(arg!(<PLAYLIST> "name of the playlist"))
-                            .arg(arg!(<ARTIST> "name of the artist"))
+                            .arg(arg!(<ARTIST> "name, URI or URL of the artist"))
+                            .arg(arg!(--first "takes the first artist found instead of asking"))
                             .arg(
-                                arg!(-s --seed [SEED] "number of songs to seed")
-                                    .validator(|x| x.parse::<usize>()),
+                                arg!(--"non-interactive" "fails when ambiguous instead of asking")
+                                    .conflicts_with("first"),
                             ),
-                    )
+                    ))
+                    .subcommand(link_options(
+                        App::new("link-file")
+                            .about("Links the artists listed in the file to the playlist")
+                            .arg(arg!(<PLAYLIST> "name of the playlist"))
+                            .arg(arg!(<FILE> "file with an artist per line, or a CSV file"))
+                            .arg(arg!(--first "takes the first artist found instead of asking"))
+                            .arg(
+                                arg!(--"non-interactive" "reports ambiguous names instead of asking")
+                                    .conflicts_with("first"),
+                            ),
+                    ))
+                    .subcommand(link_options(
+                        App::new("link-edit")
+                            .about("Changes the options of a linked artist")
+                            .arg(arg!(<PLAYLIST> "name of the playlist"))
+                            .arg(arg!(<ARTIST> "name of the artist")),
+                    ))
                     .subcommand(
                         App::new("unlink")
                             .about("Unlinks the artist from the playlist")
@@ -63,24 +188,157 @@ impl Parser {
                             .arg(arg!(<ARTIST> "name of the artist")),
                     )
                     .subcommand(
-                        App::new("update").about("Adds new artists' songs to the playlists"),
+                        App::new("unlink-file")
+                            .about("Unlinks the artists listed in the file from the playlist")
+                            .arg(arg!(<PLAYLIST> "name of the playlist"))
+                            .arg(arg!(<FILE> "file with an artist per line, or a CSV file")),
+                    )
+                    .subcommand(
+                        App::new("update")
+                            .about("Adds new artists' songs to the playlists")
+                            .arg(arg!(--"dry-run" "shows the songs that would be added")),
                     )
                     .subcommand(
                         App::new("automate")
                             .about("Automates an already existing playlist in Spotify")
                             .arg(arg!(<PLAYLIST> "name of the playlist")),
                     )
+                    .subcommand(
+                        App::new("duplicates")
+                            .about("Sets whether updates may add tracks already in the playlist")
+                            .arg(arg!(<PLAYLIST> "name of the playlist"))
+                            .arg(
+                                arg!(<SETTING> "allow or skip duplicates")
+                                    .possible_values(["allow", "skip"]),
+                            ),
+                    )
+                    .subcommand(
+                        App::new("rules")
+                            .about("Shows or changes which tracks updates leave out of a playlist")
+                            .arg(arg!(<PLAYLIST> "name of the playlist"))
+                            .arg(
+                                arg!(-e --exclude <PATTERN> "regex of names to leave out")
+                                    .required(false)
+                                    .multiple_occurrences(true)
+                                    .validator(Regex::new),
+                            )
+                            .arg(
+                                arg!(-i --include <PATTERN> "removes an excluded regex")
+                                    .required(false)
+                                    .multiple_occurrences(true),
+                            )
+                            .arg(
+                                arg!(--"min-duration" [SECS] "shortest track to add, 0 for none")
+                                    .validator(|x| x.parse::<u32>()),
+                            )
+                            .arg(
+                                arg!(--"max-duration" [SECS] "longest track to add, 0 for none")
+                                    .validator(|x| x.parse::<u32>()),
+                            )
+                            .arg(
+                                arg!(--explicit [SETTING] "allow or skip explicit tracks")
+                                    .possible_values(["allow", "skip"]),
+                            )
+                            .arg(
+                                arg!(--reset "goes back to the default rules").conflicts_with_all(
+                                    &[
+                                        "exclude",
+                                        "include",
+                                        "min-duration",
+                                        "max-duration",
+                                        "explicit",
+                                    ],
+                                ),
+                            ),
+                    )
+                    .subcommand(
+                        App::new("retention")
+                            .about("Shows or changes how long updates keep tracks in a playlist")
+                            .arg(arg!(<PLAYLIST> "name of the playlist"))
+                            .arg(
+                                arg!(--"max-tracks" [TRACKS] "most tracks to keep, 0 for no limit")
+                                    .validator(|x| x.parse::<usize>()),
+                            )
+                            .arg(
+                                arg!(--"max-age" [DAYS] "days to keep a track for, 0 for no limit")
+                                    .validator(|x| x.parse::<u32>()),
+                            ),
+                    )
                     .subcommand(
                         App::new("show")
                             .about("Shows info for a playlist")
                             .arg(arg!(<PLAYLIST> "name of the playlist")),
                     ),
             )
+            .subcommand(
+                App::new("auth")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .about("Spotify authorization")
+                    .subcommand(App::new("login").about("Logs in to Spotify through the browser"))
+                    .subcommand(App::new("status").about("Shows who is logged in"))
+                    .subcommand(App::new("logout").about("Removes the cached token")),
+            )
+            .subcommand(
+                App::new("daemon").about("Runs the history and playlists updates on a schedule"),
+            )
+            .subcommand(
+                App::new("storage")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .about("Manage the storages")
+                    .subcommand(
+                        App::new("copy")
+                            .about("Copies everything from one storage in the settings to another")
+                            .arg(arg!(--from <SECTION> "storage to copy from"))
+                            .arg(arg!(--to <SECTION> "storage to copy to"))
+                            .arg(arg!(--merge "copies into a storage that isn't empty")),
+                    ),
+            )
+            .subcommand(
+                App::new("sync")
+                    .about("Makes the automated playlists match the ones described in the file")
+                    .arg(arg!(<FILE> "TOML file describing the playlists"))
+                    .arg(arg!(--plan "only shows the changes, the default"))
+                    .arg(arg!(--apply "makes the changes").conflicts_with("plan")),
+            )
             .try_get_matches_from(args)?;
         Ok(Parser { matches })
     }
 
-    pub async fn parse<S: ServiceTrait>(&self, service: &S) -> Result<(), CoolioError> {
+    /// Auth commands are run before there is a service, as the service needs
+    /// a logged in client.
+    pub fn auth_command(&self) -> Option<AuthCommand> {
+        match self.matches.subcommand() {
+            Some(("auth", auth_matches)) => match auth_matches.subcommand() {
+                Some(("login", _)) => Some(AuthCommand::Login),
+                Some(("status", _)) => Some(AuthCommand::Status),
+                Some(("logout", _)) => Some(AuthCommand::Logout),
+                _ => unreachable!(),
+            },
+            _ => None,
+        }
+    }
+
+    /// Storage commands are run before there is a service, as they work
+    /// with storages other than the one in use.
+    pub fn storage_command(&self) -> Option<StorageCommand> {
+        match self.matches.subcommand() {
+            Some(("storage", storage_matches)) => match storage_matches.subcommand() {
+                Some(("copy", copy_matches)) => Some(StorageCommand::Copy {
+                    from: copy_matches.value_of("from").unwrap().to_string(),
+                    to: copy_matches.value_of("to").unwrap().to_string(),
+                    merge: copy_matches.is_present("merge"),
+                }),
+                _ => unreachable!(),
+            },
+            _ => None,
+        }
+    }
+
+    pub async fn parse<S: ServiceTrait>(
+        &self,
+        service: &S,
+        daemon: &Daemon,
+    ) -> Result<(), CoolioError> {
         let r = BufReader::new(stdin());
         let w = &mut stdout();
         let mut int = Interactor::<BufReader<Stdin>, Stdout>::new(r, w);
@@ -88,6 +346,27 @@ impl Parser {
         match self.matches.subcommand() {
             Some(("history", history_matches)) => match history_matches.subcommand() {
                 Some(("update", _update_matches)) => service.history_update().await,
+                Some(("import", import_matches)) => {
+                    service
+                        .history_import(&mut int, import_matches.value_of("DIR").unwrap())
+                        .await
+                }
+                Some(("backfill", _backfill_matches)) => service.history_backfill(&mut int).await,
+                Some(("gaps", _gaps_matches)) => service.history_gaps(&mut int).await,
+                Some(("dedupe", _dedupe_matches)) => service.history_dedupe(&mut int).await,
+                Some(("stats", stats_matches)) => {
+                    service
+                        .history_stats(
+                            &mut int,
+                            HistoryWindow {
+                                period: stats_matches.value_of_t("period").ok(),
+                                from: stats_matches.value_of_t("from").ok(),
+                                to: stats_matches.value_of_t("to").ok(),
+                            },
+                            stats_matches.value_of_t("size").ok(),
+                        )
+                        .await
+                }
                 Some(("throwback", throwback_matches)) => {
                     service
                         .throwback(
@@ -112,7 +391,26 @@ impl Parser {
                             &mut int,
                             link_matches.value_of("PLAYLIST").unwrap(),
                             link_matches.value_of("ARTIST").unwrap(),
-                            link_matches.value_of_t("seed").ok(),
+                            link_request(link_matches),
+                        )
+                        .await
+                }
+                Some(("link-file", file_matches)) => {
+                    service
+                        .playlists_link_file(
+                            &mut int,
+                            file_matches.value_of("PLAYLIST").unwrap(),
+                            file_matches.value_of("FILE").unwrap(),
+                            link_request(file_matches),
+                        )
+                        .await
+                }
+                Some(("link-edit", edit_matches)) => {
+                    service
+                        .playlists_link_edit(
+                            edit_matches.value_of("PLAYLIST").unwrap(),
+                            edit_matches.value_of("ARTIST").unwrap(),
+                            link_edit(edit_matches),
                         )
                         .await
                 }
@@ -124,12 +422,54 @@ impl Parser {
                         )
                         .await
                 }
-                Some(("update", _update_matches)) => service.playlists_update().await,
+                Some(("unlink-file", file_matches)) => {
+                    service
+                        .playlists_unlink_file(
+                            &mut int,
+                            file_matches.value_of("PLAYLIST").unwrap(),
+                            file_matches.value_of("FILE").unwrap(),
+                        )
+                        .await
+                }
+                Some(("update", update_matches)) => {
+                    service
+                        .playlists_update(&mut int, update_matches.is_present("dry-run"))
+                        .await
+                }
                 Some(("automate", automate_matches)) => {
                     service
                         .playlists_automate(automate_matches.value_of("PLAYLIST").unwrap())
                         .await
                 }
+                Some(("duplicates", duplicates_matches)) => {
+                    service
+                        .playlists_duplicates(
+                            duplicates_matches.value_of("PLAYLIST").unwrap(),
+                            duplicates_matches.value_of("SETTING") == Some("allow"),
+                        )
+                        .await
+                }
+                Some(("rules", rules_matches)) => {
+                    service
+                        .playlists_rules(
+                            &mut int,
+                            rules_matches.value_of("PLAYLIST").unwrap(),
+                            rules_edit(rules_matches),
+                        )
+                        .await
+                }
+                Some(("retention", retention_matches)) => {
+                    service
+                        .playlists_retention(
+                            &mut int,
+                            retention_matches.value_of("PLAYLIST").unwrap(),
+                            RetentionEdit {
+                                max_tracks: retention_matches.value_of_t("max-tracks").ok(),
+                                max_age_days: retention_matches.value_of_t("max-age").ok(),
+                            },
+                        )
+                        .await
+                }
                 Some(("show", show_matches)) => {
                     service
                         .playlists_show(&mut int, show_matches.value_of("PLAYLIST").unwrap())
@@ -137,6 +477,16 @@ impl Parser {
                 }
                 _ => unreachable!(),
             },
+            Some(("daemon", _daemon_matches)) => service.daemon(daemon).await,
+            Some(("sync", sync_matches)) => {
+                service
+                    .sync(
+                        &mut int,
+                        sync_matches.value_of("FILE").unwrap(),
+                        sync_matches.is_present("apply"),
+                    )
+                    .await
+            }
             _ => unreachable!(),
         }
     }
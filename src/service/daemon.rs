@@ -0,0 +1,105 @@
+use std::fmt;
+use std::future::Future;
+
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use log::{error, info};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::error::CoolioError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    HistoryUpdate,
+    PlaylistsUpdate,
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Task::HistoryUpdate => write!(f, "history update"),
+            Task::PlaylistsUpdate => write!(f, "playlists update"),
+        }
+    }
+}
+
+pub struct Job {
+    pub task: Task,
+    pub schedule: Schedule,
+    next: Option<DateTime<Utc>>,
+}
+
+impl Job {
+    pub fn new(task: Task, schedule: Schedule) -> Self {
+        let next = schedule.upcoming(Utc).next();
+        Job {
+            task,
+            schedule,
+            next,
+        }
+    }
+}
+
+/// Picks the job that is due the soonest. Jobs whose schedules have no
+/// upcoming runs are never picked.
+pub fn next_job(jobs: &[Job]) -> Option<usize> {
+    jobs.iter()
+        .enumerate()
+        .filter_map(|(i, j)| j.next.map(|next| (i, next)))
+        .min_by_key(|(_, next)| *next)
+        .map(|(i, _)| i)
+}
+
+/// Resolves once the process receives SIGINT or SIGTERM.
+pub async fn shutdown_signal() -> Result<(), CoolioError> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res?,
+        _ = terminate.recv() => {}
+    }
+    Ok(())
+}
+
+/// Runs the jobs on their schedules until `shutdown` resolves. A failed run
+/// is only logged, the job is retried on its next scheduled time.
+pub async fn run<F, Fut>(
+    mut jobs: Vec<Job>,
+    shutdown: impl Future<Output = Result<(), CoolioError>>,
+    mut execute: F,
+) -> Result<(), CoolioError>
+where
+    F: FnMut(Task) -> Fut,
+    Fut: Future<Output = Result<(), CoolioError>>,
+{
+    tokio::pin!(shutdown);
+    loop {
+        let i = match next_job(&jobs) {
+            Some(i) => i,
+            None => return Err("no upcoming runs in the schedule".into()),
+        };
+        let job = &mut jobs[i];
+        let at = job.next.unwrap();
+
+        // a job that was due while another one was running starts right away
+        let wait = (at - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            res = &mut shutdown => {
+                info!("shutting down");
+                return res;
+            }
+            _ = tokio::time::sleep(wait) => {}
+        }
+
+        // a started run is always finished before shutting down
+        let started = Utc::now();
+        match execute(job.task).await {
+            Ok(()) => info!(
+                "{} finished in {}s",
+                job.task,
+                (Utc::now() - started).num_seconds()
+            ),
+            Err(e) => error!("{} failed: {}", job.task, e),
+        }
+        job.next = job.schedule.after(&Utc::now()).next();
+    }
+}
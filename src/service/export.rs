@@ -0,0 +1,129 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+
+use crate::error::CoolioError;
+
+/// Entry of the extended streaming history (`Streaming_History_Audio_*.json`).
+#[derive(Debug, Deserialize)]
+struct ExtendedRecord {
+    ts: DateTime<Utc>,
+    ms_played: u32,
+    platform: Option<String>,
+    spotify_track_uri: Option<String>,
+    skipped: Option<bool>,
+}
+
+/// Entry of the account data streaming history (`StreamingHistory*.json`).
+/// It doesn't carry track ids, so the track has to be searched for.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    end_time: String,
+    artist_name: String,
+    track_name: String,
+    ms_played: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportedTrack {
+    Uri(String),
+    Search { name: String, artist: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportedListen {
+    pub track: ExportedTrack,
+    pub time: DateTime<Utc>,
+    pub ms_played: u32,
+    pub skipped: Option<bool>,
+    pub platform: Option<String>,
+}
+
+enum ExportFile {
+    Extended,
+    Account,
+}
+
+impl ExportFile {
+    fn from_name(name: &str) -> Option<Self> {
+        if !name.ends_with(".json") {
+            None
+        } else if name.starts_with("Streaming_History_Audio_") || name.starts_with("endsong_") {
+            Some(ExportFile::Extended)
+        } else if name.starts_with("StreamingHistory") {
+            Some(ExportFile::Account)
+        } else {
+            None
+        }
+    }
+}
+
+fn read_extended(content: &str) -> Result<Vec<ExportedListen>, CoolioError> {
+    let records: Vec<ExtendedRecord> = serde_json::from_str(content)?;
+    Ok(records
+        .into_iter()
+        // podcast episodes and videos don't have a track uri
+        .filter_map(|r| {
+            r.spotify_track_uri.map(|uri| ExportedListen {
+                track: ExportedTrack::Uri(uri),
+                time: r.ts,
+                ms_played: r.ms_played,
+                skipped: r.skipped,
+                platform: r.platform,
+            })
+        })
+        .collect())
+}
+
+fn read_account(content: &str) -> Result<Vec<ExportedListen>, CoolioError> {
+    let records: Vec<Record> = serde_json::from_str(content)?;
+    let mut listens = Vec::<ExportedListen>::new();
+    for r in records {
+        listens.push(ExportedListen {
+            track: ExportedTrack::Search {
+                name: r.track_name,
+                artist: r.artist_name,
+            },
+            time: DateTime::<Utc>::from_naive_utc_and_offset(
+                NaiveDateTime::parse_from_str(&r.end_time, "%Y-%m-%d %H:%M")?,
+                Utc,
+            ),
+            ms_played: r.ms_played,
+            skipped: None,
+            platform: None,
+        });
+    }
+    Ok(listens)
+}
+
+/// Reads all the streaming history files in a directory of an extracted
+/// Spotify data-privacy export. Other files in the directory are ignored.
+pub fn read_export(dir: &Path) -> Result<Vec<ExportedListen>, CoolioError> {
+    let mut files = Vec::<(String, ExportFile)>::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if let Some(kind) = ExportFile::from_name(&name) {
+            files.push((name, kind));
+        }
+    }
+
+    if files.is_empty() {
+        return Err("no streaming history files found in the directory".into());
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut listens = Vec::<ExportedListen>::new();
+    for (name, kind) in files {
+        let content = fs::read_to_string(dir.join(&name))?;
+        let mut read = match kind {
+            ExportFile::Extended => read_extended(&content),
+            ExportFile::Account => read_account(&content),
+        }
+        .map_err(|e| CoolioError::from(format!("{}: {}", name, e)))?;
+        listens.append(&mut read);
+    }
+    Ok(listens)
+}
@@ -1,16 +1,53 @@
-use std::cmp::min;
+use std::cmp::{max, min, Reverse};
 use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::path::Path;
 
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
 
-use crate::models::ThrowbackPeriod;
+use crate::models::{Gap, HistoryWindow, Listen, ThrowbackPeriod, TrackMetadata};
 use crate::storage::StorageBehavior;
 use crate::{error::CoolioError, storage::Storage};
 
-use super::spotify::Spotify;
+use super::export::{read_export, ExportedTrack};
+use super::io::Interactor;
+use super::spotify::{Spotify, TRACKS_LIMIT};
+
+// the most plays the recently played endpoint returns
+const RECENTLY_PLAYED_LIMIT: u32 = 50;
+
+// exported times are only precise to the second, or to the minute in account
+// data, while the API records milliseconds, so an exported listen is already
+// recorded when the same track was played within this or the time it played
+const IMPORT_TOLERANCE_SECS: i64 = 60;
 
 pub struct HistoryService {}
 
+#[derive(Debug)]
+pub struct ListenStats {
+    pub listens: usize,
+    pub distinct_tracks: usize,
+    pub listening_time: Duration,
+    pub top_tracks: Vec<(String, usize)>,
+    pub top_artists: Vec<(String, usize)>,
+    pub top_albums: Vec<(String, usize)>,
+}
+
+fn top(
+    counts: HashMap<String, usize>,
+    names: &HashMap<String, String>,
+    size: usize,
+) -> Vec<(String, usize)> {
+    let mut entries = counts
+        .into_iter()
+        .map(|(id, count)| (names.get(&id).cloned().unwrap_or(id), count))
+        .collect::<Vec<(String, usize)>>();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries.truncate(size);
+    entries
+}
+
 impl HistoryService {
     pub async fn update(
         &self,
@@ -20,15 +57,260 @@ impl HistoryService {
         let last_listen = storage.get_last_listen().await.ok().map(|x| x.time);
 
         let recent = spotify
-            .current_user_recently_played(50, last_listen)
+            .current_user_recently_played(RECENTLY_PLAYED_LIMIT, last_listen)
             .await?;
 
-        for l in recent {
-            storage.add_history(l).await?;
+        // a full window that doesn't reach back to the last recorded listen
+        // means some plays in between were lost
+        if let (Some(last_listen), Some(oldest)) =
+            (last_listen, recent.iter().map(|l| l.time).min())
+        {
+            if recent.len() >= RECENTLY_PLAYED_LIMIT as usize && oldest > last_listen {
+                storage
+                    .add_gap(Gap {
+                        from: last_listen,
+                        to: oldest,
+                    })
+                    .await?;
+            }
+        }
+
+        // recorded at once, so the history is only read once to dedupe them
+        if !recent.is_empty() {
+            storage.add_history_batch(recent).await?;
         }
         Ok(())
     }
 
+    pub async fn import<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        spotify: &impl Spotify,
+        storage: &StorageBehavior,
+        writer: &mut Interactor<'a, R, W>,
+        dir: &str,
+    ) -> Result<(), CoolioError> {
+        let exported = read_export(Path::new(dir))?;
+
+        let mut recorded = HashMap::<String, Vec<DateTime<Utc>>>::new();
+        for l in storage.get_history().await? {
+            recorded.entry(l.song_id).or_default().push(l.time);
+        }
+        // the same play can be in more than one file of the export
+        let mut seen = HashSet::<(String, DateTime<Utc>)>::new();
+
+        // account data exports don't have track ids, search each track only once
+        let mut searched = HashMap::<(String, String), Option<String>>::new();
+
+        let mut listens = Vec::<Listen>::new();
+        let mut duplicates = 0;
+        let mut unresolved = 0;
+        for e in exported {
+            let song_id = match e.track {
+                ExportedTrack::Uri(uri) => Some(uri),
+                ExportedTrack::Search { name, artist } => {
+                    let key = (name, artist);
+                    if !searched.contains_key(&key) {
+                        // a failed search leaves the track unresolved instead
+                        // of stopping the import
+                        let found = match spotify.search_tracks(&key.0, &key.1).await {
+                            Ok(tracks) => tracks.into_iter().next().map(|t| t.id),
+                            Err(err) => {
+                                warn!("searching for {} by {} failed: {}", key.0, key.1, err);
+                                None
+                            }
+                        };
+                        searched.insert(key.clone(), found);
+                    }
+                    searched[&key].clone()
+                }
+            };
+
+            match song_id {
+                None => unresolved += 1,
+                Some(song_id) => {
+                    let tolerance = max(
+                        Duration::milliseconds(e.ms_played as i64),
+                        Duration::seconds(IMPORT_TOLERANCE_SECS),
+                    );
+                    let known = recorded.get(&song_id).is_some_and(|times| {
+                        times.iter().any(|t| (*t - e.time).abs() <= tolerance)
+                    });
+                    if !known && seen.insert((song_id.clone(), e.time)) {
+                        listens.push(Listen {
+                            song_id,
+                            time: e.time,
+                            ms_played: Some(e.ms_played),
+                            skipped: e.skipped,
+                            platform: e.platform,
+                            context: None,
+                            track: None,
+                        });
+                    } else {
+                        duplicates += 1;
+                    }
+                }
+            }
+        }
+
+        listens.sort_by_key(|l| l.time);
+        let imported = listens.len();
+        if imported > 0 {
+            storage.add_history_batch(listens).await?;
+        }
+
+        writer.import_summary(imported, duplicates, unresolved)
+    }
+
+    pub async fn backfill<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        spotify: &impl Spotify,
+        storage: &StorageBehavior,
+        writer: &mut Interactor<'a, R, W>,
+    ) -> Result<(), CoolioError> {
+        let history = storage.get_history().await?;
+
+        let mut missing = history
+            .iter()
+            .filter(|l| l.track.is_none())
+            .map(|l| l.song_id.clone())
+            .collect::<Vec<String>>();
+        missing.sort();
+        missing.dedup();
+
+        let mut tracks = HashMap::<String, TrackMetadata>::new();
+        let mut rejected = Vec::<(String, String)>::new();
+        for batch in missing.chunks(TRACKS_LIMIT) {
+            let fetched = match spotify.tracks(batch).await {
+                Ok(fetched) => fetched,
+                // a single id Spotify doesn't accept fails the whole request,
+                // so the batch is fetched again one track at a time to find it
+                Err(_) => {
+                    let mut fetched = Vec::new();
+                    for id in batch {
+                        match spotify.tracks(std::slice::from_ref(id)).await {
+                            Ok(mut track) => fetched.append(&mut track),
+                            Err(e) => rejected.push((id.clone(), e.to_string())),
+                        }
+                    }
+                    fetched
+                }
+            };
+            tracks.extend(fetched.into_iter().map(|t| (t.id.clone(), t.into())));
+        }
+        if !tracks.is_empty() {
+            storage.set_track_metadata(&tracks).await?;
+        }
+
+        let listens = history
+            .iter()
+            .filter(|l| l.track.is_none() && tracks.contains_key(&l.song_id))
+            .count();
+        let unresolved = missing.len() - tracks.len() - rejected.len();
+        writer.backfill_summary(tracks.len(), listens, unresolved, &rejected)
+    }
+
+    pub async fn gaps<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        storage: &StorageBehavior,
+        writer: &mut Interactor<'a, R, W>,
+    ) -> Result<(), CoolioError> {
+        let gaps = storage.get_gaps().await?;
+        writer.list_gaps(&gaps)
+    }
+
+    pub async fn dedupe<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        storage: &StorageBehavior,
+        writer: &mut Interactor<'a, R, W>,
+    ) -> Result<(), CoolioError> {
+        let removed = storage.dedupe_history().await?;
+        writer.dedupe_summary(removed)
+    }
+
+    pub async fn stats<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        spotify: &impl Spotify,
+        storage: &StorageBehavior,
+        writer: &mut Interactor<'a, R, W>,
+        window: HistoryWindow,
+        size: Option<usize>,
+    ) -> Result<(), CoolioError> {
+        if let (Some(from), Some(to)) = window.bounds() {
+            if from >= to {
+                return Err("the start of the window must be before its end".into());
+            }
+        }
+
+        let mut history = storage.get_history().await?;
+        history.retain(|l| window.contains(&l.time));
+
+        let mut track_counts = HashMap::<String, usize>::new();
+        for l in &history {
+            *track_counts.entry(l.song_id.clone()).or_insert(0) += 1;
+        }
+
+        // only tracks recorded without metadata have to be looked up
+        let mut tracks = HashMap::<String, TrackMetadata>::new();
+        for l in &history {
+            if let Some(t) = &l.track {
+                tracks.insert(l.song_id.clone(), t.clone());
+            }
+        }
+        let missing = track_counts
+            .keys()
+            .filter(|id| !tracks.contains_key(*id))
+            .cloned()
+            .collect::<Vec<String>>();
+        if !missing.is_empty() {
+            for t in spotify.tracks(&missing).await? {
+                tracks.insert(t.id.clone(), t.into());
+            }
+        }
+
+        let mut names = HashMap::<String, String>::new();
+        let mut artist_counts = HashMap::<String, usize>::new();
+        let mut album_counts = HashMap::<String, usize>::new();
+        let mut listening_ms = 0;
+        for l in &history {
+            let track = tracks.get(&l.song_id);
+            // listens recorded through the API don't know how long they were played
+            listening_ms += l
+                .ms_played
+                .or_else(|| track.map(|t| t.duration_ms))
+                .unwrap_or(0) as i64;
+
+            if let Some(track) = track {
+                for (id, name) in track.artist_ids.iter().zip(&track.artist_names) {
+                    *artist_counts.entry(id.clone()).or_insert(0) += 1;
+                    names.insert(id.clone(), name.clone());
+                }
+                if let (Some(id), Some(name)) = (&track.album_id, &track.album_name) {
+                    *album_counts.entry(id.clone()).or_insert(0) += 1;
+                    names.insert(id.clone(), name.clone());
+                }
+            }
+        }
+
+        for (id, t) in &tracks {
+            names.insert(
+                id.clone(),
+                format!("{} - {}", t.name, t.artist_names.join(", ")),
+            );
+        }
+
+        let size = size.unwrap_or(10);
+        let stats = ListenStats {
+            listens: history.len(),
+            distinct_tracks: track_counts.len(),
+            listening_time: Duration::milliseconds(listening_ms),
+            top_tracks: top(track_counts, &names, size),
+            top_artists: top(artist_counts, &names, size),
+            top_albums: top(album_counts, &names, size),
+        };
+
+        writer.show_stats(&stats)
+    }
+
     pub async fn throwback(
         &self,
         spotify: &impl Spotify,
@@ -37,13 +319,7 @@ impl HistoryService {
         period: Option<ThrowbackPeriod>,
         size: Option<usize>,
     ) -> Result<(), CoolioError> {
-        let offset = match period {
-            None => Duration::weeks(25),
-            Some(ThrowbackPeriod::Days(d)) => Duration::days(d as i64),
-            Some(ThrowbackPeriod::Weeks(w)) => Duration::weeks(w as i64),
-            Some(ThrowbackPeriod::Months(m)) => Duration::days((m * 30) as i64),
-            Some(ThrowbackPeriod::Years(y)) => Duration::days((y * 365) as i64),
-        };
+        let offset = period.map_or(Duration::weeks(25), |p| p.duration());
         let before = Utc::now() - offset;
 
         let history = storage.get_history().await?;
@@ -72,14 +348,14 @@ impl HistoryService {
             .drain()
             .map(|(x, y)| Entry { id: x, count: y })
             .collect::<Vec<Entry>>();
-        entries.sort_by(|a, b| b.count.cmp(&a.count));
+        entries.sort_by_key(|e| Reverse(e.count));
 
         if entries.is_empty() {
             return Ok(());
         }
 
         let playlist = spotify
-            .create_playlist(name.unwrap_or(&format!("Throwback - {}", Utc::today())))
+            .create_playlist(name.unwrap_or(&format!("Throwback - {}", Utc::now().date_naive())))
             .await?;
 
         let size = min(size.unwrap_or(50), entries.len());
@@ -1,7 +1,15 @@
 use std::io::{BufRead, Write};
 
-use crate::{error::CoolioError, models::Playlist};
+use crate::{
+    error::CoolioError,
+    models::{
+        ExclusionRules, Gap, PlannedAddition, PlannedRemoval, Playlist, Retention, SyncAction,
+    },
+};
 
+use crate::storage::copy::CopySummary;
+
+use super::history::ListenStats;
 use super::spotify::{SimpleArtist, SimplePlaylist};
 
 pub struct Interactor<'a, R: BufRead + Send + Sync, W: Write + Send + Sync> {
@@ -52,7 +60,7 @@ impl<'a, R: BufRead + Send + Sync, W: Write + Send + Sync> Interactor<'a, R, W>
         writeln!(
             self.writer,
             "Number of tracks: {}",
-            external_playlist.tracks.len()
+            external_playlist.num_tracks
         )?;
         writeln!(
             self.writer,
@@ -68,6 +76,226 @@ impl<'a, R: BufRead + Send + Sync, W: Write + Send + Sync> Interactor<'a, R, W>
         Ok(())
     }
 
+    pub fn show_rules(&mut self, rules: &ExclusionRules, default: bool) -> Result<(), CoolioError> {
+        if default {
+            writeln!(self.writer, "Rules (default):")?;
+        } else {
+            writeln!(self.writer, "Rules:")?;
+        }
+        for p in &rules.patterns {
+            writeln!(self.writer, "\tExcluded pattern: {}", p)?;
+        }
+        if let Some(secs) = rules.min_duration_secs {
+            writeln!(self.writer, "\tMin duration: {}s", secs)?;
+        }
+        if let Some(secs) = rules.max_duration_secs {
+            writeln!(self.writer, "\tMax duration: {}s", secs)?;
+        }
+        writeln!(self.writer, "\tSkip explicit: {}", rules.skip_explicit)?;
+        Ok(())
+    }
+
+    pub fn show_retention(&mut self, retention: &Retention) -> Result<(), CoolioError> {
+        if !retention.is_limited() {
+            writeln!(self.writer, "Tracks are kept forever")?;
+        }
+        if let Some(tracks) = retention.max_tracks {
+            writeln!(self.writer, "Max tracks: {}", tracks)?;
+        }
+        if let Some(days) = retention.max_age_days {
+            writeln!(self.writer, "Max age: {} days", days)?;
+        }
+        Ok(())
+    }
+
+    pub fn link_file_summary(
+        &mut self,
+        linked: usize,
+        already_linked: usize,
+        failed: &[(String, String)],
+        link_failed: &[(String, String)],
+    ) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Linked artists: {}", linked)?;
+        writeln!(self.writer, "Already linked: {}", already_linked)?;
+        self.list_failed(failed)?;
+        writeln!(self.writer, "Failed to link: {}", link_failed.len())?;
+        for (entry, reason) in link_failed {
+            writeln!(self.writer, "\t{}: {}", entry, reason)?;
+        }
+        Ok(())
+    }
+
+    pub fn unlink_file_summary(
+        &mut self,
+        unlinked: usize,
+        failed: &[(String, String)],
+        unlink_failed: &[(String, String)],
+    ) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Unlinked artists: {}", unlinked)?;
+        self.list_failed(failed)?;
+        writeln!(self.writer, "Failed to unlink: {}", unlink_failed.len())?;
+        for (entry, reason) in unlink_failed {
+            writeln!(self.writer, "\t{}: {}", entry, reason)?;
+        }
+        Ok(())
+    }
+
+    fn list_failed(&mut self, failed: &[(String, String)]) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Not resolved: {}", failed.len())?;
+        for (entry, reason) in failed {
+            writeln!(self.writer, "\t{}: {}", entry, reason)?;
+        }
+        Ok(())
+    }
+
+    pub fn import_summary(
+        &mut self,
+        imported: usize,
+        duplicates: usize,
+        unresolved: usize,
+    ) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Imported listens: {}", imported)?;
+        writeln!(self.writer, "Already recorded: {}", duplicates)?;
+        writeln!(self.writer, "Tracks not found: {}", unresolved)?;
+        Ok(())
+    }
+
+    pub fn list_gaps(&mut self, gaps: &[Gap]) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Gaps: {}", gaps.len())?;
+        for gap in gaps {
+            let missing = gap.to - gap.from;
+            writeln!(
+                self.writer,
+                "\t{} - {} ({}h {}m)",
+                gap.from.format("%Y-%m-%d %H:%M"),
+                gap.to.format("%Y-%m-%d %H:%M"),
+                missing.num_hours(),
+                missing.num_minutes() % 60
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn authorize_url(&mut self, url: &str) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Open this URL in your browser to log in:")?;
+        writeln!(self.writer, "{}", url)?;
+        Ok(())
+    }
+
+    pub fn auth_status(&mut self, user: Option<&str>) -> Result<(), CoolioError> {
+        match user {
+            Some(user) => writeln!(self.writer, "Logged in as {}", user)?,
+            None => writeln!(self.writer, "Not logged in")?,
+        }
+        Ok(())
+    }
+
+    pub fn logged_out(&mut self, was_logged_in: bool) -> Result<(), CoolioError> {
+        if was_logged_in {
+            writeln!(self.writer, "Logged out")?;
+        } else {
+            writeln!(self.writer, "Not logged in")?;
+        }
+        Ok(())
+    }
+
+    pub fn planned_additions(&mut self, planned: &[PlannedAddition]) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Planned additions: {}", planned.len())?;
+        for p in planned {
+            match &p.album {
+                Some(album) => writeln!(
+                    self.writer,
+                    "\t{}: {} - {} ({}) [{}]",
+                    p.playlist, p.artist, p.track, album, p.reason
+                )?,
+                None => writeln!(
+                    self.writer,
+                    "\t{}: {} - {} [{}]",
+                    p.playlist, p.artist, p.track, p.reason
+                )?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn planned_removals(&mut self, planned: &[PlannedRemoval]) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Planned removals: {}", planned.len())?;
+        for p in planned {
+            writeln!(
+                self.writer,
+                "\t{}: {} - {} [{}]",
+                p.playlist, p.artist, p.track, p.reason
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn sync_plan(&mut self, plan: &[SyncAction]) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Planned changes: {}", plan.len())?;
+        for action in plan {
+            writeln!(self.writer, "\t{}", action)?;
+        }
+        Ok(())
+    }
+
+    pub fn sync_applied(&mut self, applied: usize) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Applied changes: {}", applied)?;
+        Ok(())
+    }
+
+    pub fn copy_summary(&mut self, summary: &CopySummary) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Copied listens: {}", summary.listens)?;
+        writeln!(self.writer, "Copied gaps: {}", summary.gaps)?;
+        writeln!(self.writer, "Copied playlists: {}", summary.playlists)?;
+        writeln!(self.writer, "Copied links: {}", summary.links)?;
+        Ok(())
+    }
+
+    pub fn dedupe_summary(&mut self, removed: usize) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Removed duplicates: {}", removed)?;
+        Ok(())
+    }
+
+    pub fn backfill_summary(
+        &mut self,
+        tracks: usize,
+        listens: usize,
+        unresolved: usize,
+        rejected: &[(String, String)],
+    ) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Backfilled tracks: {}", tracks)?;
+        writeln!(self.writer, "Backfilled listens: {}", listens)?;
+        writeln!(self.writer, "Tracks not found: {}", unresolved)?;
+        writeln!(self.writer, "Rejected by Spotify: {}", rejected.len())?;
+        for (id, reason) in rejected {
+            writeln!(self.writer, "\t{}: {}", id, reason)?;
+        }
+        Ok(())
+    }
+
+    pub fn show_stats(&mut self, stats: &ListenStats) -> Result<(), CoolioError> {
+        writeln!(self.writer, "Listens: {}", stats.listens)?;
+        writeln!(self.writer, "Distinct tracks: {}", stats.distinct_tracks)?;
+        writeln!(
+            self.writer,
+            "Listening time: {}h {}m",
+            stats.listening_time.num_hours(),
+            stats.listening_time.num_minutes() % 60
+        )?;
+
+        for (title, entries) in [
+            ("Top tracks:", &stats.top_tracks),
+            ("Top artists:", &stats.top_artists),
+            ("Top albums:", &stats.top_albums),
+        ] {
+            writeln!(self.writer, "{}", title)?;
+            for (i, (name, count)) in entries.iter().enumerate() {
+                writeln!(self.writer, "\t{}. {} ({} listens)", i + 1, name, count)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn choose_artist(&mut self, artists: &Vec<SimpleArtist>) -> Result<String, CoolioError> {
         let mut count_id = 1;
         writeln!(self.writer, "choose one of the following artists:")?;
@@ -1,23 +1,38 @@
 use std::io::BufRead;
 use std::io::Write;
 
+use std::str::FromStr;
+
 use async_trait::async_trait;
+use cron::Schedule;
 
-use crate::{error::CoolioError, models::ThrowbackPeriod, storage::StorageBehavior};
+use crate::models::{
+    ExclusionRules, HistoryWindow, LinkEdit, LinkRequest, RetentionEdit, RulesEdit, ThrowbackPeriod,
+};
+use crate::settings::Daemon;
+use crate::{error::CoolioError, storage::StorageBehavior};
 
+use self::daemon::{Job, Task};
 use self::io::Interactor;
-use self::{history::HistoryService, playlists::PlaylistService, spotify::Spotify};
+use self::{
+    history::HistoryService, playlists::PlaylistService, spotify::Spotify, sync::SyncService,
+};
 
+pub mod daemon;
+pub mod export;
 pub mod history;
 pub mod io;
 pub mod playlists;
+pub mod retry;
 pub mod spotify;
+pub mod sync;
 
 pub struct Service<'a, S: Spotify> {
     pub spotify: &'a S,
     pub storage: &'a StorageBehavior,
     history: HistoryService,
     playlists: PlaylistService,
+    sync: SyncService,
 }
 
 impl<'a, S: Spotify> Service<'a, S> {
@@ -26,15 +41,52 @@ impl<'a, S: Spotify> Service<'a, S> {
             spotify,
             storage,
             history: HistoryService {},
-            playlists: PlaylistService {},
+            playlists: PlaylistService {
+                default_rules: ExclusionRules::default(),
+            },
+            sync: SyncService {},
         }
     }
+
+    /// Sets the exclusion rules of the playlists that don't have their own.
+    pub fn with_default_rules(mut self, rules: ExclusionRules) -> Self {
+        self.playlists.default_rules = rules;
+        self
+    }
 }
 
 #[async_trait]
 pub trait ServiceTrait: Send + Sync {
     async fn history_update(&self) -> Result<(), CoolioError>;
 
+    async fn history_import<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'a, R, W>,
+        dir: &str,
+    ) -> Result<(), CoolioError>;
+
+    async fn history_backfill<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'a, R, W>,
+    ) -> Result<(), CoolioError>;
+
+    async fn history_gaps<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'a, R, W>,
+    ) -> Result<(), CoolioError>;
+
+    async fn history_dedupe<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'a, R, W>,
+    ) -> Result<(), CoolioError>;
+
+    async fn history_stats<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'a, R, W>,
+        window: HistoryWindow,
+        size: Option<usize>,
+    ) -> Result<(), CoolioError>;
+
     async fn throwback(
         &self,
         name: Option<&str>,
@@ -57,12 +109,43 @@ pub trait ServiceTrait: Send + Sync {
 
     async fn playlists_automate(&self, name: &str) -> Result<(), CoolioError>;
 
+    async fn playlists_duplicates(&self, name: &str, allow: bool) -> Result<(), CoolioError>;
+
+    async fn playlists_rules<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'a, R, W>,
+        name: &str,
+        edit: RulesEdit,
+    ) -> Result<(), CoolioError>;
+
+    async fn playlists_retention<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'a, R, W>,
+        name: &str,
+        edit: RetentionEdit,
+    ) -> Result<(), CoolioError>;
+
     async fn link_playlist_to_artist<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
         &self,
         int: &mut Interactor<'a, R, W>,
         playlist: &str,
         artist: &str,
-        seed: Option<usize>,
+        request: LinkRequest,
+    ) -> Result<(), CoolioError>;
+
+    async fn playlists_link_file<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'a, R, W>,
+        playlist: &str,
+        file: &str,
+        request: LinkRequest,
+    ) -> Result<(), CoolioError>;
+
+    async fn playlists_link_edit(
+        &self,
+        playlist: &str,
+        artist: &str,
+        edit: LinkEdit,
     ) -> Result<(), CoolioError>;
 
     async fn unlink_artist_from_playlist(
@@ -71,7 +154,27 @@ pub trait ServiceTrait: Send + Sync {
         artist: &str,
     ) -> Result<(), CoolioError>;
 
-    async fn playlists_update(&self) -> Result<(), CoolioError>;
+    async fn playlists_unlink_file<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'a, R, W>,
+        playlist: &str,
+        file: &str,
+    ) -> Result<(), CoolioError>;
+
+    async fn playlists_update<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'a, R, W>,
+        dry_run: bool,
+    ) -> Result<(), CoolioError>;
+
+    async fn sync<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'a, R, W>,
+        file: &str,
+        apply: bool,
+    ) -> Result<(), CoolioError>;
+
+    async fn daemon(&self, conf: &Daemon) -> Result<(), CoolioError>;
 }
 
 #[async_trait]
@@ -80,6 +183,48 @@ impl<'a, S: Spotify> ServiceTrait for Service<'a, S> {
         self.history.update(self.spotify, self.storage).await
     }
 
+    async fn history_import<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'b, R, W>,
+        dir: &str,
+    ) -> Result<(), CoolioError> {
+        self.history
+            .import(self.spotify, self.storage, int, dir)
+            .await
+    }
+
+    async fn history_backfill<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'b, R, W>,
+    ) -> Result<(), CoolioError> {
+        self.history.backfill(self.spotify, self.storage, int).await
+    }
+
+    async fn history_gaps<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'b, R, W>,
+    ) -> Result<(), CoolioError> {
+        self.history.gaps(self.storage, int).await
+    }
+
+    async fn history_dedupe<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'b, R, W>,
+    ) -> Result<(), CoolioError> {
+        self.history.dedupe(self.storage, int).await
+    }
+
+    async fn history_stats<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'b, R, W>,
+        window: HistoryWindow,
+        size: Option<usize>,
+    ) -> Result<(), CoolioError> {
+        self.history
+            .stats(self.spotify, self.storage, int, window, size)
+            .await
+    }
+
     async fn throwback(
         &self,
         name: Option<&str>,
@@ -120,15 +265,64 @@ impl<'a, S: Spotify> ServiceTrait for Service<'a, S> {
             .await
     }
 
+    async fn playlists_duplicates(&self, name: &str, allow: bool) -> Result<(), CoolioError> {
+        self.playlists
+            .set_duplicates(self.storage, name, allow)
+            .await
+    }
+
+    async fn playlists_rules<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'b, R, W>,
+        name: &str,
+        edit: RulesEdit,
+    ) -> Result<(), CoolioError> {
+        self.playlists.rules(self.storage, int, name, edit).await
+    }
+
+    async fn playlists_retention<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'b, R, W>,
+        name: &str,
+        edit: RetentionEdit,
+    ) -> Result<(), CoolioError> {
+        self.playlists
+            .retention(self.storage, int, name, edit)
+            .await
+    }
+
     async fn link_playlist_to_artist<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
         &self,
         int: &mut Interactor<'b, R, W>,
         playlist: &str,
         artist: &str,
-        seed: Option<usize>,
+        request: LinkRequest,
     ) -> Result<(), CoolioError> {
         self.playlists
-            .link_playlist_to_artist(self.spotify, self.storage, int, playlist, artist, seed)
+            .link_playlist_to_artist(self.spotify, self.storage, int, playlist, artist, request)
+            .await
+    }
+
+    async fn playlists_link_file<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'b, R, W>,
+        playlist: &str,
+        file: &str,
+        request: LinkRequest,
+    ) -> Result<(), CoolioError> {
+        self.playlists
+            .link_file(self.spotify, self.storage, int, playlist, file, request)
+            .await
+    }
+
+    async fn playlists_link_edit(
+        &self,
+        playlist: &str,
+        artist: &str,
+        edit: LinkEdit,
+    ) -> Result<(), CoolioError> {
+        self.playlists
+            .edit_link(self.spotify, self.storage, playlist, artist, edit)
             .await
     }
 
@@ -142,7 +336,66 @@ impl<'a, S: Spotify> ServiceTrait for Service<'a, S> {
             .await
     }
 
-    async fn playlists_update(&self) -> Result<(), CoolioError> {
-        self.playlists.update(self.spotify, self.storage).await
+    async fn playlists_unlink_file<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'b, R, W>,
+        playlist: &str,
+        file: &str,
+    ) -> Result<(), CoolioError> {
+        self.playlists
+            .unlink_file(self.spotify, self.storage, int, playlist, file)
+            .await
+    }
+
+    async fn playlists_update<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'b, R, W>,
+        dry_run: bool,
+    ) -> Result<(), CoolioError> {
+        let planned = self
+            .playlists
+            .update(self.spotify, self.storage, dry_run)
+            .await?;
+        if dry_run {
+            int.planned_additions(&planned.additions)?;
+            int.planned_removals(&planned.removals)?;
+        }
+        Ok(())
+    }
+
+    async fn sync<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        int: &mut Interactor<'b, R, W>,
+        file: &str,
+        apply: bool,
+    ) -> Result<(), CoolioError> {
+        let plan = self.sync.plan(self.spotify, self.storage, file).await?;
+        int.sync_plan(&plan)?;
+        if apply && !plan.is_empty() {
+            self.sync
+                .apply(self.spotify, self.storage, &self.playlists, &plan)
+                .await?;
+            int.sync_applied(plan.len())?;
+        }
+        Ok(())
+    }
+
+    async fn daemon(&self, conf: &Daemon) -> Result<(), CoolioError> {
+        let jobs = vec![
+            Job::new(Task::HistoryUpdate, Schedule::from_str(&conf.history)?),
+            Job::new(Task::PlaylistsUpdate, Schedule::from_str(&conf.playlists)?),
+        ];
+
+        daemon::run(jobs, daemon::shutdown_signal(), |task| async move {
+            match task {
+                Task::HistoryUpdate => self.history_update().await,
+                Task::PlaylistsUpdate => self
+                    .playlists
+                    .update(self.spotify, self.storage, false)
+                    .await
+                    .map(|_| ()),
+            }
+        })
+        .await
     }
 }
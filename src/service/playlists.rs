@@ -1,24 +1,198 @@
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::io::BufRead;
 use std::io::Write;
+use std::path::Path;
 
 use chrono::DateTime;
+use chrono::Duration;
 use chrono::Utc;
+use log::info;
+use regex::{Regex, RegexBuilder};
 
 use rspotify::model::AlbumType;
 
 use crate::error::CoolioError;
-use crate::models::Playlist;
+use crate::models::{
+    AdditionReason, ArtistChoice, ExclusionRules, LinkCheckpoint, LinkEdit, LinkOptions,
+    LinkRequest, PlannedAddition, PlannedChanges, PlannedRemoval, Playlist, ReleaseType,
+    RemovalReason, RetentionEdit, RulesEdit,
+};
 use crate::storage::Storage;
 use crate::storage::StorageBehavior;
 
 use super::io::Interactor;
+use super::spotify::artist_uri;
+use super::spotify::PlaylistItems;
+use super::spotify::SimpleAlbum;
 use super::spotify::SimpleArtist;
 use super::spotify::SimpleTrack;
 use super::spotify::Spotify;
 
-pub struct PlaylistService {}
+/// Reads the artists listed in the file, one per line or in the first column
+/// of a `.csv` file. Empty lines and lines starting with `#` are skipped.
+fn read_artist_list(path: &Path) -> Result<Vec<String>, CoolioError> {
+    let content = fs::read_to_string(path)?;
+    let mut entries = Vec::<String>::new();
+    if path.extension().is_some_and(|e| e == "csv") {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(content.as_bytes());
+        for (i, record) in reader.records().enumerate() {
+            let record = record?;
+            let first = record.get(0).unwrap_or_default();
+            if i == 0 && ["artist", "name", "uri"].contains(&first.to_lowercase().as_str()) {
+                continue;
+            }
+            entries.push(first.to_string());
+        }
+    } else {
+        entries = content.lines().map(|l| l.trim().to_string()).collect();
+    }
+
+    let mut seen = HashSet::<String>::new();
+    Ok(entries
+        .into_iter()
+        .filter(|e| !e.is_empty() && !e.starts_with('#'))
+        .filter(|e| seen.insert(e.clone()))
+        .collect())
+}
+
+/// Tracks already in a playlist, by URI and by ISRC to also catch re-releases.
+#[derive(Default)]
+struct PlaylistTracks {
+    ids: HashSet<String>,
+    isrcs: HashSet<String>,
+}
+
+impl PlaylistTracks {
+    fn contains(&self, track: &SimpleTrack) -> bool {
+        if self.ids.contains(&track.id) {
+            return true;
+        }
+        match &track.isrc {
+            Some(isrc) => self.isrcs.contains(isrc),
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, track: &SimpleTrack) {
+        self.ids.insert(track.id.clone());
+        if let Some(isrc) = &track.isrc {
+            self.isrcs.insert(isrc.clone());
+        }
+    }
+}
+
+/// A track of a playlist as its retention sees it.
+struct RetainedTrack {
+    // none for the tracks a dry run would have added
+    id: Option<String>,
+    position: u32,
+    added_at: Option<DateTime<Utc>>,
+    artist: String,
+    name: String,
+}
+
+/// The exclusion rules of a playlist with their patterns compiled.
+struct TrackRules {
+    patterns: Vec<Regex>,
+    // durations in seconds that fit in a u32 may not in milliseconds
+    min_duration_ms: Option<u64>,
+    max_duration_ms: Option<u64>,
+    skip_explicit: bool,
+}
+
+impl TrackRules {
+    fn new(rules: &ExclusionRules) -> Result<Self, CoolioError> {
+        let mut patterns = Vec::<Regex>::new();
+        for p in &rules.patterns {
+            patterns.push(RegexBuilder::new(p).case_insensitive(true).build()?);
+        }
+        Ok(TrackRules {
+            patterns,
+            min_duration_ms: rules.min_duration_secs.map(|s| s as u64 * 1000),
+            max_duration_ms: rules.max_duration_secs.map(|s| s as u64 * 1000),
+            skip_explicit: rules.skip_explicit,
+        })
+    }
+
+    fn excludes(&self, track: &SimpleTrack) -> bool {
+        let matches = |name: &str| self.patterns.iter().any(|p| p.is_match(name));
+        matches(&track.name)
+            || track.album_name.as_deref().is_some_and(matches)
+            || self
+                .min_duration_ms
+                .is_some_and(|d| (track.duration_ms as u64) < d)
+            || self
+                .max_duration_ms
+                .is_some_and(|d| track.duration_ms as u64 > d)
+            || (self.skip_explicit && track.explicit)
+    }
+
+    /// Leaves out the tracks the rules exclude.
+    fn retain(&self, tracks: Vec<SimpleTrack>) -> Vec<SimpleTrack> {
+        let candidates = tracks.len();
+        let kept = tracks
+            .into_iter()
+            .filter(|t| !self.excludes(t))
+            .collect::<Vec<SimpleTrack>>();
+        if kept.len() < candidates {
+            info!(
+                "skipped {} tracks excluded by the playlist rules",
+                candidates - kept.len()
+            );
+        }
+        kept
+    }
+}
+
+/// What an update adds to a playlist. On a dry run the additions are only
+/// planned.
+struct PlaylistChanges {
+    dry_run: bool,
+    // none when the playlist allows duplicates
+    existing: Option<PlaylistTracks>,
+    // ids of the albums with tracks in the playlist, which links without a
+    // checkpoint have already added
+    albums: HashSet<String>,
+    rules: TrackRules,
+    planned: Vec<PlannedAddition>,
+}
+
+pub struct PlaylistService {
+    // rules of the playlists that don't have their own
+    pub default_rules: ExclusionRules,
+}
+
+fn album_type(t: &ReleaseType) -> AlbumType {
+    match t {
+        ReleaseType::Album => AlbumType::Album,
+        ReleaseType::Single => AlbumType::Single,
+        ReleaseType::Compilation => AlbumType::Compilation,
+        ReleaseType::AppearsOn => AlbumType::AppearsOn,
+    }
+}
+
+/// Leaves out the tracks where the artist is only featured unless the link
+/// includes them. Without features only the tracks led by the artist are
+/// kept, so those of other artists on the same release are left out too.
+pub fn artist_tracks(
+    tracks: Vec<SimpleTrack>,
+    artist_id: &str,
+    options: &LinkOptions,
+) -> Vec<SimpleTrack> {
+    if options.include_features {
+        return tracks;
+    }
+    tracks
+        .into_iter()
+        .filter(|t| t.artists.first().is_some_and(|a| a.id == artist_id))
+        .collect()
+}
 
 impl PlaylistService {
     pub async fn list<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
@@ -48,7 +222,7 @@ impl PlaylistService {
         name: &str,
     ) -> Result<(), CoolioError> {
         let playlist = storage.get_playlist(name).await?;
-        let external_playlist = spotify.playlist(&playlist.id).await?;
+        let external_playlist = spotify.playlist_info(&playlist.id).await?;
 
         let mut artists = Vec::<SimpleArtist>::new();
         for a_id in playlist.artists {
@@ -89,21 +263,192 @@ impl PlaylistService {
         Err("The playlist doesn't exist".into())
     }
 
+    pub async fn set_duplicates(
+        &self,
+        storage: &StorageBehavior,
+        name: &str,
+        allow: bool,
+    ) -> Result<(), CoolioError> {
+        let playlist = storage.get_playlist(name).await?;
+        storage.set_allow_duplicates(&playlist.id, allow).await
+    }
+
+    fn playlist_rules(&self, playlist: &Playlist) -> Result<TrackRules, CoolioError> {
+        TrackRules::new(playlist.rules.as_ref().unwrap_or(&self.default_rules))
+    }
+
+    /// Shows the exclusion rules of the playlist, after making the changes
+    /// to them if there are any.
+    pub async fn rules<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        storage: &StorageBehavior,
+        writer: &mut Interactor<'a, R, W>,
+        name: &str,
+        edit: RulesEdit,
+    ) -> Result<(), CoolioError> {
+        let playlist = storage.get_playlist(name).await?;
+        let rules = if edit.reset {
+            storage.set_rules(&playlist.id, None).await?;
+            None
+        } else if edit.is_empty() {
+            playlist.rules
+        } else {
+            let mut rules = playlist.rules.unwrap_or_else(|| self.default_rules.clone());
+            rules.apply(&edit);
+            // invalid patterns would only fail the next update
+            TrackRules::new(&rules)?;
+            storage.set_rules(&playlist.id, Some(&rules)).await?;
+            Some(rules)
+        };
+
+        match rules {
+            Some(rules) => writer.show_rules(&rules, false),
+            None => writer.show_rules(&self.default_rules, true),
+        }
+    }
+
+    async fn existing_tracks(
+        &self,
+        spotify: &impl Spotify,
+        playlist: &Playlist,
+    ) -> Result<Option<PlaylistTracks>, CoolioError> {
+        if playlist.allow_duplicates {
+            return Ok(None);
+        }
+
+        let mut existing = PlaylistTracks::default();
+        let mut items = PlaylistItems::new(spotify, &playlist.id);
+        while let Some(item) = items.next().await? {
+            existing.insert(&item.track);
+        }
+        Ok(Some(existing))
+    }
+
+    /// Leaves out the tracks that are already in the playlist, or earlier in
+    /// `tracks`, and records the rest as added. Nothing is left out when the
+    /// playlist allows duplicates.
+    async fn missing_tracks(
+        &self,
+        spotify: &impl Spotify,
+        existing: Option<&mut PlaylistTracks>,
+        mut tracks: Vec<SimpleTrack>,
+        limit: Option<usize>,
+    ) -> Result<Vec<SimpleTrack>, CoolioError> {
+        let limit = limit.unwrap_or(tracks.len());
+        let existing = match existing {
+            Some(existing) => existing,
+            None => {
+                tracks.truncate(limit);
+                return Ok(tracks);
+            }
+        };
+
+        // simplified tracks don't come with an ISRC
+        let without_isrc = tracks
+            .iter()
+            .filter(|t| t.isrc.is_none())
+            .map(|t| t.id.clone())
+            .collect::<Vec<String>>();
+        if !without_isrc.is_empty() {
+            let isrcs = spotify
+                .tracks(&without_isrc)
+                .await?
+                .into_iter()
+                .filter_map(|t| t.isrc.map(|isrc| (t.id, isrc)))
+                .collect::<HashMap<String, String>>();
+            for t in tracks.iter_mut().filter(|t| t.isrc.is_none()) {
+                t.isrc = isrcs.get(&t.id).cloned();
+            }
+        }
+
+        let candidates = tracks.len();
+        let mut missing = Vec::<SimpleTrack>::new();
+        for t in tracks {
+            if missing.len() == limit {
+                break;
+            }
+            if !existing.contains(&t) {
+                existing.insert(&t);
+                missing.push(t);
+            }
+        }
+        if missing.len() < min(limit, candidates) {
+            info!(
+                "skipped {} tracks already in the playlist",
+                min(limit, candidates) - missing.len()
+            );
+        }
+        Ok(missing)
+    }
+
+    async fn add_tracks(
+        &self,
+        spotify: &impl Spotify,
+        playlist: &Playlist,
+        artist_id: &str,
+        tracks: Vec<SimpleTrack>,
+        reason: AdditionReason,
+        changes: &mut PlaylistChanges,
+    ) -> Result<(), CoolioError> {
+        if tracks.is_empty() {
+            return Ok(());
+        }
+
+        for t in &tracks {
+            changes.planned.push(PlannedAddition {
+                playlist: playlist.name.clone(),
+                artist: t
+                    .artists
+                    .iter()
+                    .find(|a| a.id == artist_id)
+                    .map(|a| a.name.clone())
+                    .unwrap_or_else(|| artist_id.to_string()),
+                album: t.album_name.clone(),
+                track: t.name.clone(),
+                reason,
+            });
+        }
+
+        if !changes.dry_run {
+            spotify
+                .playlist_add_items(&playlist.id, tracks.into_iter().map(|x| x.id))
+                .await?;
+        }
+        Ok(())
+    }
+
     async fn seed_artist_popular(
         &self,
         spotify: &impl Spotify,
         _storage: &StorageBehavior,
-        artist_id: &String,
-        playlist_id: &String,
-        seed: usize,
+        artist_id: &str,
+        playlist: &Playlist,
+        options: &LinkOptions,
+        changes: &mut PlaylistChanges,
     ) -> Result<(), CoolioError> {
-        let tracks = spotify.artist_top_tracks(artist_id).await?;
-        let seed = min(seed, tracks.len());
-        spotify
-            .playlist_add_items(playlist_id, tracks[..seed].iter().map(|x| x.id.clone()))
+        let tracks = artist_tracks(
+            spotify.artist_top_tracks(artist_id).await?,
+            artist_id,
+            options,
+        );
+        let tracks = changes.rules.retain(tracks);
+        let tracks = self
+            .missing_tracks(
+                spotify,
+                changes.existing.as_mut(),
+                tracks,
+                Some(options.seed),
+            )
             .await?;
-
-        Ok(())
+        self.add_tracks(
+            spotify,
+            playlist,
+            artist_id,
+            tracks,
+            AdditionReason::Seed,
+            changes,
+        )
+        .await
     }
 
     pub async fn link_playlist_to_artist<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
@@ -113,34 +458,187 @@ impl PlaylistService {
         writer: &mut Interactor<'a, R, W>,
         playlist: &str,
         artist: &str,
-        seed: Option<usize>,
+        request: LinkRequest,
     ) -> Result<(), CoolioError> {
         let playlist = storage.get_playlist(playlist).await?;
-        let artists = spotify.search_artists(artist).await?;
-        let chosen_artist_id = writer.choose_artist(&artists)?;
+        let chosen_artist_id = self
+            .choose_artist(spotify, writer, artist, request.choice)
+            .await?;
+        self.link_artist(
+            spotify,
+            storage,
+            &playlist,
+            &chosen_artist_id,
+            &request.edit,
+        )
+        .await
+    }
+
+    /// Links all the artists listed in the file. Entries that can't be
+    /// resolved to a single artist or fail to be linked are reported, and the
+    /// rest are linked.
+    pub async fn link_file<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        spotify: &impl Spotify,
+        storage: &StorageBehavior,
+        writer: &mut Interactor<'a, R, W>,
+        playlist: &str,
+        file: &str,
+        request: LinkRequest,
+    ) -> Result<(), CoolioError> {
+        let playlist = storage.get_playlist(playlist).await?;
+        let entries = read_artist_list(Path::new(file))?;
+
+        let mut to_link = Vec::<(String, String)>::new();
+        let mut already_linked = 0;
+        let mut failed = Vec::<(String, String)>::new();
+        for entry in entries {
+            match self
+                .choose_artist(spotify, writer, &entry, request.choice)
+                .await
+            {
+                Ok(id)
+                    if playlist.artists.contains(&id)
+                        || to_link.iter().any(|(_, linked)| linked == &id) =>
+                {
+                    already_linked += 1
+                }
+                Ok(id) => to_link.push((entry, id)),
+                Err(e) => failed.push((entry, e.to_string())),
+            }
+        }
+
+        // one artist failing to be linked doesn't stop the others
+        let mut linked = 0;
+        let mut link_failed = Vec::<(String, String)>::new();
+        for (entry, artist_id) in to_link {
+            match self
+                .link_artist(spotify, storage, &playlist, &artist_id, &request.edit)
+                .await
+            {
+                Ok(()) => linked += 1,
+                Err(e) => link_failed.push((entry, e.to_string())),
+            }
+        }
+        writer.link_file_summary(linked, already_linked, &failed, &link_failed)
+    }
+
+    async fn link_artist(
+        &self,
+        spotify: &impl Spotify,
+        storage: &StorageBehavior,
+        playlist: &Playlist,
+        chosen_artist_id: &str,
+        edit: &LinkEdit,
+    ) -> Result<(), CoolioError> {
+        let mut options = LinkOptions::default();
+        options.apply(edit);
+        // the seed is added right away when given, otherwise on the next update
+        self.link_with_options(
+            spotify,
+            storage,
+            playlist,
+            chosen_artist_id,
+            &options,
+            edit.seed.is_some(),
+        )
+        .await
+    }
 
+    /// Links the artist to the playlist. A seeded artist gets its popular
+    /// tracks added and its checkpoint set right away, as the next update
+    /// would do otherwise.
+    pub async fn link_with_options(
+        &self,
+        spotify: &impl Spotify,
+        storage: &StorageBehavior,
+        playlist: &Playlist,
+        artist_id: &str,
+        options: &LinkOptions,
+        seed: bool,
+    ) -> Result<(), CoolioError> {
         storage
-            .link_artist(&playlist.id, &playlist.name, &chosen_artist_id)
+            .link_artist(&playlist.id, &playlist.name, artist_id, options)
             .await?;
+        if !seed {
+            return Ok(());
+        }
 
-        if let Some(seed) = seed {
-            self.seed_artist_popular(spotify, storage, &chosen_artist_id, &playlist.id, seed)
-                .await?;
+        let mut changes = PlaylistChanges {
+            dry_run: false,
+            existing: self.existing_tracks(spotify, playlist).await?,
+            rules: self.playlist_rules(playlist)?,
+            albums: HashSet::new(),
+            planned: vec![],
+        };
+        let checked_at = Utc::now();
+        self.seed_artist_popular(spotify, storage, artist_id, playlist, options, &mut changes)
+            .await?;
+        storage
+            .set_checkpoint(
+                &playlist.id,
+                artist_id,
+                &LinkCheckpoint {
+                    last_checked: checked_at,
+                    albums: vec![],
+                },
+            )
+            .await
+    }
+
+    /// Finds the artist by its URI, or by searching for it and picking one of
+    /// the results.
+    async fn choose_artist<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        spotify: &impl Spotify,
+        writer: &mut Interactor<'a, R, W>,
+        artist: &str,
+        choice: ArtistChoice,
+    ) -> Result<String, CoolioError> {
+        if let Some(uri) = artist_uri(artist) {
+            return Ok(spotify.artist(&uri).await?.id);
         }
 
-        Ok(())
+        let mut artists = spotify.search_artists(artist).await?;
+        if artists.is_empty() {
+            return Err(format!("no artists found for {}", artist).into());
+        }
+        match choice {
+            ArtistChoice::Prompt => writer.choose_artist(&artists),
+            ArtistChoice::First => Ok(artists.remove(0).id),
+            ArtistChoice::Unique => {
+                if artists.len() > 1 {
+                    artists.retain(|a| a.name.to_lowercase() == artist.to_lowercase());
+                }
+                match artists.len() {
+                    1 => Ok(artists.remove(0).id),
+                    _ => Err(format!(
+                        "ambiguous artists found for {}, use the artist's URI instead",
+                        artist
+                    )
+                    .into()),
+                }
+            }
+        }
     }
 
-    pub async fn unlink_artist_from_playlist(
+    /// Finds the linked artist of the playlist by its URI, or the one that
+    /// matches the search.
+    async fn linked_artist(
         &self,
         spotify: &impl Spotify,
-        storage: &StorageBehavior,
-        playlist: &str,
+        playlist: &Playlist,
         artist: &str,
-    ) -> Result<(), CoolioError> {
-        let playlist = storage.get_playlist(playlist).await?;
+    ) -> Result<String, CoolioError> {
+        if let Some(uri) = artist_uri(artist) {
+            let id = spotify.artist(&uri).await?.id;
+            if !playlist.artists.contains(&id) {
+                return Err("artist not linked to playlist".into());
+            }
+            return Ok(id);
+        }
 
-        let potentials = spotify
+        let mut potentials = spotify
             .search_artists(artist)
             .await?
             .into_iter()
@@ -149,47 +647,87 @@ impl PlaylistService {
 
         match potentials.len() {
             0 => Err("no artists in the playlist matched your search".into()),
-            1 => storage.unlink_artist(&playlist.id, &potentials[0].id).await,
-            _ => Err("ambigious artists found, try again more concrete".into()),
+            1 => Ok(potentials.remove(0).id),
+            _ => Err("ambiguous artists found, try again more concrete".into()),
         }
     }
 
-    async fn artists_new_albums_filter(
+    pub async fn unlink_artist_from_playlist(
         &self,
         spotify: &impl Spotify,
-        _storage: &StorageBehavior,
-        artist_id: &String,
-        last_added: &DateTime<Utc>,
-        album_type: &AlbumType,
-    ) -> Result<Vec<String>, CoolioError> {
-        let albums = spotify.artist_albums(artist_id, album_type).await?;
-        let mut album_ids = Vec::<String>::new();
+        storage: &StorageBehavior,
+        playlist: &str,
+        artist: &str,
+    ) -> Result<(), CoolioError> {
+        let playlist = storage.get_playlist(playlist).await?;
+        let artist_id = self.linked_artist(spotify, &playlist, artist).await?;
+        storage.unlink_artist(&playlist.id, &artist_id).await
+    }
 
-        for album in albums {
-            if album.release_date > *last_added {
-                album_ids.push(album.id);
+    /// Unlinks all the artists listed in the file, reporting the ones that
+    /// aren't linked to the playlist or fail to be unlinked.
+    pub async fn unlink_file<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        spotify: &impl Spotify,
+        storage: &StorageBehavior,
+        writer: &mut Interactor<'a, R, W>,
+        playlist: &str,
+        file: &str,
+    ) -> Result<(), CoolioError> {
+        let playlist = storage.get_playlist(playlist).await?;
+        let entries = read_artist_list(Path::new(file))?;
+
+        let mut to_unlink = Vec::<(String, String)>::new();
+        let mut failed = Vec::<(String, String)>::new();
+        for entry in entries {
+            match self.linked_artist(spotify, &playlist, &entry).await {
+                Ok(id) if to_unlink.iter().any(|(_, unlinked)| unlinked == &id) => {}
+                Ok(id) => to_unlink.push((entry, id)),
+                Err(e) => failed.push((entry, e.to_string())),
             }
         }
 
-        Ok(album_ids)
+        // one artist failing to be unlinked doesn't stop the others
+        let mut unlinked = 0;
+        let mut unlink_failed = Vec::<(String, String)>::new();
+        for (entry, artist_id) in to_unlink {
+            match storage.unlink_artist(&playlist.id, &artist_id).await {
+                Ok(()) => unlinked += 1,
+                Err(e) => unlink_failed.push((entry, e.to_string())),
+            }
+        }
+        writer.unlink_file_summary(unlinked, &failed, &unlink_failed)
     }
 
-    async fn artists_new_albums(
+    pub async fn edit_link(
         &self,
         spotify: &impl Spotify,
         storage: &StorageBehavior,
-        artist_id: &String,
-        last_added: &DateTime<Utc>,
-    ) -> Result<Vec<String>, CoolioError> {
-        let mut all = Vec::<String>::new();
-        for t in &[AlbumType::Album, AlbumType::Single] {
-            let f = self
-                .artists_new_albums_filter(spotify, storage, artist_id, last_added, &t)
-                .await?;
+        playlist: &str,
+        artist: &str,
+        edit: LinkEdit,
+    ) -> Result<(), CoolioError> {
+        let playlist = storage.get_playlist(playlist).await?;
+        let artist_id = self.linked_artist(spotify, &playlist, artist).await?;
+        let mut options = storage.get_link_options(&playlist.id, &artist_id).await?;
+        options.apply(&edit);
+        storage
+            .set_link_options(&playlist.id, &artist_id, &options)
+            .await
+    }
 
-            for t in f {
-                if !all.contains(&t) {
-                    all.push(t);
+    async fn artist_albums(
+        &self,
+        spotify: &impl Spotify,
+        _storage: &StorageBehavior,
+        artist_id: &str,
+        options: &LinkOptions,
+    ) -> Result<Vec<SimpleAlbum>, CoolioError> {
+        let mut all = Vec::<SimpleAlbum>::new();
+        for t in &options.release_types {
+            for album in spotify.artist_albums(artist_id, &album_type(t)).await? {
+                if !all.iter().any(|a| a.id == album.id) {
+                    all.push(album);
                 }
             }
         }
@@ -200,13 +738,17 @@ impl PlaylistService {
         &self,
         spotify: &impl Spotify,
         _storage: &StorageBehavior,
-        albums: Vec<String>,
+        albums: Vec<SimpleAlbum>,
     ) -> Result<Vec<SimpleTrack>, CoolioError> {
         let mut tracks_to_add = Vec::<SimpleTrack>::new();
 
-        for album_id_to_add in albums {
-            let mut tracks = spotify.album_tracks(&album_id_to_add).await?;
-            tracks_to_add.append(&mut tracks);
+        for album in albums {
+            for mut t in spotify.album_tracks(&album.id).await? {
+                // album tracks don't say which album they are on
+                t.album_id = Some(album.id.clone());
+                t.album_name = Some(album.name.clone());
+                tracks_to_add.push(t);
+            }
         }
 
         Ok(tracks_to_add)
@@ -215,34 +757,54 @@ impl PlaylistService {
     async fn artist_add_last(
         &self,
         spotify: &impl Spotify,
-        storage: &StorageBehavior,
-        artist_id: &String,
-        playlist_id: &String,
-        last_added: &DateTime<Utc>,
+        artist_id: &str,
+        playlist: &Playlist,
+        tracks: Vec<SimpleTrack>,
+        options: &LinkOptions,
+        changes: &mut PlaylistChanges,
     ) -> Result<(), CoolioError> {
-        let album_ids = self
-            .artists_new_albums(spotify, storage, artist_id, last_added)
+        let tracks = artist_tracks(tracks, artist_id, options);
+        let tracks = changes.rules.retain(tracks);
+        let tracks = self
+            .missing_tracks(spotify, changes.existing.as_mut(), tracks, None)
             .await?;
-        let tracks = self.albums_to_tracks(spotify, storage, album_ids).await?;
-        if tracks.len() > 0 {
-            spotify
-                .playlist_add_items(playlist_id, tracks.into_iter().map(|x| x.id))
-                .await?;
-        }
-
-        Ok(())
+        self.add_tracks(
+            spotify,
+            playlist,
+            artist_id,
+            tracks,
+            AdditionReason::NewRelease,
+            changes,
+        )
+        .await
     }
 
+    /// Finds when each artist was last added to the playlist, which is where
+    /// updates of links without a checkpoint continue from, along with the
+    /// tracks and albums already in it.
     async fn playlist_artist_last_add(
         &self,
         spotify: &impl Spotify,
         _storage: &StorageBehavior,
         playlist: &Playlist,
-    ) -> Result<HashMap<String, DateTime<Utc>>, CoolioError> {
-        let external_playlist = spotify.playlist(&playlist.id).await?;
+    ) -> Result<
+        (
+            HashMap<String, DateTime<Utc>>,
+            PlaylistTracks,
+            HashSet<String>,
+        ),
+        CoolioError,
+    > {
+        let mut items = PlaylistItems::new(spotify, &playlist.id);
 
+        let mut existing = PlaylistTracks::default();
+        let mut albums = HashSet::<String>::new();
         let mut last_song_for_artist = HashMap::<String, DateTime<Utc>>::new();
-        for track in external_playlist.tracks {
+        while let Some(track) = items.next().await? {
+            existing.insert(&track.track);
+            if let Some(album_id) = &track.track.album_id {
+                albums.insert(album_id.clone());
+            }
             if let Some(added_at) = track.added_at {
                 for art in track.track.artists {
                     if let Some(added_last) = last_song_for_artist.get(&art.id) {
@@ -255,7 +817,67 @@ impl PlaylistService {
                 }
             }
         }
-        Ok(last_song_for_artist)
+        Ok((last_song_for_artist, existing, albums))
+    }
+
+    /// Adds the artist's releases that are newer than the checkpoint and
+    /// returns the new checkpoint. Links without one continue from the
+    /// artist's last track in the playlist, or get seeded if there is none.
+    async fn artist_update(
+        &self,
+        spotify: &impl Spotify,
+        storage: &StorageBehavior,
+        artist_id: &str,
+        playlist: &Playlist,
+        last_added: Option<&DateTime<Utc>>,
+        changes: &mut PlaylistChanges,
+    ) -> Result<LinkCheckpoint, CoolioError> {
+        let checked_at = Utc::now();
+        let checkpoint = storage.get_checkpoint(&playlist.id, artist_id).await?;
+        let options = storage.get_link_options(&playlist.id, artist_id).await?;
+        let albums = match (&checkpoint, last_added) {
+            (None, None) => {
+                self.seed_artist_popular(spotify, storage, artist_id, playlist, &options, changes)
+                    .await?;
+                return Ok(LinkCheckpoint {
+                    last_checked: checked_at,
+                    albums: vec![],
+                });
+            }
+            _ => {
+                self.artist_albums(spotify, storage, artist_id, &options)
+                    .await?
+            }
+        };
+
+        let new_albums = albums
+            .iter()
+            .filter(|a| match (&checkpoint, last_added) {
+                // releases from the day of the last check may have come out
+                // after it, the checked albums tell which didn't
+                (Some(c), _) => !a.released_before(&c.last_checked) && !c.albums.contains(&a.id),
+                // the albums from the period of the last added track may have
+                // come out after it, unless the playlist has them already
+                (None, Some(last_added)) => {
+                    a.released_after(last_added) && !changes.albums.contains(&a.id)
+                }
+                (None, None) => false,
+            })
+            .cloned()
+            .collect::<Vec<SimpleAlbum>>();
+        let tracks = self.albums_to_tracks(spotify, storage, new_albums).await?;
+        self.artist_add_last(spotify, artist_id, playlist, tracks, &options, changes)
+            .await?;
+
+        // older albums are left out by their release date anyway
+        Ok(LinkCheckpoint {
+            last_checked: checked_at,
+            albums: albums
+                .into_iter()
+                .filter(|a| !a.released_before(&checked_at))
+                .map(|a| a.id)
+                .collect(),
+        })
     }
 
     async fn playlist_update(
@@ -263,36 +885,181 @@ impl PlaylistService {
         spotify: &impl Spotify,
         storage: &StorageBehavior,
         playlist: &Playlist,
-    ) -> Result<(), CoolioError> {
-        let last_song_for_artist = self
+        dry_run: bool,
+    ) -> Result<PlannedChanges, CoolioError> {
+        let (last_song_for_artist, existing, albums) = self
             .playlist_artist_last_add(spotify, storage, playlist)
             .await?;
+        let mut changes = PlaylistChanges {
+            dry_run,
+            existing: if playlist.allow_duplicates {
+                None
+            } else {
+                Some(existing)
+            },
+            albums,
+            rules: self.playlist_rules(playlist)?,
+            planned: vec![],
+        };
 
         for artist_id in &playlist.artists {
-            match last_song_for_artist.get(artist_id) {
-                None => {
-                    self.seed_artist_popular(spotify, storage, artist_id, &playlist.id, 5)
-                        .await?
-                }
-                Some(last_added) => {
-                    self.artist_add_last(spotify, storage, artist_id, &playlist.id, last_added)
-                        .await?
-                }
+            let checkpoint = self
+                .artist_update(
+                    spotify,
+                    storage,
+                    artist_id,
+                    playlist,
+                    last_song_for_artist.get(artist_id),
+                    &mut changes,
+                )
+                .await?;
+            if !dry_run {
+                storage
+                    .set_checkpoint(&playlist.id, artist_id, &checkpoint)
+                    .await?;
             }
         }
 
-        Ok(())
+        let removals = self.trim(spotify, playlist, &changes).await?;
+        Ok(PlannedChanges {
+            additions: changes.planned,
+            removals,
+        })
     }
 
-    pub async fn update(
+    /// Removes the tracks past the retention of the playlist, the oldest
+    /// ones first, and returns them. On a dry run nothing is removed, and the
+    /// tracks the update plans to add count as added now.
+    async fn trim(
         &self,
         spotify: &impl Spotify,
+        playlist: &Playlist,
+        changes: &PlaylistChanges,
+    ) -> Result<Vec<PlannedRemoval>, CoolioError> {
+        let retention = &playlist.retention;
+        if !retention.is_limited() {
+            return Ok(vec![]);
+        }
+
+        let info = spotify.playlist_info(&playlist.id).await?;
+        let mut tracks = Vec::<RetainedTrack>::new();
+        let mut items = PlaylistItems::after(spotify, &info);
+        while let Some(item) = items.next().await? {
+            tracks.push(RetainedTrack {
+                position: item.position,
+                added_at: item.added_at,
+                artist: item
+                    .track
+                    .artists
+                    .first()
+                    .map(|a| a.name.clone())
+                    .unwrap_or_default(),
+                name: item.track.name,
+                id: Some(item.track.id),
+            });
+        }
+        if changes.dry_run {
+            let now = Utc::now();
+            for (i, p) in changes.planned.iter().enumerate() {
+                tracks.push(RetainedTrack {
+                    id: None,
+                    position: info.num_tracks + i as u32,
+                    added_at: Some(now),
+                    artist: p.artist.clone(),
+                    name: p.track.clone(),
+                });
+            }
+        }
+        // tracks without the time they were added count as the oldest ones
+        tracks.sort_by_key(|t| (t.added_at, t.position));
+
+        let cutoff = retention
+            .max_age_days
+            .map(|days| Utc::now() - Duration::days(days as i64));
+        let (expired, mut kept): (Vec<_>, Vec<_>) =
+            tracks
+                .into_iter()
+                .partition(|t| match (t.added_at, cutoff) {
+                    (Some(added), Some(cutoff)) => added < cutoff,
+                    _ => false,
+                });
+        let mut removed = expired
+            .into_iter()
+            .map(|t| (t, RemovalReason::MaxAge))
+            .collect::<Vec<_>>();
+        if let Some(max) = retention.max_tracks {
+            if kept.len() > max {
+                removed.extend(
+                    kept.drain(..kept.len() - max)
+                        .map(|t| (t, RemovalReason::MaxTracks)),
+                );
+            }
+        }
+
+        if !changes.dry_run && !removed.is_empty() {
+            spotify
+                .playlist_remove_items(
+                    &playlist.id,
+                    &info.snapshot_id,
+                    removed
+                        .iter()
+                        .filter_map(|(t, _)| t.id.clone().map(|id| (id, t.position)))
+                        .collect(),
+                )
+                .await?;
+            info!(
+                "removed {} tracks past the retention of {}",
+                removed.len(),
+                playlist.name
+            );
+        }
+        Ok(removed
+            .into_iter()
+            .map(|(t, reason)| PlannedRemoval {
+                playlist: playlist.name.clone(),
+                artist: t.artist,
+                track: t.name,
+                reason,
+            })
+            .collect())
+    }
+
+    /// Shows the retention of the playlist, after making the changes to it if
+    /// there are any.
+    pub async fn retention<'a, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
         storage: &StorageBehavior,
+        writer: &mut Interactor<'a, R, W>,
+        name: &str,
+        edit: RetentionEdit,
     ) -> Result<(), CoolioError> {
+        let mut playlist = storage.get_playlist(name).await?;
+        if edit != RetentionEdit::default() {
+            playlist.retention.apply(&edit);
+            storage
+                .set_retention(&playlist.id, &playlist.retention)
+                .await?;
+        }
+        writer.show_retention(&playlist.retention)
+    }
+
+    /// Adds the new tracks to the playlists, removes the ones past their
+    /// retention and returns what was changed. On a dry run nothing is.
+    pub async fn update(
+        &self,
+        spotify: &impl Spotify,
+        storage: &StorageBehavior,
+        dry_run: bool,
+    ) -> Result<PlannedChanges, CoolioError> {
         let playlists = storage.get_playlists().await?;
+        let mut planned = PlannedChanges::default();
         for playlist in playlists {
-            self.playlist_update(spotify, storage, &playlist).await?
+            let mut changes = self
+                .playlist_update(spotify, storage, &playlist, dry_run)
+                .await?;
+            planned.additions.append(&mut changes.additions);
+            planned.removals.append(&mut changes.removals);
         }
-        Ok(())
+        Ok(planned)
     }
 }
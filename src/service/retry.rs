@@ -0,0 +1,202 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use rand::Rng;
+use rspotify::model::AlbumType;
+
+use crate::error::{CoolioError, Retry};
+use crate::models::Listen;
+use crate::settings::Retry as RetryConf;
+
+use super::spotify::{
+    SimpleAlbum, SimpleArtist, SimplePage, SimplePlayable, SimplePlaylist, SimpleTrack, Spotify,
+};
+
+/// Retries the requests of another client when Spotify rate limits them, and
+/// the idempotent ones also when they fail because of server or network errors.
+pub struct RetrySpotify<S: Spotify> {
+    inner: S,
+    conf: RetryConf,
+}
+
+impl<S: Spotify> RetrySpotify<S> {
+    pub fn new(inner: S, conf: RetryConf) -> Self {
+        RetrySpotify { inner, conf }
+    }
+
+    /// Exponential backoff with jitter, so that retries don't come in bursts.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let max = self
+            .conf
+            .initial_delay_ms
+            .saturating_mul(1 << attempt.min(16));
+        Duration::from_millis(rand::thread_rng().gen_range(max / 2..=max))
+    }
+
+    async fn retry<T, F, Fut>(
+        &self,
+        name: &str,
+        idempotent: bool,
+        mut request: F,
+    ) -> Result<T, CoolioError>
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<T, CoolioError>> + Send,
+    {
+        let started = Instant::now();
+        let max_time = Duration::from_secs(self.conf.max_time_secs);
+        let mut attempt = 0;
+        loop {
+            let err = match request().await {
+                Ok(res) => {
+                    if attempt > 0 {
+                        info!("{} succeeded after {} retries", name, attempt);
+                    }
+                    return Ok(res);
+                }
+                Err(e) => e,
+            };
+
+            let delay = match err.retry() {
+                Some(Retry::RateLimited(Some(after))) => after,
+                Some(Retry::RateLimited(None)) => self.backoff(attempt),
+                // the request may have been carried out even though it failed
+                Some(Retry::Transient) if idempotent => self.backoff(attempt),
+                _ => return Err(err),
+            };
+            if started.elapsed() + delay > max_time {
+                warn!("{} failed after {} retries: {}", name, attempt, err);
+                return Err(err);
+            }
+
+            attempt += 1;
+            warn!(
+                "{} failed ({}), retry {} in {}ms",
+                name,
+                err,
+                attempt,
+                delay.as_millis()
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Spotify> Spotify for RetrySpotify<S> {
+    async fn current_user_recently_played(
+        &self,
+        limit: u32,
+        time_limit: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Listen>, CoolioError> {
+        self.retry("recently played", true, || {
+            self.inner.current_user_recently_played(limit, time_limit)
+        })
+        .await
+    }
+
+    async fn create_playlist(&self, name: &str) -> Result<SimplePlaylist, CoolioError> {
+        self.retry("create playlist", false, || {
+            self.inner.create_playlist(name)
+        })
+        .await
+    }
+
+    async fn playlist_add_batch(
+        &self,
+        playlist_id: &str,
+        items: &[String],
+        position: u32,
+    ) -> Result<(), CoolioError> {
+        self.retry("add playlist items", false, || {
+            self.inner.playlist_add_batch(playlist_id, items, position)
+        })
+        .await
+    }
+
+    async fn playlist_remove_batch(
+        &self,
+        playlist_id: &str,
+        snapshot_id: &str,
+        items: &[(String, u32)],
+    ) -> Result<(), CoolioError> {
+        self.retry("remove playlist items", false, || {
+            self.inner
+                .playlist_remove_batch(playlist_id, snapshot_id, items)
+        })
+        .await
+    }
+
+    async fn current_user_playlists(&self) -> Result<Vec<SimplePlaylist>, CoolioError> {
+        self.retry("user playlists", true, || {
+            self.inner.current_user_playlists()
+        })
+        .await
+    }
+
+    async fn artist_top_tracks(&self, id: &str) -> Result<Vec<SimpleTrack>, CoolioError> {
+        self.retry("artist top tracks", true, || {
+            self.inner.artist_top_tracks(id)
+        })
+        .await
+    }
+
+    async fn album_tracks(&self, id: &str) -> Result<Vec<SimpleTrack>, CoolioError> {
+        self.retry("album tracks", true, || self.inner.album_tracks(id))
+            .await
+    }
+
+    async fn artist_albums(
+        &self,
+        id: &str,
+        album_type: &AlbumType,
+    ) -> Result<Vec<SimpleAlbum>, CoolioError> {
+        self.retry("artist albums", true, || {
+            self.inner.artist_albums(id, album_type)
+        })
+        .await
+    }
+
+    async fn playlist_info(&self, id: &str) -> Result<SimplePlaylist, CoolioError> {
+        self.retry("playlist info", true, || self.inner.playlist_info(id))
+            .await
+    }
+
+    async fn playlist_items(
+        &self,
+        id: &str,
+        offset: u32,
+    ) -> Result<SimplePage<SimplePlayable>, CoolioError> {
+        self.retry("playlist items", true, || {
+            self.inner.playlist_items(id, offset)
+        })
+        .await
+    }
+
+    async fn tracks(&self, ids: &[String]) -> Result<Vec<SimpleTrack>, CoolioError> {
+        self.retry("tracks", true, || self.inner.tracks(ids)).await
+    }
+
+    async fn artist(&self, id: &str) -> Result<SimpleArtist, CoolioError> {
+        self.retry("artist", true, || self.inner.artist(id)).await
+    }
+
+    async fn search_artists(&self, name: &str) -> Result<Vec<SimpleArtist>, CoolioError> {
+        self.retry("search artists", true, || self.inner.search_artists(name))
+            .await
+    }
+
+    async fn search_tracks(
+        &self,
+        name: &str,
+        artist: &str,
+    ) -> Result<Vec<SimpleTrack>, CoolioError> {
+        self.retry("search tracks", true, || {
+            self.inner.search_tracks(name, artist)
+        })
+        .await
+    }
+}
@@ -1,15 +1,47 @@
+use std::cmp::Reverse;
+use std::str::FromStr;
+
 use async_trait::async_trait;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Utc};
+use log::warn;
 use rspotify::model::{
-    AlbumId, AlbumType, ArtistId, FullArtist, FullPlaylist, FullTrack, Market, PlayableItem,
-    PlaylistId, PlaylistItem, SearchResult, SearchType, SimplifiedArtist, SimplifiedPlaylist,
-    SimplifiedTrack, TrackId,
+    AlbumId, AlbumType, ArtistId, FullArtist, FullPlaylist, FullTrack, ItemPositions, Market,
+    PlayableItem, PlaylistId, PlaylistItem, SearchResult, SearchType, SimplifiedArtist,
+    SimplifiedPlaylist, SimplifiedTrack, TrackId,
 };
 use rspotify::prelude::*;
 use rspotify::{model::TimeLimits, AuthCodeSpotify};
 
 use crate::error::CoolioError;
-use crate::models::{Listen, Playlist};
+use crate::models::{Listen, Playlist, Retention, TrackMetadata};
+
+// the most playlist items Spotify returns in one page
+pub const PLAYLIST_ITEMS_LIMIT: u32 = 100;
+// the most items that can be added to a playlist in one request
+pub const PLAYLIST_ADD_LIMIT: usize = 100;
+// the most items that can be removed from a playlist in one request
+pub const PLAYLIST_REMOVE_LIMIT: usize = 100;
+// the most tracks that can be fetched in one request
+pub const TRACKS_LIMIT: usize = 50;
+
+/// The URI of an artist given as a `spotify:artist:` URI or an
+/// `open.spotify.com/artist/` URL, none for anything else. Whether the
+/// artist exists is left to `Spotify::artist`.
+pub fn artist_uri(s: &str) -> Option<String> {
+    let s = s.trim();
+    let id = match s.strip_prefix("spotify:artist:") {
+        Some(id) => id,
+        None => {
+            let (_, path) = s.split_once("open.spotify.com/")?;
+            let (_, id) = path.split_once("artist/")?;
+            id.split(['?', '/']).next()?
+        }
+    };
+    if id.is_empty() || id.contains(char::is_whitespace) {
+        return None;
+    }
+    Some(format!("spotify:artist:{}", id))
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct SimpleArtist {
@@ -44,20 +76,48 @@ impl From<FullArtist> for SimpleArtist {
 #[derive(Debug, Default, Clone)]
 pub struct SimpleTrack {
     pub id: String,
+    pub name: String,
     pub artists: Vec<SimpleArtist>,
+    pub album_id: Option<String>,
+    pub album_name: Option<String>,
+    pub duration_ms: u32,
+    // only full tracks come with their ISRC
+    pub isrc: Option<String>,
+    pub explicit: bool,
+}
+
+impl From<SimpleTrack> for TrackMetadata {
+    fn from(t: SimpleTrack) -> Self {
+        TrackMetadata {
+            name: t.name,
+            artist_ids: t.artists.iter().map(|a| a.id.clone()).collect(),
+            artist_names: t.artists.into_iter().map(|a| a.name).collect(),
+            album_id: t.album_id,
+            album_name: t.album_name,
+            duration_ms: t.duration_ms,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct SimplePlayable {
+    // where the item is in the playlist
+    pub position: u32,
     pub added_at: Option<DateTime<Utc>>,
     pub track: SimpleTrack,
 }
 
 impl From<FullTrack> for SimpleTrack {
-    fn from(t: FullTrack) -> Self {
+    fn from(mut t: FullTrack) -> Self {
         SimpleTrack {
+            isrc: t.external_ids.remove("isrc"),
             id: t.id.unwrap().uri(),
+            name: t.name,
             artists: t.artists.into_iter().map(|a| a.into()).collect(),
+            album_id: t.album.id.map(|x| x.uri()),
+            album_name: Some(t.album.name),
+            duration_ms: t.duration.as_millis() as u32,
+            explicit: t.explicit,
         }
     }
 }
@@ -66,19 +126,51 @@ impl From<SimplifiedTrack> for SimpleTrack {
     fn from(t: SimplifiedTrack) -> Self {
         SimpleTrack {
             id: t.id.unwrap().uri(),
+            name: t.name,
             artists: t.artists.into_iter().map(|a| a.into()).collect(),
+            album_id: None,
+            album_name: None,
+            duration_ms: t.duration.as_millis() as u32,
+            isrc: None,
+            explicit: t.explicit,
         }
     }
 }
 
-impl From<PlaylistItem> for SimplePlayable {
-    fn from(pi: PlaylistItem) -> Self {
-        SimplePlayable {
-            added_at: pi.added_at,
-            track: match pi.track {
-                Some(PlayableItem::Track(t)) => t.into(),
-                _ => unreachable!(),
-            },
+/// The tracks of a page of playlist items starting at `offset`. Podcast
+/// episodes and local files are left out, they aren't tracks updates handle.
+pub fn playlist_tracks(items: Vec<PlaylistItem>, offset: u32) -> Vec<SimplePlayable> {
+    items
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, item)| match item.track {
+            Some(PlayableItem::Track(t)) if t.id.is_some() => Some(SimplePlayable {
+                position: offset + i as u32,
+                added_at: item.added_at,
+                track: t.into(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// How precisely the release date of an album is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatePrecision {
+    Day,
+    Month,
+    Year,
+}
+
+impl FromStr for DatePrecision {
+    type Err = CoolioError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(DatePrecision::Day),
+            "month" => Ok(DatePrecision::Month),
+            "year" => Ok(DatePrecision::Year),
+            _ => Err(format!("unknown release date precision: {}", s).into()),
         }
     }
 }
@@ -86,30 +178,89 @@ impl From<PlaylistItem> for SimplePlayable {
 #[derive(Debug, Clone)]
 pub struct SimpleAlbum {
     pub id: String,
+    pub name: String,
+    // the start of the day, month or year the album was released in
     pub release_date: DateTime<Utc>,
+    pub release_precision: DatePrecision,
+}
+
+impl SimpleAlbum {
+    /// Parses a release date as given by Spotify, `2021-03-05`, `2021-03`
+    /// or `2021` depending on its precision.
+    pub fn parse_release_date(
+        date: &str,
+        precision: DatePrecision,
+    ) -> Result<DateTime<Utc>, CoolioError> {
+        let date = match precision {
+            DatePrecision::Day => date.to_string(),
+            DatePrecision::Month => format!("{}-01", date),
+            DatePrecision::Year => format!("{}-01-01", date),
+        };
+        Ok(DateTime::<Utc>::from_naive_utc_and_offset(
+            NaiveDate::parse_from_str(&date, "%Y-%m-%d")?.and_time(NaiveTime::MIN),
+            Utc,
+        ))
+    }
+
+    /// Whether the album may have been released after the given time. An
+    /// album from 2021 counts as released after any time in 2021, as its date
+    /// isn't known precisely enough to tell, so callers have to leave out the
+    /// albums they already have by their ids.
+    pub fn released_after(&self, time: &DateTime<Utc>) -> bool {
+        !self.released_before(time)
+    }
+
+    /// Whether the album was released before the day, month or year of the
+    /// given time, depending on how precisely the release date is known.
+    pub fn released_before(&self, time: &DateTime<Utc>) -> bool {
+        let released = self.release_date;
+        match self.release_precision {
+            DatePrecision::Day => released.date_naive() < time.date_naive(),
+            DatePrecision::Month => {
+                (released.year(), released.month()) < (time.year(), time.month())
+            }
+            DatePrecision::Year => released.year() < time.year(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct SimplePlaylist {
     pub id: String,
     pub name: String,
+    // the version of the playlist
+    pub snapshot_id: String,
     pub description: Option<String>,
     pub num_followers: u32,
     pub collaborative: bool,
     pub public: bool,
+    pub num_tracks: u32,
     pub tracks: Vec<SimplePlayable>,
+    // offset of the tracks after the ones fetched, none when there are no more
+    pub next_tracks: Option<u32>,
+}
+
+/// A page of items, with the offset of the next one if there is any.
+#[derive(Debug, Default, Clone)]
+pub struct SimplePage<T> {
+    pub items: Vec<T>,
+    pub next: Option<u32>,
 }
 
 impl From<FullPlaylist> for SimplePlaylist {
     fn from(p: FullPlaylist) -> Self {
+        let next_tracks = p.tracks.next.as_ref().map(|_| p.tracks.items.len() as u32);
         SimplePlaylist {
             id: p.id.uri(),
             name: p.name,
+            snapshot_id: p.snapshot_id,
             description: p.description,
             num_followers: p.followers.total,
             collaborative: p.collaborative,
             public: p.public.unwrap_or(false),
-            tracks: p.tracks.items.into_iter().map(|x| x.into()).collect(),
+            num_tracks: p.tracks.total,
+            tracks: playlist_tracks(p.tracks.items, 0),
+            next_tracks,
         }
     }
 }
@@ -119,11 +270,14 @@ impl From<SimplifiedPlaylist> for SimplePlaylist {
         SimplePlaylist {
             id: p.id.uri(),
             name: p.name,
+            snapshot_id: p.snapshot_id,
             description: None,
             num_followers: 0,
             collaborative: p.collaborative,
             public: p.public.unwrap_or(false),
+            num_tracks: p.tracks.total,
             tracks: vec![],
+            next_tracks: None,
         }
     }
 }
@@ -135,6 +289,9 @@ impl Into<Playlist> for SimplePlaylist {
             name: self.name,
             artists: vec![],
             automated: false,
+            allow_duplicates: false,
+            rules: None,
+            retention: Retention::default(),
         }
     }
 }
@@ -149,10 +306,78 @@ pub trait Spotify: Send + Sync {
 
     async fn create_playlist(&self, name: &str) -> Result<SimplePlaylist, CoolioError>;
 
+    /// Adds the items in batches the API accepts, keeping their order.
     async fn playlist_add_items<'a>(
         &self,
         playlist_id: &str,
         items: impl IntoIterator<Item = String> + Send + 'a,
+    ) -> Result<(), CoolioError> {
+        let items = items.into_iter().collect::<Vec<String>>();
+        let batches = items.chunks(PLAYLIST_ADD_LIMIT).len();
+
+        for (i, batch) in items.chunks(PLAYLIST_ADD_LIMIT).enumerate() {
+            let position = (i * PLAYLIST_ADD_LIMIT) as u32;
+            if let Err(e) = self.playlist_add_batch(playlist_id, batch, position).await {
+                return Err(format!(
+                    "adding batch {} of {} to the playlist failed, {} of {} tracks were added: {}",
+                    i + 1,
+                    batches,
+                    position,
+                    items.len(),
+                    e
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds at most `PLAYLIST_ADD_LIMIT` items at the given position.
+    async fn playlist_add_batch(
+        &self,
+        playlist_id: &str,
+        items: &[String],
+        position: u32,
+    ) -> Result<(), CoolioError>;
+
+    /// Removes the items at the given positions in batches the API accepts,
+    /// starting from the last so that the positions of the rest don't move.
+    /// The positions are those of the playlist at the given snapshot.
+    async fn playlist_remove_items(
+        &self,
+        playlist_id: &str,
+        snapshot_id: &str,
+        mut items: Vec<(String, u32)>,
+    ) -> Result<(), CoolioError> {
+        items.sort_by_key(|i| Reverse(i.1));
+        let batches = items.chunks(PLAYLIST_REMOVE_LIMIT).len();
+
+        for (i, batch) in items.chunks(PLAYLIST_REMOVE_LIMIT).enumerate() {
+            if let Err(e) = self
+                .playlist_remove_batch(playlist_id, snapshot_id, batch)
+                .await
+            {
+                return Err(format!(
+                    "removing batch {} of {} failed, {} of {} tracks were removed: {}",
+                    i + 1,
+                    batches,
+                    i * PLAYLIST_REMOVE_LIMIT,
+                    items.len(),
+                    e
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes at most `PLAYLIST_REMOVE_LIMIT` items, given with their positions
+    /// at the snapshot.
+    async fn playlist_remove_batch(
+        &self,
+        playlist_id: &str,
+        snapshot_id: &str,
+        items: &[(String, u32)],
     ) -> Result<(), CoolioError>;
     async fn current_user_playlists(&self) -> Result<Vec<SimplePlaylist>, CoolioError>;
     async fn artist_top_tracks(&self, id: &str) -> Result<Vec<SimpleTrack>, CoolioError>;
@@ -163,9 +388,68 @@ pub trait Spotify: Send + Sync {
         album_type: &AlbumType,
     ) -> Result<Vec<SimpleAlbum>, CoolioError>;
 
-    async fn playlist(&self, id: &str) -> Result<SimplePlaylist, CoolioError>;
+    /// The playlist with the first page of its tracks, the rest can be walked
+    /// with `PlaylistItems::after`.
+    async fn playlist_info(&self, id: &str) -> Result<SimplePlaylist, CoolioError>;
+    async fn playlist_items(
+        &self,
+        id: &str,
+        offset: u32,
+    ) -> Result<SimplePage<SimplePlayable>, CoolioError>;
+    async fn tracks(&self, ids: &[String]) -> Result<Vec<SimpleTrack>, CoolioError>;
     async fn artist(&self, id: &str) -> Result<SimpleArtist, CoolioError>;
     async fn search_artists(&self, name: &str) -> Result<Vec<SimpleArtist>, CoolioError>;
+    async fn search_tracks(
+        &self,
+        name: &str,
+        artist: &str,
+    ) -> Result<Vec<SimpleTrack>, CoolioError>;
+}
+
+/// Walks the items of a playlist one page at a time, so that large playlists
+/// aren't held in memory all at once.
+pub struct PlaylistItems<'a, S: Spotify> {
+    spotify: &'a S,
+    id: &'a str,
+    page: std::vec::IntoIter<SimplePlayable>,
+    next: Option<u32>,
+}
+
+impl<'a, S: Spotify> PlaylistItems<'a, S> {
+    pub fn new(spotify: &'a S, id: &'a str) -> Self {
+        PlaylistItems {
+            spotify,
+            id,
+            page: Vec::new().into_iter(),
+            next: Some(0),
+        }
+    }
+
+    /// Walks the items of a playlist fetched with `playlist_info`, starting
+    /// with the ones it came with.
+    pub fn after(spotify: &'a S, playlist: &'a SimplePlaylist) -> Self {
+        PlaylistItems {
+            spotify,
+            id: &playlist.id,
+            page: playlist.tracks.clone().into_iter(),
+            next: playlist.next_tracks,
+        }
+    }
+
+    pub async fn next(&mut self) -> Result<Option<SimplePlayable>, CoolioError> {
+        loop {
+            if let Some(item) = self.page.next() {
+                return Ok(Some(item));
+            }
+            let offset = match self.next {
+                Some(offset) => offset,
+                None => return Ok(None),
+            };
+            let fetched = self.spotify.playlist_items(self.id, offset).await?;
+            self.page = fetched.items.into_iter();
+            self.next = fetched.next;
+        }
+    }
 }
 
 pub struct HTTPSpotify {
@@ -185,7 +469,7 @@ impl Spotify for HTTPSpotify {
         limit: u32,
         time_limit: Option<DateTime<Utc>>,
     ) -> Result<Vec<Listen>, CoolioError> {
-        let last_listen = time_limit.map(|x| TimeLimits::After(x));
+        let last_listen = time_limit.map(TimeLimits::After);
 
         Ok(self
             .spotify
@@ -193,9 +477,17 @@ impl Spotify for HTTPSpotify {
             .await?
             .items
             .into_iter()
-            .map(|x| Listen {
-                song_id: x.track.id.unwrap().uri(),
-                time: x.played_at,
+            .map(|x| {
+                let track: SimpleTrack = x.track.into();
+                Listen {
+                    song_id: track.id.clone(),
+                    time: x.played_at,
+                    ms_played: None,
+                    skipped: None,
+                    platform: None,
+                    context: x.context.map(|c| c.uri),
+                    track: Some(track.into()),
+                }
             })
             .collect::<Vec<Listen>>())
     }
@@ -209,15 +501,16 @@ impl Spotify for HTTPSpotify {
         Ok(playlist.into())
     }
 
-    async fn playlist_add_items<'a>(
+    async fn playlist_add_batch(
         &self,
         playlist_id: &str,
-        items: impl IntoIterator<Item = String> + Send + 'a,
+        items: &[String],
+        position: u32,
     ) -> Result<(), CoolioError> {
         let please_live = items
-            .into_iter()
-            .map(|x| TrackId::from_uri(&x).unwrap())
-            .collect::<Vec<TrackId>>();
+            .iter()
+            .map(|x| TrackId::from_uri(x))
+            .collect::<Result<Vec<TrackId>, _>>()?;
 
         let to_add = please_live
             .iter()
@@ -225,7 +518,52 @@ impl Spotify for HTTPSpotify {
             .collect::<Vec<&dyn PlayableId>>();
 
         self.spotify
-            .playlist_add_items(&PlaylistId::from_uri(playlist_id)?, to_add, Some(0))
+            .playlist_add_items(
+                &PlaylistId::from_uri(playlist_id)?,
+                to_add,
+                Some(position as i32),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn playlist_remove_batch(
+        &self,
+        playlist_id: &str,
+        snapshot_id: &str,
+        items: &[(String, u32)],
+    ) -> Result<(), CoolioError> {
+        // the same track may be at several of the positions
+        let mut ids = Vec::<TrackId>::new();
+        let mut positions = Vec::<Vec<u32>>::new();
+        for (id, position) in items {
+            let id = TrackId::from_uri(id)?;
+            match ids.iter().position(|x| *x == id) {
+                Some(i) => positions[i].push(*position),
+                None => {
+                    ids.push(id);
+                    positions.push(vec![*position]);
+                }
+            }
+        }
+
+        let to_remove = ids
+            .iter()
+            .zip(positions.iter())
+            .map(|(id, positions)| ItemPositions {
+                id: id as &dyn PlayableId,
+                positions,
+            })
+            .collect::<Vec<ItemPositions>>();
+
+        self.spotify
+            .playlist_remove_specific_occurrences_of_items(
+                &PlaylistId::from_uri(playlist_id)?,
+                to_remove,
+                // Spotify finds the positions in this snapshot, even if the
+                // playlist changed since
+                Some(snapshot_id),
+            )
             .await?;
         Ok(())
     }
@@ -256,7 +594,7 @@ impl Spotify for HTTPSpotify {
         Ok(playlists)
     }
 
-    async fn playlist(&self, id: &str) -> Result<SimplePlaylist, CoolioError> {
+    async fn playlist_info(&self, id: &str) -> Result<SimplePlaylist, CoolioError> {
         let p = self
             .spotify
             .playlist(&PlaylistId::from_uri(id)?, None, None)
@@ -264,6 +602,44 @@ impl Spotify for HTTPSpotify {
         Ok(p.into())
     }
 
+    async fn playlist_items(
+        &self,
+        id: &str,
+        offset: u32,
+    ) -> Result<SimplePage<SimplePlayable>, CoolioError> {
+        let fetched = self
+            .spotify
+            .playlist_items_manual(
+                &PlaylistId::from_uri(id)?,
+                None,
+                None,
+                Some(PLAYLIST_ITEMS_LIMIT),
+                Some(offset),
+            )
+            .await?;
+
+        Ok(SimplePage {
+            next: fetched.next.map(|_| offset + fetched.items.len() as u32),
+            items: playlist_tracks(fetched.items, offset),
+        })
+    }
+
+    async fn tracks(&self, ids: &[String]) -> Result<Vec<SimpleTrack>, CoolioError> {
+        let mut tracks = Vec::<SimpleTrack>::new();
+
+        for chunk in ids.chunks(TRACKS_LIMIT) {
+            let track_ids = chunk
+                .iter()
+                .map(|x| TrackId::from_uri(x))
+                .collect::<Result<Vec<TrackId>, _>>()?;
+
+            for t in self.spotify.tracks(&track_ids, None).await? {
+                tracks.push(t.into())
+            }
+        }
+        Ok(tracks)
+    }
+
     async fn artist(&self, id: &str) -> Result<SimpleArtist, CoolioError> {
         let p = self.spotify.artist(&ArtistId::from_uri(id)?).await?;
         Ok(p.into())
@@ -291,6 +667,23 @@ impl Spotify for HTTPSpotify {
         }
     }
 
+    async fn search_tracks(
+        &self,
+        name: &str,
+        artist: &str,
+    ) -> Result<Vec<SimpleTrack>, CoolioError> {
+        let query = format!("track:{} artist:{}", name, artist);
+        let r = self
+            .spotify
+            .search(&query, &SearchType::Track, None, None, Some(5), None)
+            .await?;
+
+        match r {
+            SearchResult::Tracks(t) => Ok(t.items.into_iter().map(|x| x.into()).collect()),
+            _ => unreachable!(),
+        }
+    }
+
     async fn artist_albums(
         &self,
         id: &str,
@@ -313,18 +706,23 @@ impl Spotify for HTTPSpotify {
                 .await?;
 
             for a in fetched.items {
-                if let Some(release_date) = a.release_date {
-                    if let Some("day") = a.release_date_precision.as_ref().map(|x| x.as_str()) {
-                        albums.push(SimpleAlbum {
+                if let (Some(release_date), Some(precision)) =
+                    (a.release_date, a.release_date_precision)
+                {
+                    // an album with a date that can't be read is left out
+                    // rather than failing the update of the whole artist
+                    let parsed = precision.parse::<DatePrecision>().and_then(|precision| {
+                        SimpleAlbum::parse_release_date(&release_date, precision)
+                            .map(|date| (date, precision))
+                    });
+                    match parsed {
+                        Ok((release_date, precision)) => albums.push(SimpleAlbum {
                             id: a.id.unwrap().uri(),
-                            release_date: DateTime::<Utc>::from_utc(
-                                NaiveDateTime::parse_from_str(
-                                    &(release_date + " 00:00:00"),
-                                    "%Y-%m-%d %H:%M:%S",
-                                )?,
-                                Utc,
-                            ),
-                        })
+                            name: a.name,
+                            release_date,
+                            release_precision: precision,
+                        }),
+                        Err(e) => warn!("skipping album {}: {}", a.name, e),
                     }
                 }
             }
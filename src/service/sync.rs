@@ -0,0 +1,227 @@
+use std::collections::{HashMap, HashSet};
+
+use config::{Config, File, FileFormat};
+use serde::Deserialize;
+
+use crate::error::CoolioError;
+use crate::models::{LinkEdit, LinkOptions, ReleaseType, SyncAction};
+use crate::storage::{Storage, StorageBehavior};
+
+use super::playlists::PlaylistService;
+use super::spotify::{artist_uri, SimpleArtist, Spotify};
+
+/// Automated playlists as described in a `coolio sync` file.
+#[derive(Debug, Deserialize)]
+struct SyncFile {
+    #[serde(default)]
+    playlists: Vec<SyncPlaylist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SyncPlaylist {
+    name: String,
+    #[serde(default)]
+    artists: Vec<SyncArtist>,
+}
+
+/// A linked artist, the options it leaves out get their default values.
+#[derive(Debug, Deserialize)]
+struct SyncArtist {
+    uri: String,
+    types: Option<Vec<String>>,
+    seed: Option<usize>,
+    features: Option<bool>,
+}
+
+impl SyncArtist {
+    fn options(&self) -> Result<LinkOptions, CoolioError> {
+        let release_types = match &self.types {
+            Some(types) => Some(
+                types
+                    .iter()
+                    .map(|t| t.parse())
+                    .collect::<Result<Vec<ReleaseType>, CoolioError>>()?,
+            ),
+            None => None,
+        };
+        let mut options = LinkOptions::default();
+        options.apply(&LinkEdit {
+            release_types,
+            seed: self.seed,
+            include_features: self.features,
+        });
+        Ok(options)
+    }
+}
+
+fn read_sync_file(path: &str) -> Result<SyncFile, CoolioError> {
+    let mut c = Config::new();
+    c.merge(File::new(path, FileFormat::Toml))?;
+    let file: SyncFile = c.try_into()?;
+
+    let mut names = HashSet::<&str>::new();
+    for p in &file.playlists {
+        if !names.insert(p.name.as_str()) {
+            return Err(format!("playlist {} is described more than once", p.name).into());
+        }
+    }
+    Ok(file)
+}
+
+/// Looks the artist up, each one only once while planning.
+async fn cached_artist(
+    spotify: &impl Spotify,
+    artists: &mut HashMap<String, SimpleArtist>,
+    id: &str,
+) -> Result<SimpleArtist, CoolioError> {
+    if let Some(artist) = artists.get(id) {
+        return Ok(artist.clone());
+    }
+    let artist = spotify.artist(id).await?;
+    // it can be asked for by its URI or by its id
+    artists.insert(id.to_string(), artist.clone());
+    artists.insert(artist.id.clone(), artist.clone());
+    Ok(artist)
+}
+
+pub struct SyncService {}
+
+impl SyncService {
+    /// Lists the changes that make the automated playlists match the file.
+    /// Playlists that aren't in the file are left as they are.
+    pub async fn plan(
+        &self,
+        spotify: &impl Spotify,
+        storage: &StorageBehavior,
+        path: &str,
+    ) -> Result<Vec<SyncAction>, CoolioError> {
+        let file = read_sync_file(path)?;
+        let stored = storage.get_playlists().await?;
+        // only needed when a playlist has to be automated or created
+        let mut external = None;
+        let mut artists = HashMap::<String, SimpleArtist>::new();
+
+        let mut plan = Vec::<SyncAction>::new();
+        for p in file.playlists {
+            let mut declared = Vec::<(String, String, LinkOptions)>::new();
+            for a in &p.artists {
+                let uri = artist_uri(&a.uri)
+                    .ok_or_else(|| format!("{}: {} isn't an artist URI", p.name, a.uri))?;
+                let artist = cached_artist(spotify, &mut artists, &uri).await?;
+                if declared.iter().any(|(id, _, _)| *id == artist.id) {
+                    return Err(format!("{}: {} is linked more than once", p.name, a.uri).into());
+                }
+                declared.push((artist.id, artist.name, a.options()?));
+            }
+
+            let linked = match stored.iter().find(|s| s.name == p.name) {
+                Some(s) => s,
+                None => {
+                    if external.is_none() {
+                        external = Some(spotify.current_user_playlists().await?);
+                    }
+                    let exists = external
+                        .as_ref()
+                        .is_some_and(|e| e.iter().any(|e| e.name == p.name));
+                    plan.push(if exists {
+                        SyncAction::AutomatePlaylist {
+                            playlist: p.name.clone(),
+                        }
+                    } else {
+                        SyncAction::CreatePlaylist {
+                            playlist: p.name.clone(),
+                        }
+                    });
+                    for (artist_id, artist, options) in declared {
+                        plan.push(SyncAction::Link {
+                            playlist: p.name.clone(),
+                            artist_id,
+                            artist,
+                            options,
+                        });
+                    }
+                    continue;
+                }
+            };
+
+            for (artist_id, artist, options) in &declared {
+                if !linked.artists.contains(artist_id) {
+                    plan.push(SyncAction::Link {
+                        playlist: p.name.clone(),
+                        artist_id: artist_id.clone(),
+                        artist: artist.clone(),
+                        options: options.clone(),
+                    });
+                } else if storage.get_link_options(&linked.id, artist_id).await? != *options {
+                    plan.push(SyncAction::EditLink {
+                        playlist: p.name.clone(),
+                        artist_id: artist_id.clone(),
+                        artist: artist.clone(),
+                        options: options.clone(),
+                    });
+                }
+            }
+            for artist_id in &linked.artists {
+                if !declared.iter().any(|(id, _, _)| id == artist_id) {
+                    plan.push(SyncAction::Unlink {
+                        playlist: p.name.clone(),
+                        artist_id: artist_id.clone(),
+                        artist: cached_artist(spotify, &mut artists, artist_id).await?.name,
+                    });
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Makes the planned changes, in the order they were planned.
+    pub async fn apply(
+        &self,
+        spotify: &impl Spotify,
+        storage: &StorageBehavior,
+        playlists: &PlaylistService,
+        plan: &[SyncAction],
+    ) -> Result<(), CoolioError> {
+        for action in plan {
+            match action {
+                SyncAction::CreatePlaylist { playlist } => {
+                    playlists.create(spotify, storage, playlist).await?
+                }
+                SyncAction::AutomatePlaylist { playlist } => {
+                    playlists.automate(spotify, storage, playlist).await?
+                }
+                SyncAction::Link {
+                    playlist,
+                    artist_id,
+                    options,
+                    ..
+                } => {
+                    let playlist = storage.get_playlist(playlist).await?;
+                    playlists
+                        .link_with_options(spotify, storage, &playlist, artist_id, options, true)
+                        .await?
+                }
+                SyncAction::EditLink {
+                    playlist,
+                    artist_id,
+                    options,
+                    ..
+                } => {
+                    let playlist = storage.get_playlist(playlist).await?;
+                    storage
+                        .set_link_options(&playlist.id, artist_id, options)
+                        .await?
+                }
+                SyncAction::Unlink {
+                    playlist,
+                    artist_id,
+                    ..
+                } => {
+                    let playlist = storage.get_playlist(playlist).await?;
+                    storage.unlink_artist(&playlist.id, artist_id).await?
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,27 @@
+use std::collections::HashMap;
+use std::fmt;
+
 use config::{Config, ConfigError, File};
 use serde::Deserialize;
 
+use crate::error::CoolioError;
+use crate::models::ExclusionRules;
+
 #[derive(Debug, Deserialize)]
 pub struct Spotify {
     pub client_id: String,
     pub client_secret: String,
     pub redirect_uri: String,
     pub scopes: Vec<String>,
+    #[serde(default = "default_token_cache")]
+    pub token_cache: String,
 }
 
-#[derive(Debug, Deserialize)]
+fn default_token_cache() -> String {
+    ".spotify_token_cache.json".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Database {
     pub name: String,
     pub user: String,
@@ -17,24 +29,95 @@ pub struct Database {
     pub host: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct LocalStorage {
     pub path: String,
+    #[serde(default)]
+    pub format: FileFormat,
 }
 
-#[derive(Debug, Deserialize)]
+/// How the records are encoded in the files of the filesystem storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FileFormat::Csv => "csv",
+            FileFormat::Jsonl => "jsonl",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SqliteDatabase {
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "adapter")]
 pub enum Storage {
     #[serde(rename = "psql")]
     Psql(Database),
     #[serde(rename = "fs")]
     Fs(LocalStorage),
+    #[serde(rename = "sqlite")]
+    Sqlite(SqliteDatabase),
+}
+
+/// Cron expressions (with seconds) for the jobs run by `coolio daemon`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Daemon {
+    pub history: String,
+    pub playlists: String,
+}
+
+impl Default for Daemon {
+    fn default() -> Self {
+        Daemon {
+            history: "0 */30 * * * *".to_string(),
+            playlists: "0 15 0,12 * * *".to_string(),
+        }
+    }
+}
+
+/// Limits for retrying requests that Spotify rate limited or failed to serve.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Retry {
+    pub max_time_secs: u64,
+    pub initial_delay_ms: u64,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            max_time_secs: 120,
+            initial_delay_ms: 500,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub spotify: Spotify,
     pub storage: Storage,
+    // other storages, by name, to copy from or to
+    #[serde(default)]
+    pub storages: HashMap<String, Storage>,
+    #[serde(default)]
+    pub daemon: Daemon,
+    #[serde(default)]
+    pub retry: Retry,
+    // used by the playlists that don't have rules of their own
+    #[serde(default)]
+    pub rules: ExclusionRules,
 }
 
 impl Settings {
@@ -45,4 +128,15 @@ impl Settings {
 
         s.try_into()
     }
+
+    /// The storage under `[storages.<name>]`, or the one in use for `storage`.
+    pub fn storage_section(&self, name: &str) -> Result<Storage, CoolioError> {
+        if name == "storage" {
+            return Ok(self.storage.clone());
+        }
+        self.storages
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("no storage named {} in the settings", name).into())
+    }
 }
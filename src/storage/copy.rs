@@ -0,0 +1,239 @@
+use std::collections::HashSet;
+
+use log::info;
+
+use crate::error::CoolioError;
+use crate::models::{LinkCheckpoint, LinkOptions, Listen, Playlist, Retention};
+
+use super::{Storage, StorageBehavior};
+
+// listens written at once, an interrupted copy only loses the batch it was on
+const HISTORY_BATCH: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageCommand {
+    Copy {
+        from: String,
+        to: String,
+        merge: bool,
+    },
+}
+
+/// What a copy added to the target storage.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CopySummary {
+    pub listens: usize,
+    pub gaps: usize,
+    pub playlists: usize,
+    pub links: usize,
+}
+
+/// What a link of the target should have once the copy is done.
+struct CopiedLink {
+    playlist_id: String,
+    playlist: String,
+    artist_id: String,
+    options: LinkOptions,
+    checkpoint: Option<LinkCheckpoint>,
+}
+
+/// The settings a playlist of the target should end up with: the ones it
+/// has of its own, and those of the source where it has none.
+fn merge_settings(source: &Playlist, target: Option<&Playlist>) -> Playlist {
+    let mut merged = source.clone();
+    if let Some(t) = target {
+        merged.allow_duplicates = t.allow_duplicates || source.allow_duplicates;
+        merged.rules = t.rules.clone().or_else(|| source.rules.clone());
+        merged.retention = Retention {
+            max_tracks: t.retention.max_tracks.or(source.retention.max_tracks),
+            max_age_days: t.retention.max_age_days.or(source.retention.max_age_days),
+        };
+    }
+    merged
+}
+
+/// Copies the history, gaps, playlists and links from one storage into
+/// another. The target has to be empty, unless the copy is merged into it:
+/// then only what it's missing is copied, which also picks up a copy that
+/// was interrupted where it stopped. Playlists and links the target already
+/// has keep their settings, and get the ones they are missing.
+pub async fn copy(
+    from: &StorageBehavior,
+    to: &StorageBehavior,
+    merge: bool,
+) -> Result<CopySummary, CoolioError> {
+    let target_history = to.get_history().await?;
+    let target_gaps = to.get_gaps().await?;
+    let target_playlists = to.get_playlists().await?;
+    let empty = target_history.is_empty() && target_gaps.is_empty() && target_playlists.is_empty();
+    if !empty && !merge {
+        return Err("the target storage isn't empty, use --merge to copy into it anyway".into());
+    }
+
+    let mut summary = CopySummary::default();
+
+    let mut playlists = Vec::<Playlist>::new();
+    let mut links = Vec::<CopiedLink>::new();
+    for p in from.get_playlists().await? {
+        let existing = target_playlists.iter().find(|t| t.id == p.id);
+        let merged = merge_settings(&p, existing);
+        let current = match existing {
+            Some(t) => t.clone(),
+            None => {
+                to.create_playlist(&p.id, &p.name).await?;
+                summary.playlists += 1;
+                Playlist::default()
+            }
+        };
+        if current.allow_duplicates != merged.allow_duplicates {
+            to.set_allow_duplicates(&p.id, merged.allow_duplicates)
+                .await?;
+        }
+        if current.rules != merged.rules {
+            to.set_rules(&p.id, merged.rules.as_ref()).await?;
+        }
+        if current.retention != merged.retention {
+            to.set_retention(&p.id, &merged.retention).await?;
+        }
+
+        for artist_id in &p.artists {
+            let mut link = CopiedLink {
+                playlist_id: p.id.clone(),
+                playlist: p.name.clone(),
+                artist_id: artist_id.clone(),
+                options: from.get_link_options(&p.id, artist_id).await?,
+                checkpoint: from.get_checkpoint(&p.id, artist_id).await?,
+            };
+            if existing.is_some_and(|t| t.artists.contains(artist_id)) {
+                // a link without options of its own has the default ones
+                let options = to.get_link_options(&p.id, artist_id).await?;
+                if options != LinkOptions::default() {
+                    link.options = options;
+                } else if link.options != options {
+                    to.set_link_options(&p.id, artist_id, &link.options).await?;
+                }
+                match to.get_checkpoint(&p.id, artist_id).await? {
+                    Some(checkpoint) => link.checkpoint = Some(checkpoint),
+                    None => {
+                        if let Some(checkpoint) = &link.checkpoint {
+                            to.set_checkpoint(&p.id, artist_id, checkpoint).await?;
+                        }
+                    }
+                }
+            } else {
+                to.link_artist(&p.id, &p.name, artist_id, &link.options)
+                    .await?;
+                if let Some(checkpoint) = &link.checkpoint {
+                    to.set_checkpoint(&p.id, artist_id, checkpoint).await?;
+                }
+                summary.links += 1;
+            }
+            links.push(link);
+        }
+        playlists.push(merged);
+    }
+
+    for gap in from.get_gaps().await? {
+        if !target_gaps.contains(&gap) {
+            to.add_gap(gap).await?;
+            summary.gaps += 1;
+        }
+    }
+
+    let mut copied = target_history
+        .iter()
+        .map(Listen::key)
+        .collect::<HashSet<_>>();
+    let mut missing = from
+        .get_history()
+        .await?
+        .into_iter()
+        .filter(|l| copied.insert(l.key()))
+        .collect::<Vec<Listen>>();
+    missing.sort_by_key(|l| l.time);
+    for batch in missing.chunks(HISTORY_BATCH) {
+        to.add_history_batch(batch.to_vec()).await?;
+        summary.listens += batch.len();
+        info!("copied {} of {} listens", summary.listens, missing.len());
+    }
+
+    verify(
+        to,
+        &playlists,
+        &links,
+        target_history.len() + summary.listens,
+        target_gaps.len() + summary.gaps,
+    )
+    .await?;
+    Ok(summary)
+}
+
+/// Checks that the target ended up with everything that was copied.
+async fn verify(
+    to: &StorageBehavior,
+    playlists: &[Playlist],
+    links: &[CopiedLink],
+    listens: usize,
+    gaps: usize,
+) -> Result<(), CoolioError> {
+    let copied_listens = to.get_history().await?.len();
+    if copied_listens != listens {
+        return Err(format!(
+            "copy verification failed: the target has {} listens instead of {}",
+            copied_listens, listens
+        )
+        .into());
+    }
+
+    let copied_gaps = to.get_gaps().await?.len();
+    if copied_gaps != gaps {
+        return Err(format!(
+            "copy verification failed: the target has {} gaps instead of {}",
+            copied_gaps, gaps
+        )
+        .into());
+    }
+
+    let copied_playlists = to.get_playlists().await?;
+    for p in playlists {
+        let copied = copied_playlists
+            .iter()
+            .find(|c| c.id == p.id)
+            .ok_or_else(|| format!("copy verification failed: playlist {} is missing", p.name))?;
+        if let Some(artist) = p.artists.iter().find(|a| !copied.artists.contains(a)) {
+            return Err(format!(
+                "copy verification failed: artist {} isn't linked to playlist {}",
+                artist, p.name
+            )
+            .into());
+        }
+        if copied.allow_duplicates != p.allow_duplicates
+            || copied.rules != p.rules
+            || copied.retention != p.retention
+        {
+            return Err(format!(
+                "copy verification failed: the settings of playlist {} weren't copied",
+                p.name
+            )
+            .into());
+        }
+    }
+
+    for l in links {
+        let options = to.get_link_options(&l.playlist_id, &l.artist_id).await?;
+        let checkpoint = to.get_checkpoint(&l.playlist_id, &l.artist_id).await?;
+        // the target may keep the time of the last check less precisely
+        let same_checkpoint = match (&checkpoint, &l.checkpoint) {
+            (Some(c), Some(expected)) => c.albums == expected.albums,
+            (c, expected) => c.is_none() && expected.is_none(),
+        };
+        if options != l.options || !same_checkpoint {
+            return Err(format!(
+                "copy verification failed: the link of artist {} to playlist {} wasn't copied",
+                l.artist_id, l.playlist
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
@@ -1,60 +1,185 @@
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use csv::{Reader, Writer};
-use serde::{Deserialize, Serialize};
+use fs2::FileExt;
+use log::{info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+use std::collections::HashMap;
 use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
-use crate::models::{Listen, Playlist};
-use crate::{error::CoolioError, settings::LocalStorage};
+use crate::error::CoolioError;
+use crate::models::{
+    ExclusionRules, Gap, LinkCheckpoint, LinkOptions, Listen, Playlist, ReleaseType, Retention,
+    TrackMetadata,
+};
+use crate::settings::{FileFormat, LocalStorage};
 
-use super::Storage;
+use super::{merge_listens, Storage};
 
+// version 1 was csv without a header, its columns known by their position
+const FORMAT_VERSION: u32 = 2;
+const HEADER_MAGIC: &str = "#coolio";
+
+#[derive(Clone, Copy)]
 enum StorageFile {
     History,
+    Gaps,
     Playlist,
     Links,
 }
 
+impl StorageFile {
+    fn name(&self) -> &'static str {
+        match self {
+            StorageFile::History => "history",
+            StorageFile::Gaps => "gaps",
+            StorageFile::Playlist => "playlist",
+            StorageFile::Links => "links",
+        }
+    }
+}
+
+/// The first line of every file, `#coolio <file> v<version> <format>`. Csv
+/// files follow it with a row naming their columns.
+struct Header {
+    version: u32,
+    format: FileFormat,
+    // length of the line, with its line break, zero for files without one
+    len: usize,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct ListenRecord {
     song_id: String,
     time: DateTime<Utc>,
+    #[serde(default)]
+    ms_played: Option<u32>,
+    #[serde(default)]
+    skipped: Option<bool>,
+    #[serde(default)]
+    platform: Option<String>,
+    #[serde(default)]
+    context: Option<String>,
+    #[serde(default)]
+    track_name: Option<String>,
+    // lists of artists are kept as json arrays in a single column
+    #[serde(default)]
+    artist_ids: Option<String>,
+    #[serde(default)]
+    artist_names: Option<String>,
+    #[serde(default)]
+    album_id: Option<String>,
+    #[serde(default)]
+    album_name: Option<String>,
+    #[serde(default)]
+    duration_ms: Option<u32>,
 }
 
 impl From<Listen> for ListenRecord {
     fn from(l: Listen) -> Self {
-        ListenRecord {
+        let mut record = ListenRecord {
             song_id: l.song_id,
             time: l.time,
+            ms_played: l.ms_played,
+            skipped: l.skipped,
+            platform: l.platform,
+            context: l.context,
+            track_name: None,
+            artist_ids: None,
+            artist_names: None,
+            album_id: None,
+            album_name: None,
+            duration_ms: None,
+        };
+        if let Some(t) = l.track {
+            record.track_name = Some(t.name);
+            record.artist_ids = serde_json::to_string(&t.artist_ids).ok();
+            record.artist_names = serde_json::to_string(&t.artist_names).ok();
+            record.album_id = t.album_id;
+            record.album_name = t.album_name;
+            record.duration_ms = Some(t.duration_ms);
         }
+        record
     }
 }
 
-impl Into<Listen> for ListenRecord {
-    fn into(self) -> Listen {
+impl From<ListenRecord> for Listen {
+    fn from(record: ListenRecord) -> Self {
+        let parse = |x: Option<String>| x.and_then(|x| serde_json::from_str(&x).ok());
+        // unreadable metadata is treated as missing, so it gets backfilled again
+        let track = match (
+            record.track_name,
+            parse(record.artist_ids),
+            parse(record.artist_names),
+            record.duration_ms,
+        ) {
+            (Some(name), Some(artist_ids), Some(artist_names), Some(duration_ms)) => {
+                Some(TrackMetadata {
+                    name,
+                    artist_ids,
+                    artist_names,
+                    album_id: record.album_id,
+                    album_name: record.album_name,
+                    duration_ms,
+                })
+            }
+            _ => None,
+        };
+
         Listen {
-            song_id: self.song_id,
-            time: self.time,
+            song_id: record.song_id,
+            time: record.time,
+            ms_played: record.ms_played,
+            skipped: record.skipped,
+            platform: record.platform,
+            context: record.context,
+            track,
         }
     }
 }
 
+#[derive(Serialize, Deserialize)]
+struct GapRecord {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
 #[derive(Serialize, Deserialize)]
 struct PlaylistRecord {
     id: String,
     name: String,
+    #[serde(default)]
+    allow_duplicates: bool,
+    // json of the exclusion rules, none for the defaults
+    #[serde(default)]
+    rules: Option<String>,
+    #[serde(default)]
+    max_tracks: Option<usize>,
+    #[serde(default)]
+    max_age_days: Option<u32>,
 }
 
-impl Into<Playlist> for PlaylistRecord {
-    fn into(self) -> Playlist {
-        Playlist {
+impl TryInto<Playlist> for PlaylistRecord {
+    type Error = CoolioError;
+
+    fn try_into(self) -> Result<Playlist, Self::Error> {
+        Ok(Playlist {
             id: self.id,
             name: self.name,
             artists: vec![],
             automated: true,
-        }
+            allow_duplicates: self.allow_duplicates,
+            rules: match self.rules {
+                Some(rules) => Some(serde_json::from_str(&rules)?),
+                None => None,
+            },
+            retention: Retention {
+                max_tracks: self.max_tracks,
+                max_age_days: self.max_age_days,
+            },
+        })
     }
 }
 
@@ -62,137 +187,552 @@ impl Into<Playlist> for PlaylistRecord {
 struct LinkRecord {
     playlist_id: String,
     artist_id: String,
+    #[serde(default)]
+    last_checked: Option<DateTime<Utc>>,
+    // json array of the checked album ids
+    #[serde(default)]
+    checked_albums: Option<String>,
+    // comma separated release types
+    #[serde(default)]
+    release_types: Option<String>,
+    #[serde(default)]
+    seed: Option<usize>,
+    #[serde(default)]
+    include_features: Option<bool>,
+}
+
+impl LinkRecord {
+    fn options(&self) -> Result<LinkOptions, CoolioError> {
+        let mut options = LinkOptions::default();
+        if let Some(types) = &self.release_types {
+            options.release_types = ReleaseType::parse_list(types)?;
+        }
+        if let Some(seed) = self.seed {
+            options.seed = seed;
+        }
+        if let Some(features) = self.include_features {
+            options.include_features = features;
+        }
+        Ok(options)
+    }
+
+    fn set_options(&mut self, options: &LinkOptions) {
+        let types = options
+            .release_types
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>();
+        self.release_types = Some(types.join(","));
+        self.seed = Some(options.seed);
+        self.include_features = Some(options.include_features);
+    }
 }
 
 pub struct Fs {
     path: String,
+    format: FileFormat,
+}
+
+/// The advisory lock on the storage directory, released when dropped.
+struct FsLock(fs::File);
+
+impl Drop for FsLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
 }
 
 impl Fs {
     pub async fn new(conf: LocalStorage) -> Result<Self, CoolioError> {
-        Ok(Fs { path: conf.path })
+        let st = Fs {
+            path: conf.path,
+            format: conf.format,
+        };
+        st.upgrade()?;
+        Ok(st)
     }
 
-    fn get_path(&self, sf: StorageFile) -> PathBuf {
-        Path::new(&self.path).join(match sf {
-            StorageFile::History => "history",
-            StorageFile::Playlist => "playlist",
-            StorageFile::Links => "links",
-        })
+    /// Rewrites the files that are in an older format version, or in another
+    /// format than the one in the settings, in the current one.
+    fn upgrade(&self) -> Result<(), CoolioError> {
+        let _lock = self.lock(true)?;
+        self.upgrade_file::<ListenRecord>(StorageFile::History)?;
+        self.upgrade_file::<GapRecord>(StorageFile::Gaps)?;
+        self.upgrade_file::<PlaylistRecord>(StorageFile::Playlist)?;
+        self.upgrade_file::<LinkRecord>(StorageFile::Links)
     }
 
-    fn get_writer(&self, sf: StorageFile, append: bool) -> Result<Writer<fs::File>, CoolioError> {
+    fn upgrade_file<T: Serialize + DeserializeOwned>(
+        &self,
+        sf: StorageFile,
+    ) -> Result<(), CoolioError> {
+        let path = self.get_path(sf);
+        let file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        // the header is all it takes to know whether the file is up to date
+        let mut line = Vec::new();
+        BufReader::new(file).read_until(b'\n', &mut line)?;
+        let header = parse_header(sf, &line[..complete_len(&line)])?;
+        if header.version == FORMAT_VERSION && header.format == self.format {
+            return Ok(());
+        }
+
+        let records = self.read::<T>(sf)?;
+        info!(
+            "upgrading {} to format version {} in {}",
+            path.display(),
+            FORMAT_VERSION,
+            self.format
+        );
+        self.rewrite(sf, &records)
+    }
+
+    /// Locks the storage against the other coolio processes using it: shared
+    /// for reading, exclusive for anything that writes.
+    fn lock(&self, exclusive: bool) -> Result<FsLock, CoolioError> {
         let file = fs::OpenOptions::new()
             .create(true)
             .write(true)
-            .truncate(!append)
-            .append(append)
-            .open(self.get_path(sf))?;
+            .truncate(false)
+            .open(Path::new(&self.path).join(".lock"))?;
+        if exclusive {
+            file.lock_exclusive()?;
+        } else {
+            file.lock_shared()?;
+        }
+        Ok(FsLock(file))
+    }
 
-        Ok(csv::WriterBuilder::new()
-            .has_headers(false)
-            .from_writer(file))
+    /// Rewrites the playlist file with a change to one of the playlists.
+    fn update_playlist<F: FnOnce(&mut PlaylistRecord)>(
+        &self,
+        playlist_id: &str,
+        change: F,
+    ) -> Result<(), CoolioError> {
+        let _lock = self.lock(true)?;
+        let mut playlists = self.read::<PlaylistRecord>(StorageFile::Playlist)?;
+        let playlist = playlists
+            .iter_mut()
+            .find(|p| p.id == playlist_id)
+            .ok_or("playlist doesn't exist")?;
+        change(playlist);
+
+        self.rewrite(StorageFile::Playlist, &playlists)
     }
 
-    fn get_reader(&self, sf: StorageFile) -> Result<Reader<fs::File>, CoolioError> {
-        let file = fs::OpenOptions::new()
+    /// Rewrites the links file with a change to one of the links.
+    fn update_link<F: FnOnce(&mut LinkRecord)>(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+        change: F,
+    ) -> Result<(), CoolioError> {
+        let _lock = self.lock(true)?;
+        let mut links = self.read::<LinkRecord>(StorageFile::Links)?;
+        let link = links
+            .iter_mut()
+            .find(|l| l.playlist_id == playlist_id && l.artist_id == artist_id)
+            .ok_or("artist not linked to playlist")?;
+        change(link);
+
+        self.rewrite(StorageFile::Links, &links)
+    }
+
+    fn get_path(&self, sf: StorageFile) -> PathBuf {
+        Path::new(&self.path).join(sf.name())
+    }
+
+    /// Reads all the records of a file. A record that was cut short by a
+    /// crash while appending it is the last line, without its line break:
+    /// it is left out.
+    fn read<T: DeserializeOwned>(&self, sf: StorageFile) -> Result<Vec<T>, CoolioError> {
+        let path = self.get_path(sf);
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        let complete = complete_len(&content);
+        if complete < content.len() {
+            warn!(
+                "ignoring a partially written record at the end of {}",
+                path.display()
+            );
+        }
+
+        let content = &content[..complete];
+        let header = parse_header(sf, content)?;
+        let body = &content[header.len..];
+        match (header.version, header.format) {
+            (1, _) => read_csv(body, false),
+            (_, FileFormat::Csv) => read_csv(body, true),
+            (_, FileFormat::Jsonl) => read_jsonl(body),
+        }
+    }
+
+    /// Appends records to a file, dropping a partially written record left at
+    /// its end first so the new ones don't get glued to it.
+    fn append<T: Serialize>(&self, sf: StorageFile, records: &[T]) -> Result<(), CoolioError> {
+        let mut file = fs::OpenOptions::new()
             .create(true)
-            .write(true)
             .read(true)
+            .write(true)
+            .truncate(false)
             .open(self.get_path(sf))?;
-        Ok(csv::ReaderBuilder::new()
-            .has_headers(false)
-            .from_reader(file))
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        let complete = complete_len(&content);
+        if complete < content.len() {
+            file.set_len(complete as u64)?;
+        }
+
+        let mut buf = Vec::new();
+        let header = parse_header(sf, &content[..complete])?;
+        let (format, columns) = if complete == 0 {
+            buf.extend(header_line(sf, self.format).as_bytes());
+            (self.format, true)
+        } else if header.version < FORMAT_VERSION {
+            return Err(format!(
+                "{} is in format version {}, it has to be upgraded first",
+                self.get_path(sf).display(),
+                header.version
+            )
+            .into());
+        } else {
+            (header.format, complete == header.len)
+        };
+        buf.extend(serialize(records, format, columns)?);
+
+        file.seek(SeekFrom::Start(complete as u64))?;
+        file.write_all(&buf)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Replaces a file with the given records. They are written to a temporary
+    /// file first, which is then renamed over the old one, so a crash leaves
+    /// either the old or the new content behind and never a truncated file.
+    fn rewrite<T: Serialize>(&self, sf: StorageFile, records: &[T]) -> Result<(), CoolioError> {
+        let path = self.get_path(sf);
+        let tmp = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(header_line(sf, self.format).as_bytes())?;
+        file.write_all(&serialize(records, self.format, true)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        // the rename itself only survives a crash once the directory is synced
+        fs::File::open(&self.path)?.sync_all()?;
+        Ok(())
+    }
+
+    fn write_history(&self, history: Vec<Listen>) -> Result<(), CoolioError> {
+        let records = history
+            .into_iter()
+            .map(ListenRecord::from)
+            .collect::<Vec<_>>();
+        self.rewrite(StorageFile::History, &records)
+    }
+
+    fn read_history(&self) -> Result<Vec<Listen>, CoolioError> {
+        Ok(self
+            .read::<ListenRecord>(StorageFile::History)?
+            .into_iter()
+            .map(|l| l.into())
+            .collect())
+    }
+
+    fn read_playlists(&self) -> Result<Vec<Playlist>, CoolioError> {
+        let mut playlists = Vec::<Playlist>::new();
+        for record in self.read::<PlaylistRecord>(StorageFile::Playlist)? {
+            playlists.push(record.try_into()?);
+        }
+
+        for link in self.read::<LinkRecord>(StorageFile::Links)? {
+            if let Some(p) = playlists.iter_mut().find(|p| p.id == link.playlist_id) {
+                p.artists.push(link.artist_id);
+            }
+        }
+        Ok(playlists)
     }
+
+    fn find_link(&self, playlist_id: &str, artist_id: &str) -> Result<LinkRecord, CoolioError> {
+        let _lock = self.lock(false)?;
+        self.read::<LinkRecord>(StorageFile::Links)?
+            .into_iter()
+            .find(|l| l.playlist_id == playlist_id && l.artist_id == artist_id)
+            .ok_or_else(|| "artist not linked to playlist".into())
+    }
+}
+
+/// Length of the content up to the end of its last complete line.
+fn complete_len(content: &[u8]) -> usize {
+    content
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map_or(0, |i| i + 1)
+}
+
+fn header_line(sf: StorageFile, format: FileFormat) -> String {
+    format!(
+        "{} {} v{} {}\n",
+        HEADER_MAGIC,
+        sf.name(),
+        FORMAT_VERSION,
+        format
+    )
+}
+
+/// Reads the header of a file. Files written before there was one are in
+/// version 1.
+fn parse_header(sf: StorageFile, content: &[u8]) -> Result<Header, CoolioError> {
+    if !content.starts_with(HEADER_MAGIC.as_bytes()) {
+        return Ok(Header {
+            version: 1,
+            format: FileFormat::Csv,
+            len: 0,
+        });
+    }
+    let len = content
+        .iter()
+        .position(|b| *b == b'\n')
+        .map_or(content.len(), |i| i + 1);
+    let line = String::from_utf8_lossy(&content[..len]);
+    let invalid = || format!("invalid {} file header: {}", sf.name(), line.trim_end());
+
+    let parts = line.split_whitespace().collect::<Vec<_>>();
+    let (name, version, format) = match parts[..] {
+        [_, name, version, format] => (name, version, format),
+        _ => return Err(invalid().into()),
+    };
+    if name != sf.name() {
+        return Err(invalid().into());
+    }
+    let version = version
+        .strip_prefix('v')
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|v| *v > 1)
+        .ok_or_else(invalid)?;
+    if version > FORMAT_VERSION {
+        return Err(format!(
+            "the {} file is in format version {}, which is newer than this coolio knows",
+            sf.name(),
+            version
+        )
+        .into());
+    }
+    let format = match format {
+        "csv" => FileFormat::Csv,
+        "jsonl" => FileFormat::Jsonl,
+        _ => return Err(invalid().into()),
+    };
+    Ok(Header {
+        version,
+        format,
+        len,
+    })
+}
+
+/// Reads csv records, by the names of their columns or, for files without a
+/// header, by their position.
+fn read_csv<T: DeserializeOwned>(content: &[u8], columns: bool) -> Result<Vec<T>, CoolioError> {
+    // records written before the optional columns were added are shorter
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(columns)
+        .flexible(true)
+        .from_reader(content);
+    let mut records = Vec::<T>::new();
+    for record in rdr.deserialize() {
+        records.push(record?);
+    }
+    Ok(records)
+}
+
+/// Reads json records, one per line.
+fn read_jsonl<T: DeserializeOwned>(content: &[u8]) -> Result<Vec<T>, CoolioError> {
+    let mut records = Vec::<T>::new();
+    for line in content.split(|b| *b == b'\n') {
+        if !line.is_empty() {
+            records.push(serde_json::from_slice(line)?);
+        }
+    }
+    Ok(records)
+}
+
+/// Encodes records in a format, starting csv with the row of column names when
+/// `columns` is set.
+fn serialize<T: Serialize>(
+    records: &[T],
+    format: FileFormat,
+    columns: bool,
+) -> Result<Vec<u8>, CoolioError> {
+    let mut buf = Vec::new();
+    match format {
+        FileFormat::Csv => {
+            let mut wtr = csv::WriterBuilder::new()
+                .has_headers(columns)
+                .from_writer(&mut buf);
+            for record in records {
+                wtr.serialize(record)?;
+            }
+            wtr.flush()?;
+        }
+        FileFormat::Jsonl => {
+            for record in records {
+                serde_json::to_writer(&mut buf, record)?;
+                buf.push(b'\n');
+            }
+        }
+    }
+    Ok(buf)
 }
 
 #[async_trait]
 impl Storage for Fs {
-    async fn add_history(&self, listen: Listen) -> Result<(), CoolioError> {
-        let mut wtr = self.get_writer(StorageFile::History, true)?;
-        wtr.serialize(&ListenRecord::from(listen))?;
-        wtr.flush()?;
-        Ok(())
+    async fn add_history_batch(&self, listens: Vec<Listen>) -> Result<(), CoolioError> {
+        let _lock = self.lock(true)?;
+        let mut history = self.read_history()?;
+        let existing = history.len();
+        if merge_listens(&mut history, listens) {
+            return self.write_history(history);
+        }
+
+        // nothing recorded changed, so only the new listens are appended
+        let records = history
+            .split_off(existing)
+            .into_iter()
+            .map(ListenRecord::from)
+            .collect::<Vec<_>>();
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.append(StorageFile::History, &records)
     }
 
     async fn get_history(&self) -> Result<Vec<Listen>, CoolioError> {
-        let mut rdr = self.get_reader(StorageFile::History)?;
-        let mut history = Vec::<Listen>::new();
-        for record in rdr.deserialize() {
-            let l: ListenRecord = record?;
-            history.push(l.into());
+        let _lock = self.lock(false)?;
+        self.read_history()
+    }
+
+    async fn dedupe_history(&self) -> Result<usize, CoolioError> {
+        let _lock = self.lock(true)?;
+        let history = self.read_history()?;
+        let before = history.len();
+        let mut deduped = Vec::<Listen>::new();
+        merge_listens(&mut deduped, history);
+
+        let removed = before - deduped.len();
+        if removed > 0 {
+            self.write_history(deduped)?;
         }
-        Ok(history)
+        Ok(removed)
     }
 
-    async fn get_last_listen(&self) -> Result<Listen, CoolioError> {
-        let mut rdr = self.get_reader(StorageFile::History)?;
-        let mut listen: Option<ListenRecord> = None;
-        for record in rdr.deserialize::<ListenRecord>() {
-            let r = record?;
-            if let Some(l) = listen.as_ref() {
-                if l.time < r.time {
-                    listen = Some(r);
-                }
-            } else {
-                listen = Some(r);
+    async fn set_track_metadata(
+        &self,
+        tracks: &HashMap<String, TrackMetadata>,
+    ) -> Result<(), CoolioError> {
+        let _lock = self.lock(true)?;
+        let mut history = self.read_history()?;
+        for l in history.iter_mut() {
+            if l.track.is_none() {
+                l.track = tracks.get(&l.song_id).cloned();
             }
         }
 
-        match listen {
-            None => Err("no listen history".into()),
-            Some(l) => Ok(l.into()),
-        }
+        self.write_history(history)
+    }
+
+    async fn get_last_listen(&self) -> Result<Listen, CoolioError> {
+        self.get_history()
+            .await?
+            .into_iter()
+            .reduce(|last, l| if last.time < l.time { l } else { last })
+            .ok_or_else(|| "no listen history".into())
+    }
+
+    async fn add_gap(&self, gap: Gap) -> Result<(), CoolioError> {
+        let _lock = self.lock(true)?;
+        self.append(
+            StorageFile::Gaps,
+            &[GapRecord {
+                from: gap.from,
+                to: gap.to,
+            }],
+        )
+    }
+
+    async fn get_gaps(&self) -> Result<Vec<Gap>, CoolioError> {
+        let _lock = self.lock(false)?;
+        Ok(self
+            .read::<GapRecord>(StorageFile::Gaps)?
+            .into_iter()
+            .map(|g| Gap {
+                from: g.from,
+                to: g.to,
+            })
+            .collect())
     }
 
     async fn create_playlist(&self, id: &str, name: &str) -> Result<(), CoolioError> {
-        let mut wtr = self.get_writer(StorageFile::Playlist, true)?;
-        wtr.serialize(&PlaylistRecord {
-            id: id.to_string(),
-            name: name.to_string(),
-        })?;
-        wtr.flush()?;
-        Ok(())
+        let _lock = self.lock(true)?;
+        self.append(
+            StorageFile::Playlist,
+            &[PlaylistRecord {
+                id: id.to_string(),
+                name: name.to_string(),
+                allow_duplicates: false,
+                rules: None,
+                max_tracks: None,
+                max_age_days: None,
+            }],
+        )
     }
 
     async fn get_playlists(&self) -> Result<Vec<Playlist>, CoolioError> {
-        let mut rdr = self.get_reader(StorageFile::Playlist)?;
-        let mut playlists = Vec::<Playlist>::new();
-        for record in rdr.deserialize::<PlaylistRecord>() {
-            playlists.push(record?.into());
-        }
-
-        rdr = self.get_reader(StorageFile::Links)?;
-        for record in rdr.deserialize() {
-            let link: LinkRecord = record?;
-            for p in playlists.iter_mut() {
-                if p.id == link.playlist_id {
-                    p.artists.push(link.artist_id);
-                    break;
-                }
-            }
-        }
-
-        Ok(playlists)
+        let _lock = self.lock(false)?;
+        self.read_playlists()
     }
 
     async fn get_playlist(&self, name: &str) -> Result<Playlist, CoolioError> {
-        let mut rdr = self.get_reader(StorageFile::Playlist)?;
-        for record in rdr.deserialize() {
-            let playlist: PlaylistRecord = record?;
-            if playlist.name == name {
-                let mut playlist: Playlist = playlist.into();
-                let mut link_rdr = self.get_reader(StorageFile::Links)?;
-                for link_rec in link_rdr.deserialize() {
-                    let link: LinkRecord = link_rec?;
-                    if link.playlist_id == playlist.id {
-                        playlist.artists.push(link.artist_id);
-                    }
-                }
-                return Ok(playlist);
-            }
-        }
-        Err("playlist doesn't exist".into())
+        let _lock = self.lock(false)?;
+        self.read_playlists()?
+            .into_iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| "playlist doesn't exist".into())
+    }
+
+    async fn set_allow_duplicates(
+        &self,
+        playlist_id: &str,
+        allow: bool,
+    ) -> Result<(), CoolioError> {
+        self.update_playlist(playlist_id, |p| p.allow_duplicates = allow)
+    }
+
+    async fn set_rules(
+        &self,
+        playlist_id: &str,
+        rules: Option<&ExclusionRules>,
+    ) -> Result<(), CoolioError> {
+        let rules = match rules {
+            Some(rules) => Some(serde_json::to_string(rules)?),
+            None => None,
+        };
+        self.update_playlist(playlist_id, |p| p.rules = rules)
+    }
+
+    async fn set_retention(
+        &self,
+        playlist_id: &str,
+        retention: &Retention,
+    ) -> Result<(), CoolioError> {
+        self.update_playlist(playlist_id, |p| {
+            p.max_tracks = retention.max_tracks;
+            p.max_age_days = retention.max_age_days;
+        })
     }
 
     async fn link_artist(
@@ -200,31 +740,77 @@ impl Storage for Fs {
         playlist_id: &str,
         _playlist_name: &str,
         artist_id: &str,
+        options: &LinkOptions,
     ) -> Result<(), CoolioError> {
-        let mut wtr = self.get_writer(StorageFile::Links, true)?;
-        wtr.serialize(&LinkRecord {
+        let mut link = LinkRecord {
             playlist_id: playlist_id.to_string(),
             artist_id: artist_id.to_string(),
-        })?;
-        wtr.flush()?;
-        Ok(())
+            last_checked: None,
+            checked_albums: None,
+            release_types: None,
+            seed: None,
+            include_features: None,
+        };
+        link.set_options(options);
+        let _lock = self.lock(true)?;
+        self.append(StorageFile::Links, &[link])
     }
 
     async fn unlink_artist(&self, playlist_id: &str, artist_id: &str) -> Result<(), CoolioError> {
-        let mut links = Vec::<LinkRecord>::new();
-        let mut rdr = self.get_reader(StorageFile::Links)?;
-        for record in rdr.deserialize() {
-            let link: LinkRecord = record?;
-            if link.playlist_id != playlist_id || link.artist_id != artist_id {
-                links.push(link);
-            }
-        }
+        let _lock = self.lock(true)?;
+        let links = self
+            .read::<LinkRecord>(StorageFile::Links)?
+            .into_iter()
+            .filter(|l| l.playlist_id != playlist_id || l.artist_id != artist_id)
+            .collect::<Vec<_>>();
+        self.rewrite(StorageFile::Links, &links)
+    }
 
-        let mut wtr = self.get_writer(StorageFile::Links, false)?;
-        for link in links {
-            wtr.serialize(&link)?;
-        }
-        wtr.flush()?;
-        Ok(())
+    async fn get_checkpoint(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+    ) -> Result<Option<LinkCheckpoint>, CoolioError> {
+        let link = self.find_link(playlist_id, artist_id)?;
+        Ok(match link.last_checked {
+            Some(last_checked) => Some(LinkCheckpoint {
+                last_checked,
+                albums: match link.checked_albums {
+                    Some(albums) => serde_json::from_str(&albums)?,
+                    None => vec![],
+                },
+            }),
+            None => None,
+        })
+    }
+
+    async fn set_checkpoint(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+        checkpoint: &LinkCheckpoint,
+    ) -> Result<(), CoolioError> {
+        let albums = serde_json::to_string(&checkpoint.albums)?;
+        self.update_link(playlist_id, artist_id, |link| {
+            link.last_checked = Some(checkpoint.last_checked);
+            link.checked_albums = Some(albums);
+        })
+    }
+
+    async fn get_link_options(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+    ) -> Result<LinkOptions, CoolioError> {
+        self.find_link(playlist_id, artist_id)?.options()
+    }
+
+    async fn set_link_options(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+        options: &LinkOptions,
+    ) -> Result<(), CoolioError> {
+        self.update_link(playlist_id, artist_id, |link| link.set_options(options))
     }
 }
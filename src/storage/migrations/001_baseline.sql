@@ -0,0 +1,58 @@
+-- the schema as it was set up by hand, every statement can run again on
+-- databases that already have some of it
+CREATE TABLE IF NOT EXISTS listen(
+    song_id TEXT,
+    time TIMESTAMP WITH TIME ZONE,
+    ms_played BIGINT,
+    skipped BOOLEAN,
+    platform TEXT,
+    context TEXT,
+    track_name TEXT,
+    artist_ids TEXT[],
+    artist_names TEXT[],
+    album_id TEXT,
+    album_name TEXT,
+    duration_ms BIGINT
+);
+
+ALTER TABLE listen ADD COLUMN IF NOT EXISTS ms_played BIGINT;
+ALTER TABLE listen ADD COLUMN IF NOT EXISTS skipped BOOLEAN;
+ALTER TABLE listen ADD COLUMN IF NOT EXISTS platform TEXT;
+ALTER TABLE listen ADD COLUMN IF NOT EXISTS context TEXT;
+ALTER TABLE listen ADD COLUMN IF NOT EXISTS track_name TEXT;
+ALTER TABLE listen ADD COLUMN IF NOT EXISTS artist_ids TEXT[];
+ALTER TABLE listen ADD COLUMN IF NOT EXISTS artist_names TEXT[];
+ALTER TABLE listen ADD COLUMN IF NOT EXISTS album_id TEXT;
+ALTER TABLE listen ADD COLUMN IF NOT EXISTS album_name TEXT;
+ALTER TABLE listen ADD COLUMN IF NOT EXISTS duration_ms BIGINT;
+
+CREATE TABLE IF NOT EXISTS gap(
+    from_time TIMESTAMP WITH TIME ZONE,
+    to_time TIMESTAMP WITH TIME ZONE
+);
+
+CREATE TABLE IF NOT EXISTS playlist(
+    playlist_name TEXT,
+    playlist_id TEXT,
+    artist_id TEXT,
+    UNIQUE (playlist_id, artist_id)
+);
+
+-- the playlist allows duplicates if any of its rows does
+ALTER TABLE playlist ADD COLUMN IF NOT EXISTS allow_duplicates BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- checkpoints of the linked artists, the rows without an artist don't have them
+ALTER TABLE playlist ADD COLUMN IF NOT EXISTS last_checked TIMESTAMP WITH TIME ZONE;
+ALTER TABLE playlist ADD COLUMN IF NOT EXISTS checked_albums TEXT[];
+
+-- options of the linked artists, missing ones fall back to the defaults
+ALTER TABLE playlist ADD COLUMN IF NOT EXISTS release_types TEXT[];
+ALTER TABLE playlist ADD COLUMN IF NOT EXISTS seed INT;
+ALTER TABLE playlist ADD COLUMN IF NOT EXISTS include_features BOOLEAN;
+
+-- json of the exclusion rules, the playlist uses the default ones without them
+ALTER TABLE playlist ADD COLUMN IF NOT EXISTS rules TEXT;
+
+-- retention of the playlist, tracks past it are removed on updates
+ALTER TABLE playlist ADD COLUMN IF NOT EXISTS max_tracks INT;
+ALTER TABLE playlist ADD COLUMN IF NOT EXISTS max_age_days INT;
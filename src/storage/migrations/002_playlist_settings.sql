@@ -0,0 +1,22 @@
+-- the settings of a playlist are kept once, instead of on each of the rows
+-- of its links
+CREATE TABLE playlist_settings(
+    playlist_id TEXT PRIMARY KEY,
+    allow_duplicates BOOLEAN NOT NULL DEFAULT FALSE,
+    -- json of the exclusion rules, the playlist uses the default ones without them
+    rules TEXT,
+    -- retention of the playlist, tracks past it are removed on updates
+    max_tracks INT,
+    max_age_days INT
+);
+
+-- the rows of a playlist could disagree, a setting made on any of them wins
+INSERT INTO playlist_settings(playlist_id, allow_duplicates, rules, max_tracks, max_age_days)
+SELECT playlist_id, BOOL_OR(allow_duplicates), MAX(rules), MAX(max_tracks), MAX(max_age_days)
+FROM playlist
+GROUP BY playlist_id;
+
+ALTER TABLE playlist DROP COLUMN allow_duplicates;
+ALTER TABLE playlist DROP COLUMN rules;
+ALTER TABLE playlist DROP COLUMN max_tracks;
+ALTER TABLE playlist DROP COLUMN max_age_days;
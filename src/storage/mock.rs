@@ -1,16 +1,26 @@
+use std::collections::HashMap;
+
 use async_trait::async_trait;
 use tokio::sync::Mutex;
 
 use crate::{
     error::CoolioError,
-    models::{Listen, Playlist},
-    storage::Storage,
+    models::{
+        ExclusionRules, Gap, LinkCheckpoint, LinkOptions, Listen, Playlist, Retention,
+        TrackMetadata,
+    },
+    storage::{merge_listens, Storage},
 };
 
 #[derive(Clone, Debug, Default)]
 pub struct StorageState {
     pub listens: Vec<Listen>,
+    pub gaps: Vec<Gap>,
     pub playlists: Vec<Playlist>,
+    pub checkpoints: HashMap<(String, String), LinkCheckpoint>,
+    pub link_options: HashMap<(String, String), LinkOptions>,
+    // artist ids that fail to be unlinked
+    pub failing_unlinks: Vec<String>,
 }
 
 pub struct Mock {
@@ -27,8 +37,8 @@ impl Mock {
 
 #[async_trait]
 impl Storage for Mock {
-    async fn add_history(&self, listen: Listen) -> Result<(), CoolioError> {
-        self.state.lock().await.listens.push(listen);
+    async fn add_history_batch(&self, listens: Vec<Listen>) -> Result<(), CoolioError> {
+        merge_listens(&mut self.state.lock().await.listens, listens);
         Ok(())
     }
 
@@ -36,6 +46,27 @@ impl Storage for Mock {
         Ok(self.state.lock().await.listens.to_vec())
     }
 
+    async fn dedupe_history(&self) -> Result<usize, CoolioError> {
+        let listens = &mut self.state.lock().await.listens;
+        let before = listens.len();
+        let mut deduped = Vec::<Listen>::new();
+        merge_listens(&mut deduped, std::mem::take(listens));
+        *listens = deduped;
+        Ok(before - listens.len())
+    }
+
+    async fn set_track_metadata(
+        &self,
+        tracks: &HashMap<String, TrackMetadata>,
+    ) -> Result<(), CoolioError> {
+        for l in self.state.lock().await.listens.iter_mut() {
+            if l.track.is_none() {
+                l.track = tracks.get(&l.song_id).cloned();
+            }
+        }
+        Ok(())
+    }
+
     async fn get_last_listen(&self) -> Result<Listen, CoolioError> {
         let h = self.state.lock().await.listens.to_vec();
         if h.len() == 0 {
@@ -50,12 +81,24 @@ impl Storage for Mock {
         Ok(last_listen)
     }
 
+    async fn add_gap(&self, gap: Gap) -> Result<(), CoolioError> {
+        self.state.lock().await.gaps.push(gap);
+        Ok(())
+    }
+
+    async fn get_gaps(&self) -> Result<Vec<Gap>, CoolioError> {
+        Ok(self.state.lock().await.gaps.to_vec())
+    }
+
     async fn create_playlist(&self, id: &str, name: &str) -> Result<(), CoolioError> {
         self.state.lock().await.playlists.push(Playlist {
             id: id.to_string(),
             name: name.to_string(),
             automated: true,
             artists: vec![],
+            allow_duplicates: false,
+            rules: None,
+            retention: Retention::default(),
         });
         Ok(())
     }
@@ -74,19 +117,69 @@ impl Storage for Mock {
         Err("no such playlist".into())
     }
 
+    async fn set_allow_duplicates(
+        &self,
+        playlist_id: &str,
+        allow: bool,
+    ) -> Result<(), CoolioError> {
+        let ps = &mut self.state.lock().await.playlists;
+        for p in ps {
+            if p.id == playlist_id {
+                p.allow_duplicates = allow;
+                return Ok(());
+            }
+        }
+        Err("playlist doesn't exist".into())
+    }
+
+    async fn set_rules(
+        &self,
+        playlist_id: &str,
+        rules: Option<&ExclusionRules>,
+    ) -> Result<(), CoolioError> {
+        let ps = &mut self.state.lock().await.playlists;
+        for p in ps {
+            if p.id == playlist_id {
+                p.rules = rules.cloned();
+                return Ok(());
+            }
+        }
+        Err("playlist doesn't exist".into())
+    }
+
+    async fn set_retention(
+        &self,
+        playlist_id: &str,
+        retention: &Retention,
+    ) -> Result<(), CoolioError> {
+        let ps = &mut self.state.lock().await.playlists;
+        for p in ps {
+            if p.id == playlist_id {
+                p.retention = retention.clone();
+                return Ok(());
+            }
+        }
+        Err("playlist doesn't exist".into())
+    }
+
     async fn link_artist(
         &self,
         playlist_id: &str,
         _playlist_name: &str,
         artist_id: &str,
+        options: &LinkOptions,
     ) -> Result<(), CoolioError> {
-        let ps = &mut self.state.lock().await.playlists;
-        for p in ps {
+        let state = &mut *self.state.lock().await;
+        for p in &mut state.playlists {
             if p.id == playlist_id {
                 if p.artists.contains(&artist_id.to_string()) {
                     return Err("duplicate artists".into());
                 } else {
                     p.artists.push(artist_id.to_string());
+                    state.link_options.insert(
+                        (playlist_id.to_string(), artist_id.to_string()),
+                        options.clone(),
+                    );
                     return Ok(());
                 }
             }
@@ -95,12 +188,18 @@ impl Storage for Mock {
     }
 
     async fn unlink_artist(&self, playlist_id: &str, artist_id: &str) -> Result<(), CoolioError> {
-        let ps = &mut self.state.lock().await.playlists;
-        for p in ps {
+        let state = &mut *self.state.lock().await;
+        if state.failing_unlinks.iter().any(|a| a == artist_id) {
+            return Err("unlink failed".into());
+        }
+        for p in &mut state.playlists {
             if p.id == playlist_id {
                 let len_before = p.artists.len();
                 p.artists.retain(|a| a != artist_id);
                 if len_before != p.artists.len() {
+                    let key = (playlist_id.to_string(), artist_id.to_string());
+                    state.checkpoints.remove(&key);
+                    state.link_options.remove(&key);
                     return Ok(());
                 } else {
                     return Err("artist not linked to playlist".into());
@@ -109,4 +208,68 @@ impl Storage for Mock {
         }
         Err("playlist doesn't exist".into())
     }
+
+    async fn get_checkpoint(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+    ) -> Result<Option<LinkCheckpoint>, CoolioError> {
+        let key = (playlist_id.to_string(), artist_id.to_string());
+        Ok(self.state.lock().await.checkpoints.get(&key).cloned())
+    }
+
+    async fn set_checkpoint(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+        checkpoint: &LinkCheckpoint,
+    ) -> Result<(), CoolioError> {
+        let state = &mut *self.state.lock().await;
+        let linked = state
+            .playlists
+            .iter()
+            .any(|p| p.id == playlist_id && p.artists.iter().any(|a| a == artist_id));
+        if !linked {
+            return Err("artist not linked to playlist".into());
+        }
+        state.checkpoints.insert(
+            (playlist_id.to_string(), artist_id.to_string()),
+            checkpoint.clone(),
+        );
+        Ok(())
+    }
+
+    async fn get_link_options(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+    ) -> Result<LinkOptions, CoolioError> {
+        let key = (playlist_id.to_string(), artist_id.to_string());
+        self.state
+            .lock()
+            .await
+            .link_options
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| "artist not linked to playlist".into())
+    }
+
+    async fn set_link_options(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+        options: &LinkOptions,
+    ) -> Result<(), CoolioError> {
+        let state = &mut *self.state.lock().await;
+        match state
+            .link_options
+            .get_mut(&(playlist_id.to_string(), artist_id.to_string()))
+        {
+            Some(o) => {
+                *o = options.clone();
+                Ok(())
+            }
+            None => Err("artist not linked to playlist".into()),
+        }
+    }
 }
@@ -1,6 +1,8 @@
+pub mod copy;
 pub mod fs;
 pub mod mock;
 pub mod psql;
+pub mod sqlite;
 
 use async_trait::async_trait;
 use enum_as_inner::EnumAsInner;
@@ -9,6 +11,7 @@ use enum_dispatch::enum_dispatch;
 use fs::Fs;
 use mock::Mock;
 use psql::Psql;
+use sqlite::Sqlite;
 
 use crate::error::CoolioError;
 
@@ -17,32 +20,121 @@ use crate::error::CoolioError;
 pub enum StorageBehavior {
     Psql,
     Fs,
+    Sqlite,
     Mock,
 }
 
-use crate::models::{Listen, Playlist};
+use std::collections::HashMap;
+
+use crate::models::{
+    ExclusionRules, Gap, LinkCheckpoint, LinkOptions, Listen, Playlist, Retention, TrackMetadata,
+};
+
+/// Adds listens to a history, merging those already in it into the existing
+/// records instead. Returns whether any existing record was changed.
+pub fn merge_listens(history: &mut Vec<Listen>, listens: Vec<Listen>) -> bool {
+    let mut recorded = history
+        .iter()
+        .enumerate()
+        .map(|(i, l)| (l.key(), i))
+        .collect::<HashMap<_, _>>();
+    let existing = history.len();
+
+    let mut changed = false;
+    for l in listens {
+        match recorded.get(&l.key()) {
+            Some(&i) => {
+                let before = history[i].clone();
+                history[i].merge(l);
+                changed |= i < existing && history[i] != before;
+            }
+            None => {
+                recorded.insert(l.key(), history.len());
+                history.push(l);
+            }
+        }
+    }
+    changed
+}
 
 #[async_trait]
 #[enum_dispatch(StorageBehavior)]
 pub trait Storage: Send + Sync {
-    async fn add_history(&self, listen: Listen) -> Result<(), CoolioError>;
+    async fn add_history_batch(&self, listens: Vec<Listen>) -> Result<(), CoolioError>;
 
     async fn get_history(&self) -> Result<Vec<Listen>, CoolioError>;
 
+    /// Removes repeated listens of the same track at the same time,
+    /// returning how many were removed.
+    async fn dedupe_history(&self) -> Result<usize, CoolioError>;
+
+    async fn set_track_metadata(
+        &self,
+        tracks: &HashMap<String, TrackMetadata>,
+    ) -> Result<(), CoolioError>;
+
     async fn get_last_listen(&self) -> Result<Listen, CoolioError>;
 
+    async fn add_gap(&self, gap: Gap) -> Result<(), CoolioError>;
+
+    async fn get_gaps(&self) -> Result<Vec<Gap>, CoolioError>;
+
     async fn create_playlist(&self, id: &str, name: &str) -> Result<(), CoolioError>;
 
     async fn get_playlists(&self) -> Result<Vec<Playlist>, CoolioError>;
 
     async fn get_playlist(&self, name: &str) -> Result<Playlist, CoolioError>;
 
+    async fn set_allow_duplicates(&self, playlist_id: &str, allow: bool)
+        -> Result<(), CoolioError>;
+
+    /// Sets the exclusion rules of a playlist, none going back to the defaults.
+    async fn set_rules(
+        &self,
+        playlist_id: &str,
+        rules: Option<&ExclusionRules>,
+    ) -> Result<(), CoolioError>;
+
+    async fn set_retention(
+        &self,
+        playlist_id: &str,
+        retention: &Retention,
+    ) -> Result<(), CoolioError>;
+
     async fn link_artist(
         &self,
         playlist_id: &str,
         playlist_name: &str,
         artist_id: &str,
+        options: &LinkOptions,
     ) -> Result<(), CoolioError>;
 
     async fn unlink_artist(&self, playlist_id: &str, artist_id: &str) -> Result<(), CoolioError>;
+
+    /// The checkpoint of a link, none if the link hasn't been updated yet.
+    async fn get_checkpoint(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+    ) -> Result<Option<LinkCheckpoint>, CoolioError>;
+
+    async fn set_checkpoint(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+        checkpoint: &LinkCheckpoint,
+    ) -> Result<(), CoolioError>;
+
+    async fn get_link_options(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+    ) -> Result<LinkOptions, CoolioError>;
+
+    async fn set_link_options(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+        options: &LinkOptions,
+    ) -> Result<(), CoolioError>;
 }
@@ -1,12 +1,100 @@
 use crate::{error::CoolioError, models::Playlist, settings::Database};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use async_trait::async_trait;
-use tokio_postgres::{Client, NoTls};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use tokio_postgres::{Client, NoTls, Row};
 
-use super::Storage;
-use crate::models::Listen;
+use super::{merge_listens, Storage};
+use crate::models::{
+    ExclusionRules, Gap, LinkCheckpoint, LinkOptions, Listen, ReleaseType, Retention, TrackMetadata,
+};
 
 pub struct Psql {
     client: Client,
+    // whether the listens have their unique index, which upserting them needs
+    unique_listens: AtomicBool,
+}
+
+#[derive(Debug)]
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Changes to the schema, applied in order to bring databases up to date.
+/// Released ones must not be changed, new changes go in a new migration.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "baseline",
+        sql: include_str!("migrations/001_baseline.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "playlist settings",
+        sql: include_str!("migrations/002_playlist_settings.sql"),
+    },
+];
+
+// any key works, as long as every instance of coolio uses the same one
+const MIGRATION_LOCK: i64 = 0x636f6f6c696f;
+
+/// Finds the migrations a database at the `current` version doesn't have
+/// yet, in the order they are applied.
+pub fn pending_migrations(
+    current: i32,
+    migrations: &[Migration],
+) -> Result<Vec<&Migration>, CoolioError> {
+    let latest = migrations.last().map_or(0, |m| m.version);
+    if current > latest {
+        return Err(format!(
+            "database schema version {} is newer than {}, the latest this coolio knows",
+            current, latest
+        )
+        .into());
+    }
+    Ok(migrations.iter().filter(|m| m.version > current).collect())
+}
+
+/// Applies the migrations the database doesn't have yet, all of them or none.
+async fn migrate(client: &mut Client) -> Result<(), CoolioError> {
+    let tx = client.transaction().await?;
+    // another instance starting at the same time waits for this one to finish
+    tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])
+        .await?;
+    tx.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_version(
+            version INT PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        )",
+    )
+    .await?;
+
+    let current: i32 = tx
+        .query_one("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[])
+        .await?
+        .get(0);
+    for m in pending_migrations(current, MIGRATIONS)? {
+        tx.batch_execute(m.sql).await.map_err(|e| {
+            CoolioError::from(format!(
+                "migration {} ({}) failed: {}",
+                m.version, m.name, e
+            ))
+        })?;
+        tx.execute(
+            "INSERT INTO schema_version(version, name) VALUES ($1, $2)",
+            &[&m.version, &m.name],
+        )
+        .await?;
+        info!("applied migration {} ({})", m.version, m.name);
+    }
+    tx.commit().await?;
+    Ok(())
 }
 
 impl Psql {
@@ -18,64 +106,297 @@ impl Psql {
             host = conf.host,
             dbname = conf.name
         );
-        let (client, connection) = tokio_postgres::connect(&conn_str, NoTls).await?;
+        let (mut client, connection) = tokio_postgres::connect(&conn_str, NoTls).await?;
         tokio::spawn(async move {
             if let Err(e) = connection.await {
                 eprintln!("connection error: {}", e);
             }
         });
 
-        Ok(Psql { client })
+        migrate(&mut client).await?;
+        let unique_listens = AtomicBool::new(index_listens(&client).await?);
+        Ok(Psql {
+            client,
+            unique_listens,
+        })
+    }
+}
+
+// the listens are upserted by it, so that none is recorded twice
+const LISTEN_INDEX: &str =
+    "CREATE UNIQUE INDEX IF NOT EXISTS listen_song_id_time ON listen(song_id, time)";
+
+/// Creates the unique index of the listens, unless some were recorded twice
+/// before there was one. Returns whether the index is there.
+async fn index_listens(client: &Client) -> Result<bool, CoolioError> {
+    let indexed: bool = client
+        .query_one("SELECT to_regclass('listen_song_id_time') IS NOT NULL", &[])
+        .await?
+        .get(0);
+    if indexed {
+        return Ok(true);
+    }
+
+    let duplicates: i64 = client
+        .query_one(
+            "SELECT COUNT(*) FROM (
+                SELECT 1 FROM listen GROUP BY song_id, time HAVING COUNT(*) > 1
+            ) AS d",
+            &[],
+        )
+        .await?
+        .get(0);
+    if duplicates > 0 {
+        warn!(
+            "{} listens were recorded more than once, run `coolio history dedupe` before recording new ones",
+            duplicates
+        );
+        return Ok(false);
+    }
+    client.batch_execute(LISTEN_INDEX).await?;
+    Ok(true)
+}
+
+const LISTEN_COLUMNS: &str = "song_id, time, ms_played, skipped, platform, context, \
+    track_name, artist_ids, artist_names, album_id, album_name, duration_ms";
+
+// a listen recorded again only fills in what the stored one is missing
+const LISTEN_UPSERT: &str = "ON CONFLICT (song_id, time) DO UPDATE SET
+    ms_played = COALESCE(listen.ms_played, EXCLUDED.ms_played),
+    skipped = COALESCE(listen.skipped, EXCLUDED.skipped),
+    platform = COALESCE(listen.platform, EXCLUDED.platform),
+    context = COALESCE(listen.context, EXCLUDED.context),
+    track_name = COALESCE(listen.track_name, EXCLUDED.track_name),
+    artist_ids = COALESCE(listen.artist_ids, EXCLUDED.artist_ids),
+    artist_names = COALESCE(listen.artist_names, EXCLUDED.artist_names),
+    album_id = COALESCE(listen.album_id, EXCLUDED.album_id),
+    album_name = COALESCE(listen.album_name, EXCLUDED.album_name),
+    duration_ms = COALESCE(listen.duration_ms, EXCLUDED.duration_ms)";
+
+fn listen_from_row(row: &Row) -> Listen {
+    let track = match (
+        row.get::<_, Option<String>>(6),
+        row.get::<_, Option<Vec<String>>>(7),
+        row.get::<_, Option<Vec<String>>>(8),
+        row.get::<_, Option<i64>>(11),
+    ) {
+        (Some(name), Some(artist_ids), Some(artist_names), Some(duration_ms)) => {
+            Some(TrackMetadata {
+                name,
+                artist_ids,
+                artist_names,
+                album_id: row.get(9),
+                album_name: row.get(10),
+                duration_ms: duration_ms as u32,
+            })
+        }
+        _ => None,
+    };
+
+    Listen {
+        song_id: row.get(0),
+        time: row.get(1),
+        ms_played: row.get::<_, Option<i64>>(2).map(|x| x as u32),
+        skipped: row.get(3),
+        platform: row.get(4),
+        context: row.get(5),
+        track,
     }
 }
 
 #[async_trait]
 impl Storage for Psql {
-    async fn add_history(&self, listen: Listen) -> Result<(), CoolioError> {
-        let query_text = "INSERT INTO listen VALUES ($1, $2)";
+    async fn add_history_batch(&self, listens: Vec<Listen>) -> Result<(), CoolioError> {
+        if !self.unique_listens.load(Ordering::Relaxed) {
+            return Err(
+                "some listens were recorded more than once, run `coolio history dedupe` first"
+                    .into(),
+            );
+        }
+
+        // arrays of arrays can't be unnested, so the artists are passed as json
+        let query_text = format!(
+            "
+        INSERT INTO listen({})
+        SELECT s, t, mp, sk, p, c, tn,
+            CASE WHEN ai IS NULL THEN NULL ELSE ARRAY(SELECT jsonb_array_elements_text(ai::JSONB)) END,
+            CASE WHEN an IS NULL THEN NULL ELSE ARRAY(SELECT jsonb_array_elements_text(an::JSONB)) END,
+            ali, aln, d
+        FROM UNNEST(
+            $1::TEXT[], $2::TIMESTAMPTZ[], $3::BIGINT[], $4::BOOLEAN[], $5::TEXT[], $6::TEXT[],
+            $7::TEXT[], $8::TEXT[], $9::TEXT[], $10::TEXT[], $11::TEXT[], $12::BIGINT[]
+        ) AS l(s, t, mp, sk, p, c, tn, ai, an, ali, aln, d)
+        {}",
+            LISTEN_COLUMNS, LISTEN_UPSERT
+        );
+
+        // a row can't be upserted twice in the same statement
+        let mut deduped = Vec::<Listen>::new();
+        merge_listens(&mut deduped, listens);
+        let listens = deduped;
+
+        let expected = listens.len() as u64;
+        let mut song_ids = Vec::<String>::new();
+        let mut times = Vec::<DateTime<Utc>>::new();
+        let mut ms_played = Vec::<Option<i64>>::new();
+        let mut skipped = Vec::<Option<bool>>::new();
+        let mut platforms = Vec::<Option<String>>::new();
+        let mut contexts = Vec::<Option<String>>::new();
+        let mut track_names = Vec::<Option<String>>::new();
+        let mut artist_ids = Vec::<Option<String>>::new();
+        let mut artist_names = Vec::<Option<String>>::new();
+        let mut album_ids = Vec::<Option<String>>::new();
+        let mut album_names = Vec::<Option<String>>::new();
+        let mut durations = Vec::<Option<i64>>::new();
+        for l in listens {
+            song_ids.push(l.song_id);
+            times.push(l.time);
+            ms_played.push(l.ms_played.map(|x| x as i64));
+            skipped.push(l.skipped);
+            platforms.push(l.platform);
+            contexts.push(l.context);
+            let track = l.track.as_ref();
+            track_names.push(track.map(|t| t.name.clone()));
+            artist_ids.push(track.and_then(|t| serde_json::to_string(&t.artist_ids).ok()));
+            artist_names.push(track.and_then(|t| serde_json::to_string(&t.artist_names).ok()));
+            album_ids.push(track.and_then(|t| t.album_id.clone()));
+            album_names.push(track.and_then(|t| t.album_name.clone()));
+            durations.push(track.map(|t| t.duration_ms as i64));
+        }
 
         let res = self
             .client
-            .execute(query_text, &[&listen.song_id, &listen.time])
+            .execute(
+                &query_text,
+                &[
+                    &song_ids,
+                    &times,
+                    &ms_played,
+                    &skipped,
+                    &platforms,
+                    &contexts,
+                    &track_names,
+                    &artist_ids,
+                    &artist_names,
+                    &album_ids,
+                    &album_names,
+                    &durations,
+                ],
+            )
             .await?;
 
-        if res != 1 {
-            Err("no values inserted".into())
+        if res != expected {
+            Err("not all values inserted".into())
         } else {
             Ok(())
         }
     }
 
     async fn get_history(&self) -> Result<Vec<Listen>, CoolioError> {
-        let query_text = "SELECT song_id, time FROM listen ORDER BY time";
+        let query_text = format!("SELECT {} FROM listen ORDER BY time", LISTEN_COLUMNS);
         let mut history = Vec::<Listen>::new();
-        let h = self.client.query(query_text, &[]).await?;
+        let h = self.client.query(&query_text, &[]).await?;
 
         for row in h {
-            history.push(Listen {
-                song_id: row.get(0),
-                time: row.get(1),
-            })
+            history.push(listen_from_row(&row))
         }
         Ok(history)
     }
 
+    async fn dedupe_history(&self) -> Result<usize, CoolioError> {
+        // keeps the most complete of the repeated rows
+        let query_text = "
+        DELETE FROM listen WHERE ctid IN (
+            SELECT ctid FROM (
+                SELECT ctid, ROW_NUMBER() OVER (
+                    PARTITION BY song_id, time
+                    ORDER BY track_name IS NULL, ms_played IS NULL
+                ) AS n
+                FROM listen
+            ) AS l
+            WHERE n > 1
+        )";
+        let removed = self.client.execute(query_text, &[]).await?;
+        self.client.batch_execute(LISTEN_INDEX).await?;
+        self.unique_listens.store(true, Ordering::Relaxed);
+        Ok(removed as usize)
+    }
+
+    async fn set_track_metadata(
+        &self,
+        tracks: &HashMap<String, TrackMetadata>,
+    ) -> Result<(), CoolioError> {
+        let query_text = "
+        UPDATE listen
+        SET track_name = $2, artist_ids = $3, artist_names = $4,
+            album_id = $5, album_name = $6, duration_ms = $7
+        WHERE song_id = $1 AND track_name IS NULL";
+
+        for (song_id, t) in tracks {
+            self.client
+                .execute(
+                    query_text,
+                    &[
+                        song_id,
+                        &t.name,
+                        &t.artist_ids,
+                        &t.artist_names,
+                        &t.album_id,
+                        &t.album_name,
+                        &(t.duration_ms as i64),
+                    ],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
     async fn get_last_listen(&self) -> Result<Listen, CoolioError> {
-        let query_text = "SELECT song_id, time FROM listen ORDER BY time DESC LIMIT 1";
+        let query_text = format!(
+            "SELECT {} FROM listen ORDER BY time DESC LIMIT 1",
+            LISTEN_COLUMNS
+        );
 
-        for row in self.client.query(query_text, &[]).await? {
-            return Ok(Listen {
-                song_id: row.get(0),
-                time: row.get(1),
-            });
+        for row in self.client.query(&query_text, &[]).await? {
+            return Ok(listen_from_row(&row));
         }
 
         Err("no listens found".into())
     }
 
+    async fn add_gap(&self, gap: Gap) -> Result<(), CoolioError> {
+        let query_text = "INSERT INTO gap(from_time, to_time) VALUES ($1, $2)";
+        let res = self
+            .client
+            .execute(query_text, &[&gap.from, &gap.to])
+            .await?;
+
+        if res != 1 {
+            Err("no values inserted".into())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn get_gaps(&self) -> Result<Vec<Gap>, CoolioError> {
+        let query_text = "SELECT from_time, to_time FROM gap ORDER BY from_time";
+        let mut gaps = Vec::<Gap>::new();
+        for row in self.client.query(query_text, &[]).await? {
+            gaps.push(Gap {
+                from: row.get(0),
+                to: row.get(1),
+            });
+        }
+        Ok(gaps)
+    }
+
     async fn create_playlist(&self, id: &str, name: &str) -> Result<(), CoolioError> {
-        let query_text =
-            "INSERT INTO playlist(playlist_id, playlist_name, artist_id) VALUES($1, $2, NULL)";
+        // one statement, so the playlist doesn't end up without its settings
+        let query_text = "
+        WITH settings AS (
+            INSERT INTO playlist_settings(playlist_id) VALUES ($1) ON CONFLICT DO NOTHING
+        )
+        INSERT INTO playlist(playlist_id, playlist_name, artist_id) VALUES($1, $2, NULL)";
         let res = self
             .client
             .execute(query_text, &[&id.to_string(), &name.to_string()])
@@ -89,9 +410,12 @@ impl Storage for Psql {
 
     async fn get_playlists(&self) -> Result<Vec<Playlist>, CoolioError> {
         let query_text = "
-        SELECT playlist_name, playlist_id, ARRAY_REMOVE(ARRAY_AGG(artist_id), NULL) AS \"artists\"
-        FROM playlist
-        GROUP BY (playlist_name, playlist_id)";
+        SELECT p.playlist_name, p.playlist_id,
+            ARRAY_REMOVE(ARRAY_AGG(p.artist_id), NULL) AS \"artists\",
+            COALESCE(s.allow_duplicates, FALSE), s.rules, s.max_tracks, s.max_age_days
+        FROM playlist p
+        LEFT JOIN playlist_settings s ON s.playlist_id = p.playlist_id
+        GROUP BY p.playlist_name, p.playlist_id, s.playlist_id";
 
         let mut playlists = Vec::<Playlist>::new();
 
@@ -99,11 +423,16 @@ impl Storage for Psql {
             let name = row.get(0);
             let id = row.get(1);
             let artists = row.get(2);
+            let allow_duplicates = row.get(3);
+            let rules: Option<String> = row.get(4);
             playlists.push(Playlist {
                 name,
                 id,
                 artists,
                 automated: true,
+                allow_duplicates,
+                rules: parse_rules(rules)?,
+                retention: parse_retention(row.get(5), row.get(6)),
             })
         }
 
@@ -111,15 +440,29 @@ impl Storage for Psql {
     }
 
     async fn get_playlist(&self, name: &str) -> Result<Playlist, CoolioError> {
-        let query_text = "SELECT playlist_id, artist_id FROM playlist WHERE playlist_name = $1";
+        let query_text = "
+        SELECT p.playlist_id, p.artist_id,
+            COALESCE(s.allow_duplicates, FALSE), s.rules, s.max_tracks, s.max_age_days
+        FROM playlist p
+        LEFT JOIN playlist_settings s ON s.playlist_id = p.playlist_id
+        WHERE p.playlist_name = $1";
 
         let mut artists = Vec::<String>::new();
         let mut id: Option<String> = None;
+        let mut allow_duplicates = false;
+        let mut rules: Option<String> = None;
+        let mut max_tracks: Option<i32> = None;
+        let mut max_age_days: Option<i32> = None;
         for row in self.client.query(query_text, &[&name.to_string()]).await? {
             id = row.get(0);
             if let Some(artist) = row.get(1) {
                 artists.push(artist);
             }
+            // every row has the same settings of the playlist
+            allow_duplicates = row.get(2);
+            rules = row.get(3);
+            max_tracks = row.get(4);
+            max_age_days = row.get(5);
         }
 
         if let Some(id) = id {
@@ -128,20 +471,104 @@ impl Storage for Psql {
                 artists,
                 name: name.to_string(),
                 automated: true,
+                allow_duplicates,
+                rules: parse_rules(rules)?,
+                retention: parse_retention(max_tracks, max_age_days),
             })
         } else {
             Err("playlist doesnt exist".into())
         }
     }
 
+    async fn set_allow_duplicates(
+        &self,
+        playlist_id: &str,
+        allow: bool,
+    ) -> Result<(), CoolioError> {
+        let query_text = "
+        INSERT INTO playlist_settings(playlist_id, allow_duplicates)
+        SELECT DISTINCT playlist_id, $2::BOOLEAN FROM playlist WHERE playlist_id = $1
+        ON CONFLICT (playlist_id) DO UPDATE SET allow_duplicates = EXCLUDED.allow_duplicates";
+
+        let res = self
+            .client
+            .execute(query_text, &[&playlist_id.to_string(), &allow])
+            .await?;
+
+        if res == 0 {
+            Err("playlist doesnt exist".into())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn set_rules(
+        &self,
+        playlist_id: &str,
+        rules: Option<&ExclusionRules>,
+    ) -> Result<(), CoolioError> {
+        let query_text = "
+        INSERT INTO playlist_settings(playlist_id, rules)
+        SELECT DISTINCT playlist_id, $2::TEXT FROM playlist WHERE playlist_id = $1
+        ON CONFLICT (playlist_id) DO UPDATE SET rules = EXCLUDED.rules";
+
+        let rules = match rules {
+            Some(rules) => Some(serde_json::to_string(rules)?),
+            None => None,
+        };
+        let res = self
+            .client
+            .execute(query_text, &[&playlist_id.to_string(), &rules])
+            .await?;
+
+        if res == 0 {
+            Err("playlist doesnt exist".into())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn set_retention(
+        &self,
+        playlist_id: &str,
+        retention: &Retention,
+    ) -> Result<(), CoolioError> {
+        let query_text = "
+        INSERT INTO playlist_settings(playlist_id, max_tracks, max_age_days)
+        SELECT DISTINCT playlist_id, $2::INT, $3::INT FROM playlist WHERE playlist_id = $1
+        ON CONFLICT (playlist_id) DO UPDATE
+        SET max_tracks = EXCLUDED.max_tracks, max_age_days = EXCLUDED.max_age_days";
+
+        let res = self
+            .client
+            .execute(
+                query_text,
+                &[
+                    &playlist_id.to_string(),
+                    &retention.max_tracks.map(|x| x as i32),
+                    &retention.max_age_days.map(|x| x as i32),
+                ],
+            )
+            .await?;
+
+        if res == 0 {
+            Err("playlist doesnt exist".into())
+        } else {
+            Ok(())
+        }
+    }
+
     async fn link_artist(
         &self,
         playlist_id: &str,
         playlist_name: &str,
         artist_id: &str,
+        options: &LinkOptions,
     ) -> Result<(), CoolioError> {
-        let query_text =
-            "INSERT INTO playlist(playlist_id, playlist_name, artist_id) VALUES ($1, $2, $3)";
+        let query_text = "
+        INSERT INTO playlist(
+            playlist_id, playlist_name, artist_id, release_types, seed, include_features
+        ) VALUES ($1, $2, $3, $4, $5, $6)";
 
         let res = self
             .client
@@ -151,6 +578,9 @@ impl Storage for Psql {
                     &playlist_id.to_string(),
                     &playlist_name.to_string(),
                     &artist_id.to_string(),
+                    &release_types(options),
+                    &(options.seed as i32),
+                    &options.include_features,
                 ],
             )
             .await?;
@@ -179,4 +609,150 @@ impl Storage for Psql {
             Ok(())
         }
     }
+
+    async fn get_checkpoint(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+    ) -> Result<Option<LinkCheckpoint>, CoolioError> {
+        let query_text = "
+        SELECT last_checked, checked_albums
+        FROM playlist
+        WHERE playlist_id = $1 AND artist_id = $2";
+
+        let row = self
+            .client
+            .query_opt(
+                query_text,
+                &[&playlist_id.to_string(), &artist_id.to_string()],
+            )
+            .await?
+            .ok_or("artist not linked to playlist")?;
+
+        let last_checked: Option<DateTime<Utc>> = row.get(0);
+        let albums: Option<Vec<String>> = row.get(1);
+        Ok(last_checked.map(|last_checked| LinkCheckpoint {
+            last_checked,
+            albums: albums.unwrap_or_default(),
+        }))
+    }
+
+    async fn set_checkpoint(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+        checkpoint: &LinkCheckpoint,
+    ) -> Result<(), CoolioError> {
+        let query_text = "
+        UPDATE playlist SET last_checked = $3, checked_albums = $4
+        WHERE playlist_id = $1 AND artist_id = $2";
+
+        let res = self
+            .client
+            .execute(
+                query_text,
+                &[
+                    &playlist_id.to_string(),
+                    &artist_id.to_string(),
+                    &checkpoint.last_checked,
+                    &checkpoint.albums,
+                ],
+            )
+            .await?;
+
+        if res != 1 {
+            Err("artist not linked to playlist".into())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn get_link_options(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+    ) -> Result<LinkOptions, CoolioError> {
+        let query_text = "
+        SELECT release_types, seed, include_features
+        FROM playlist
+        WHERE playlist_id = $1 AND artist_id = $2";
+
+        let row = self
+            .client
+            .query_opt(
+                query_text,
+                &[&playlist_id.to_string(), &artist_id.to_string()],
+            )
+            .await?
+            .ok_or("artist not linked to playlist")?;
+
+        // links made before the options existed have them missing
+        let mut options = LinkOptions::default();
+        let types: Option<Vec<String>> = row.get(0);
+        if let Some(types) = types {
+            options.release_types = types.iter().map(|t| t.parse()).collect::<Result<_, _>>()?;
+        }
+        let seed: Option<i32> = row.get(1);
+        if let Some(seed) = seed {
+            options.seed = seed as usize;
+        }
+        let features: Option<bool> = row.get(2);
+        if let Some(features) = features {
+            options.include_features = features;
+        }
+        Ok(options)
+    }
+
+    async fn set_link_options(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+        options: &LinkOptions,
+    ) -> Result<(), CoolioError> {
+        let query_text = "
+        UPDATE playlist SET release_types = $3, seed = $4, include_features = $5
+        WHERE playlist_id = $1 AND artist_id = $2";
+
+        let res = self
+            .client
+            .execute(
+                query_text,
+                &[
+                    &playlist_id.to_string(),
+                    &artist_id.to_string(),
+                    &release_types(options),
+                    &(options.seed as i32),
+                    &options.include_features,
+                ],
+            )
+            .await?;
+
+        if res != 1 {
+            Err("artist not linked to playlist".into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn release_types(options: &LinkOptions) -> Vec<String> {
+    options
+        .release_types
+        .iter()
+        .map(ReleaseType::to_string)
+        .collect()
+}
+
+fn parse_rules(rules: Option<String>) -> Result<Option<ExclusionRules>, CoolioError> {
+    match rules {
+        Some(rules) => Ok(Some(serde_json::from_str(&rules)?)),
+        None => Ok(None),
+    }
+}
+
+fn parse_retention(max_tracks: Option<i32>, max_age_days: Option<i32>) -> Retention {
+    Retention {
+        max_tracks: max_tracks.map(|x| x as usize),
+        max_age_days: max_age_days.map(|x| x as u32),
+    }
 }
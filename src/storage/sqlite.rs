@@ -0,0 +1,590 @@
+use crate::{error::CoolioError, models::Playlist, settings::SqliteDatabase};
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row, ToSql};
+use tokio::sync::Mutex;
+
+use super::{merge_listens, Storage};
+use crate::models::{
+    ExclusionRules, Gap, LinkCheckpoint, LinkOptions, Listen, ReleaseType, Retention, TrackMetadata,
+};
+
+// the same tables as in Postgres, with the arrays kept as json
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS listen(
+    song_id TEXT NOT NULL,
+    time TEXT NOT NULL,
+    ms_played INTEGER,
+    skipped BOOLEAN,
+    platform TEXT,
+    context TEXT,
+    track_name TEXT,
+    artist_ids TEXT,
+    artist_names TEXT,
+    album_id TEXT,
+    album_name TEXT,
+    duration_ms INTEGER,
+    UNIQUE (song_id, time)
+);
+
+CREATE INDEX IF NOT EXISTS listen_time ON listen(time);
+
+CREATE TABLE IF NOT EXISTS gap(
+    from_time TEXT NOT NULL,
+    to_time TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS playlist(
+    playlist_name TEXT NOT NULL,
+    playlist_id TEXT NOT NULL,
+    artist_id TEXT,
+    allow_duplicates BOOLEAN NOT NULL DEFAULT FALSE,
+    last_checked TEXT,
+    checked_albums TEXT,
+    release_types TEXT,
+    seed INTEGER,
+    include_features BOOLEAN,
+    rules TEXT,
+    max_tracks INTEGER,
+    max_age_days INTEGER,
+    UNIQUE (playlist_id, artist_id)
+);";
+
+pub struct Sqlite {
+    // rusqlite connections can't be shared between threads
+    conn: Mutex<Connection>,
+}
+
+impl Sqlite {
+    pub async fn new(conf: SqliteDatabase) -> Result<Self, CoolioError> {
+        let conn = Connection::open(&conf.path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Sqlite {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+const LISTEN_COLUMNS: &str = "song_id, time, ms_played, skipped, platform, context, \
+    track_name, artist_ids, artist_names, album_id, album_name, duration_ms";
+
+// a listen recorded again only fills in what the stored one is missing
+const LISTEN_UPSERT: &str = "ON CONFLICT (song_id, time) DO UPDATE SET
+    ms_played = COALESCE(listen.ms_played, excluded.ms_played),
+    skipped = COALESCE(listen.skipped, excluded.skipped),
+    platform = COALESCE(listen.platform, excluded.platform),
+    context = COALESCE(listen.context, excluded.context),
+    track_name = COALESCE(listen.track_name, excluded.track_name),
+    artist_ids = COALESCE(listen.artist_ids, excluded.artist_ids),
+    artist_names = COALESCE(listen.artist_names, excluded.artist_names),
+    album_id = COALESCE(listen.album_id, excluded.album_id),
+    album_name = COALESCE(listen.album_name, excluded.album_name),
+    duration_ms = COALESCE(listen.duration_ms, excluded.duration_ms)";
+
+fn insert_listen(conn: &Connection, listen: &Listen) -> Result<usize, CoolioError> {
+    let query_text = format!(
+        "INSERT INTO listen({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12) {}",
+        LISTEN_COLUMNS, LISTEN_UPSERT
+    );
+
+    let track = listen.track.as_ref();
+    Ok(conn.execute(
+        &query_text,
+        params![
+            listen.song_id,
+            listen.time,
+            listen.ms_played,
+            listen.skipped,
+            listen.platform,
+            listen.context,
+            track.map(|t| t.name.clone()),
+            track.map(|t| to_json(&t.artist_ids)).transpose()?,
+            track.map(|t| to_json(&t.artist_names)).transpose()?,
+            track.and_then(|t| t.album_id.clone()),
+            track.and_then(|t| t.album_name.clone()),
+            track.map(|t| t.duration_ms),
+        ],
+    )?)
+}
+
+fn listen_from_row(row: &Row) -> Result<Listen, CoolioError> {
+    let track = match (
+        row.get::<_, Option<String>>(6)?,
+        row.get::<_, Option<String>>(7)?,
+        row.get::<_, Option<String>>(8)?,
+        row.get::<_, Option<u32>>(11)?,
+    ) {
+        (Some(name), Some(artist_ids), Some(artist_names), Some(duration_ms)) => {
+            Some(TrackMetadata {
+                name,
+                artist_ids: serde_json::from_str(&artist_ids)?,
+                artist_names: serde_json::from_str(&artist_names)?,
+                album_id: row.get(9)?,
+                album_name: row.get(10)?,
+                duration_ms,
+            })
+        }
+        _ => None,
+    };
+
+    Ok(Listen {
+        song_id: row.get(0)?,
+        time: row.get(1)?,
+        ms_played: row.get(2)?,
+        skipped: row.get(3)?,
+        platform: row.get(4)?,
+        context: row.get(5)?,
+        track,
+    })
+}
+
+#[async_trait]
+impl Storage for Sqlite {
+    async fn add_history_batch(&self, listens: Vec<Listen>) -> Result<(), CoolioError> {
+        let mut conn = self.conn.lock().await;
+
+        let mut deduped = Vec::<Listen>::new();
+        merge_listens(&mut deduped, listens);
+
+        // either the whole batch is recorded or none of it
+        let tx = conn.transaction()?;
+        for l in &deduped {
+            if insert_listen(&tx, l)? != 1 {
+                return Err("not all values inserted".into());
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn get_history(&self) -> Result<Vec<Listen>, CoolioError> {
+        let query_text = format!("SELECT {} FROM listen ORDER BY time", LISTEN_COLUMNS);
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(&query_text)?;
+        let mut rows = stmt.query([])?;
+
+        let mut history = Vec::<Listen>::new();
+        while let Some(row) = rows.next()? {
+            history.push(listen_from_row(row)?);
+        }
+        Ok(history)
+    }
+
+    async fn dedupe_history(&self) -> Result<usize, CoolioError> {
+        // listens are unique from the start, there's nothing to remove
+        Ok(0)
+    }
+
+    async fn set_track_metadata(
+        &self,
+        tracks: &HashMap<String, TrackMetadata>,
+    ) -> Result<(), CoolioError> {
+        let query_text = "
+        UPDATE listen
+        SET track_name = ?2, artist_ids = ?3, artist_names = ?4,
+            album_id = ?5, album_name = ?6, duration_ms = ?7
+        WHERE song_id = ?1 AND track_name IS NULL";
+
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        for (song_id, t) in tracks {
+            tx.execute(
+                query_text,
+                params![
+                    song_id,
+                    t.name,
+                    to_json(&t.artist_ids)?,
+                    to_json(&t.artist_names)?,
+                    t.album_id,
+                    t.album_name,
+                    t.duration_ms,
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn get_last_listen(&self) -> Result<Listen, CoolioError> {
+        let query_text = format!(
+            "SELECT {} FROM listen ORDER BY time DESC LIMIT 1",
+            LISTEN_COLUMNS
+        );
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(&query_text)?;
+        let mut rows = stmt.query([])?;
+
+        match rows.next()? {
+            Some(row) => listen_from_row(row),
+            None => Err("no listens found".into()),
+        }
+    }
+
+    async fn add_gap(&self, gap: Gap) -> Result<(), CoolioError> {
+        let query_text = "INSERT INTO gap(from_time, to_time) VALUES (?1, ?2)";
+        let res = self
+            .conn
+            .lock()
+            .await
+            .execute(query_text, params![gap.from, gap.to])?;
+
+        if res != 1 {
+            Err("no values inserted".into())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn get_gaps(&self) -> Result<Vec<Gap>, CoolioError> {
+        let query_text = "SELECT from_time, to_time FROM gap ORDER BY from_time";
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(query_text)?;
+        let gaps = stmt
+            .query_map([], |row| {
+                Ok(Gap {
+                    from: row.get(0)?,
+                    to: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<Gap>, _>>()?;
+        Ok(gaps)
+    }
+
+    async fn create_playlist(&self, id: &str, name: &str) -> Result<(), CoolioError> {
+        let query_text =
+            "INSERT INTO playlist(playlist_id, playlist_name, artist_id) VALUES(?1, ?2, NULL)";
+        let res = self
+            .conn
+            .lock()
+            .await
+            .execute(query_text, params![id, name])?;
+        if res != 1 {
+            Err("error in inserting of playlist".into())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn get_playlists(&self) -> Result<Vec<Playlist>, CoolioError> {
+        let query_text = "
+        SELECT playlist_name, playlist_id, artist_id, allow_duplicates, rules, max_tracks,
+            max_age_days
+        FROM playlist
+        ORDER BY rowid";
+
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(query_text)?;
+        let mut rows = stmt.query([])?;
+
+        // a playlist has a row of its own and one for each linked artist
+        let mut playlists = Vec::<Playlist>::new();
+        while let Some(row) = rows.next()? {
+            let id: String = row.get(1)?;
+            let i = match playlists.iter().position(|p| p.id == id) {
+                Some(i) => i,
+                None => {
+                    playlists.push(Playlist {
+                        name: row.get(0)?,
+                        id,
+                        artists: vec![],
+                        automated: true,
+                        allow_duplicates: false,
+                        rules: None,
+                        retention: Retention::default(),
+                    });
+                    playlists.len() - 1
+                }
+            };
+            add_playlist_row(&mut playlists[i], row)?;
+        }
+
+        Ok(playlists)
+    }
+
+    async fn get_playlist(&self, name: &str) -> Result<Playlist, CoolioError> {
+        let query_text = "
+        SELECT playlist_name, playlist_id, artist_id, allow_duplicates, rules, max_tracks,
+            max_age_days
+        FROM playlist
+        WHERE playlist_name = ?1
+        ORDER BY rowid";
+
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(query_text)?;
+        let mut rows = stmt.query(params![name])?;
+
+        let mut playlist: Option<Playlist> = None;
+        while let Some(row) = rows.next()? {
+            let p = match playlist.as_mut() {
+                Some(p) => p,
+                None => playlist.insert(Playlist {
+                    id: row.get(1)?,
+                    artists: vec![],
+                    name: name.to_string(),
+                    automated: true,
+                    allow_duplicates: false,
+                    rules: None,
+                    retention: Retention::default(),
+                }),
+            };
+            add_playlist_row(p, row)?;
+        }
+
+        playlist.ok_or_else(|| "playlist doesnt exist".into())
+    }
+
+    async fn set_allow_duplicates(
+        &self,
+        playlist_id: &str,
+        allow: bool,
+    ) -> Result<(), CoolioError> {
+        let query_text = "UPDATE playlist SET allow_duplicates = ?2 WHERE playlist_id = ?1";
+        let conn = self.conn.lock().await;
+        update_playlist(&conn, query_text, params![playlist_id, allow])
+    }
+
+    async fn set_rules(
+        &self,
+        playlist_id: &str,
+        rules: Option<&ExclusionRules>,
+    ) -> Result<(), CoolioError> {
+        let query_text = "UPDATE playlist SET rules = ?2 WHERE playlist_id = ?1";
+        let rules = rules.map(to_json).transpose()?;
+        let conn = self.conn.lock().await;
+        update_playlist(&conn, query_text, params![playlist_id, rules])
+    }
+
+    async fn set_retention(
+        &self,
+        playlist_id: &str,
+        retention: &Retention,
+    ) -> Result<(), CoolioError> {
+        let query_text =
+            "UPDATE playlist SET max_tracks = ?2, max_age_days = ?3 WHERE playlist_id = ?1";
+        let conn = self.conn.lock().await;
+        update_playlist(
+            &conn,
+            query_text,
+            params![
+                playlist_id,
+                retention.max_tracks.map(|x| x as i64),
+                retention.max_age_days
+            ],
+        )
+    }
+
+    async fn link_artist(
+        &self,
+        playlist_id: &str,
+        playlist_name: &str,
+        artist_id: &str,
+        options: &LinkOptions,
+    ) -> Result<(), CoolioError> {
+        let query_text = "
+        INSERT INTO playlist(
+            playlist_id, playlist_name, artist_id, release_types, seed, include_features
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+
+        let res = self.conn.lock().await.execute(
+            query_text,
+            params![
+                playlist_id,
+                playlist_name,
+                artist_id,
+                to_json(&release_types(options))?,
+                options.seed as i64,
+                options.include_features,
+            ],
+        )?;
+
+        if res != 1 {
+            Err("artist not linked to playlist".into())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn unlink_artist(&self, playlist_id: &str, artist_id: &str) -> Result<(), CoolioError> {
+        let query_text = "DELETE FROM playlist WHERE playlist_id = ?1 AND artist_id = ?2";
+        let conn = self.conn.lock().await;
+        update_link(&conn, query_text, params![playlist_id, artist_id])
+    }
+
+    async fn get_checkpoint(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+    ) -> Result<Option<LinkCheckpoint>, CoolioError> {
+        let query_text = "
+        SELECT last_checked, checked_albums
+        FROM playlist
+        WHERE playlist_id = ?1 AND artist_id = ?2";
+
+        let (last_checked, albums) = self
+            .conn
+            .lock()
+            .await
+            .query_row(query_text, params![playlist_id, artist_id], |row| {
+                Ok((
+                    row.get::<_, Option<DateTime<Utc>>>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                ))
+            })
+            .optional()?
+            .ok_or("artist not linked to playlist")?;
+
+        let albums = match albums {
+            Some(albums) => serde_json::from_str(&albums)?,
+            None => vec![],
+        };
+        Ok(last_checked.map(|last_checked| LinkCheckpoint {
+            last_checked,
+            albums,
+        }))
+    }
+
+    async fn set_checkpoint(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+        checkpoint: &LinkCheckpoint,
+    ) -> Result<(), CoolioError> {
+        let query_text = "
+        UPDATE playlist SET last_checked = ?3, checked_albums = ?4
+        WHERE playlist_id = ?1 AND artist_id = ?2";
+
+        let conn = self.conn.lock().await;
+        update_link(
+            &conn,
+            query_text,
+            params![
+                playlist_id,
+                artist_id,
+                checkpoint.last_checked,
+                to_json(&checkpoint.albums)?,
+            ],
+        )
+    }
+
+    async fn get_link_options(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+    ) -> Result<LinkOptions, CoolioError> {
+        let query_text = "
+        SELECT release_types, seed, include_features
+        FROM playlist
+        WHERE playlist_id = ?1 AND artist_id = ?2";
+
+        let (types, seed, features) = self
+            .conn
+            .lock()
+            .await
+            .query_row(query_text, params![playlist_id, artist_id], |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    row.get::<_, Option<i64>>(1)?,
+                    row.get::<_, Option<bool>>(2)?,
+                ))
+            })
+            .optional()?
+            .ok_or("artist not linked to playlist")?;
+
+        let mut options = LinkOptions::default();
+        if let Some(types) = types {
+            let types: Vec<String> = serde_json::from_str(&types)?;
+            options.release_types = types.iter().map(|t| t.parse()).collect::<Result<_, _>>()?;
+        }
+        if let Some(seed) = seed {
+            options.seed = seed as usize;
+        }
+        if let Some(features) = features {
+            options.include_features = features;
+        }
+        Ok(options)
+    }
+
+    async fn set_link_options(
+        &self,
+        playlist_id: &str,
+        artist_id: &str,
+        options: &LinkOptions,
+    ) -> Result<(), CoolioError> {
+        let query_text = "
+        UPDATE playlist SET release_types = ?3, seed = ?4, include_features = ?5
+        WHERE playlist_id = ?1 AND artist_id = ?2";
+
+        let conn = self.conn.lock().await;
+        update_link(
+            &conn,
+            query_text,
+            params![
+                playlist_id,
+                artist_id,
+                to_json(&release_types(options))?,
+                options.seed as i64,
+                options.include_features,
+            ],
+        )
+    }
+}
+
+/// Runs a change to all the rows of a playlist.
+fn update_playlist(
+    conn: &Connection,
+    query_text: &str,
+    params: &[&dyn ToSql],
+) -> Result<(), CoolioError> {
+    if conn.execute(query_text, params)? == 0 {
+        Err("playlist doesnt exist".into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Runs a change to the row of a single link.
+fn update_link(
+    conn: &Connection,
+    query_text: &str,
+    params: &[&dyn ToSql],
+) -> Result<(), CoolioError> {
+    if conn.execute(query_text, params)? != 1 {
+        Err("artist not linked to playlist".into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Adds what a row of the playlist table says to the playlist.
+fn add_playlist_row(playlist: &mut Playlist, row: &Row) -> Result<(), CoolioError> {
+    if let Some(artist) = row.get(2)? {
+        playlist.artists.push(artist);
+    }
+    playlist.allow_duplicates |= row.get::<_, bool>(3)?;
+    if let Some(rules) = row.get::<_, Option<String>>(4)? {
+        if playlist.rules.is_none() {
+            playlist.rules = Some(serde_json::from_str(&rules)?);
+        }
+    }
+    if let Some(max_tracks) = row.get::<_, Option<i64>>(5)? {
+        playlist.retention.max_tracks = Some(max_tracks as usize);
+    }
+    if let Some(max_age_days) = row.get(6)? {
+        playlist.retention.max_age_days = Some(max_age_days);
+    }
+    Ok(())
+}
+
+fn to_json<T: serde::Serialize + ?Sized>(value: &T) -> Result<String, CoolioError> {
+    Ok(serde_json::to_string(value)?)
+}
+
+fn release_types(options: &LinkOptions) -> Vec<String> {
+    options
+        .release_types
+        .iter()
+        .map(ReleaseType::to_string)
+        .collect()
+}
@@ -0,0 +1,124 @@
+use std::{env, fs, str};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::auth::{callback_address, logout, new_client, receive_code, status};
+use crate::service::io::Interactor;
+use crate::settings::Spotify;
+
+fn test_conf(token_cache: &str) -> Spotify {
+    Spotify {
+        client_id: "client_id".to_string(),
+        client_secret: "client_secret".to_string(),
+        redirect_uri: "http://localhost:4815/callback".to_string(),
+        scopes: vec!["user-read-recently-played".to_string()],
+        token_cache: env::temp_dir()
+            .join(token_cache)
+            .to_str()
+            .unwrap()
+            .to_string(),
+    }
+}
+
+async fn browse(addr: &str, target: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, addr);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[test]
+fn test_auth_callback_address() {
+    assert_eq!(
+        callback_address("http://localhost:4815/callback").unwrap(),
+        ("localhost:4815".to_string(), "/callback".to_string())
+    );
+    assert_eq!(
+        callback_address("http://127.0.0.1").unwrap(),
+        ("127.0.0.1:80".to_string(), "/".to_string())
+    );
+    callback_address("https://localhost:4815/callback").unwrap_err();
+    callback_address("http:///callback").unwrap_err();
+}
+
+#[tokio::test]
+async fn test_auth_receive_code() {
+    let conf = test_conf("coolio_test_auth_receive_code");
+    let spotify = new_client(&conf);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let redirect_uri = format!("http://{}/callback", addr);
+
+    let state = spotify.oauth.state.clone();
+    let browser = tokio::spawn(async move {
+        let favicon = browse(&addr, "/favicon.ico").await;
+        let callback = browse(&addr, &format!("/callback?code=abc&state={}", state)).await;
+        (favicon, callback)
+    });
+
+    let code = receive_code(&spotify, &listener, &redirect_uri, "/callback")
+        .await
+        .unwrap();
+    assert_eq!(code, "abc");
+
+    let (favicon, callback) = browser.await.unwrap();
+    assert!(favicon.starts_with("HTTP/1.1 404"));
+    assert!(callback.starts_with("HTTP/1.1 200"));
+}
+
+#[tokio::test]
+async fn test_auth_receive_code_denied() {
+    let conf = test_conf("coolio_test_auth_receive_code_denied");
+    let spotify = new_client(&conf);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let redirect_uri = format!("http://{}/callback", addr);
+
+    let browser =
+        tokio::spawn(
+            async move { browse(&addr, "/callback?error=access_denied&state=whatever").await },
+        );
+
+    let err = receive_code(&spotify, &listener, &redirect_uri, "/callback")
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Spotify authorization failed: access_denied"
+    );
+    assert!(browser.await.unwrap().starts_with("HTTP/1.1 400"));
+}
+
+#[tokio::test]
+async fn test_auth_status_not_logged_in() {
+    let conf = test_conf("coolio_test_auth_status");
+    let _ = fs::remove_file(&conf.token_cache);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    status(&conf, &mut int).await.unwrap();
+
+    assert_eq!(str::from_utf8(&output).unwrap(), "Not logged in\n");
+}
+
+#[test]
+fn test_auth_logout() {
+    let conf = test_conf("coolio_test_auth_logout");
+    fs::write(&conf.token_cache, "{}").unwrap();
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    logout(&conf, &mut int).unwrap();
+    logout(&conf, &mut int).unwrap();
+
+    assert_eq!(
+        str::from_utf8(&output).unwrap(),
+        "Logged out\nNot logged in\n"
+    );
+    assert!(fs::metadata(&conf.token_cache).is_err());
+}
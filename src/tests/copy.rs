@@ -0,0 +1,190 @@
+use chrono::{TimeZone, Utc};
+
+use crate::models::{ExclusionRules, Gap, LinkCheckpoint, LinkOptions, ReleaseType, Retention};
+use crate::storage::copy::{copy, CopySummary};
+use crate::storage::mock::Mock as MockStorage;
+use crate::storage::{Storage, StorageBehavior};
+use crate::tests::listen;
+
+async fn source() -> StorageBehavior {
+    let st = StorageBehavior::from(MockStorage::new());
+    let listens = (0..2500)
+        .map(|i| listen(&format!("track_{}", i % 7), 2500 - i))
+        .collect();
+    st.add_history_batch(listens).await.unwrap();
+    st.add_gap(Gap {
+        from: Utc.timestamp_opt(10, 0).unwrap(),
+        to: Utc.timestamp_opt(20, 0).unwrap(),
+    })
+    .await
+    .unwrap();
+
+    st.create_playlist("maman_id", "maman").await.unwrap();
+    st.create_playlist("empty_id", "empty").await.unwrap();
+    st.set_allow_duplicates("maman_id", true).await.unwrap();
+    st.set_rules("maman_id", Some(&ExclusionRules::default()))
+        .await
+        .unwrap();
+    st.set_retention(
+        "maman_id",
+        &Retention {
+            max_tracks: Some(10),
+            max_age_days: None,
+        },
+    )
+    .await
+    .unwrap();
+    let options = LinkOptions {
+        release_types: vec![ReleaseType::Single],
+        seed: 1,
+        include_features: false,
+    };
+    st.link_artist("maman_id", "maman", "artist_1", &options)
+        .await
+        .unwrap();
+    st.link_artist("maman_id", "maman", "artist_2", &LinkOptions::default())
+        .await
+        .unwrap();
+    st.set_checkpoint(
+        "maman_id",
+        "artist_1",
+        &LinkCheckpoint {
+            last_checked: Utc.timestamp_opt(3000, 0).unwrap(),
+            albums: vec!["album_1".to_string()],
+        },
+    )
+    .await
+    .unwrap();
+    st
+}
+
+#[tokio::test]
+async fn test_storage_copy() {
+    let from = source().await;
+    let to = StorageBehavior::from(MockStorage::new());
+
+    let summary = copy(&from, &to, false).await.unwrap();
+    assert_eq!(
+        summary,
+        CopySummary {
+            listens: 2500,
+            gaps: 1,
+            playlists: 2,
+            links: 2,
+        }
+    );
+
+    let history = to.get_history().await.unwrap();
+    assert_eq!(history.len(), 2500);
+    // copied oldest first
+    assert_eq!(history[0], listen("track_0", 1));
+    assert_eq!(history[2499], listen("track_0", 2500));
+    assert_eq!(to.get_gaps().await.unwrap(), from.get_gaps().await.unwrap());
+
+    let playlists = to.get_playlists().await.unwrap();
+    assert_eq!(playlists.len(), 2);
+    assert_eq!(playlists[0].id, "maman_id");
+    assert_eq!(playlists[0].name, "maman");
+    assert_eq!(playlists[0].artists, vec!["artist_1", "artist_2"]);
+    assert!(playlists[0].allow_duplicates);
+    assert_eq!(playlists[0].rules, Some(ExclusionRules::default()));
+    assert_eq!(playlists[0].retention.max_tracks, Some(10));
+    assert_eq!(playlists[1].id, "empty_id");
+    assert_eq!(playlists[1].artists.len(), 0);
+    assert_eq!(playlists[1].rules, None);
+    for artist_id in ["artist_1", "artist_2"] {
+        assert_eq!(
+            to.get_link_options("maman_id", artist_id).await.unwrap(),
+            from.get_link_options("maman_id", artist_id).await.unwrap()
+        );
+        assert_eq!(
+            to.get_checkpoint("maman_id", artist_id).await.unwrap(),
+            from.get_checkpoint("maman_id", artist_id).await.unwrap()
+        );
+    }
+
+    // the target isn't empty anymore
+    copy(&from, &to, false).await.unwrap_err();
+    let summary = copy(&from, &to, true).await.unwrap();
+    assert_eq!(summary, CopySummary::default());
+    assert_eq!(to.get_history().await.unwrap().len(), 2500);
+}
+
+#[tokio::test]
+async fn test_storage_copy_merge() {
+    let from = source().await;
+    let to = StorageBehavior::from(MockStorage::new());
+
+    // as left by an interrupted copy, next to what the target had of its own
+    let mut copied = from.get_history().await.unwrap();
+    copied.sort_by_key(|l| l.time);
+    copied.truncate(1000);
+    copied.push(listen("track_own", 5000));
+    to.add_history_batch(copied).await.unwrap();
+    to.create_playlist("maman_id", "maman").await.unwrap();
+    to.set_retention(
+        "maman_id",
+        &Retention {
+            max_tracks: None,
+            max_age_days: Some(7),
+        },
+    )
+    .await
+    .unwrap();
+    to.link_artist("maman_id", "maman", "artist_1", &LinkOptions::default())
+        .await
+        .unwrap();
+    let own_options = LinkOptions {
+        seed: 9,
+        ..Default::default()
+    };
+    to.link_artist("maman_id", "maman", "artist_2", &own_options)
+        .await
+        .unwrap();
+
+    copy(&from, &to, false).await.unwrap_err();
+    assert_eq!(to.get_history().await.unwrap().len(), 1001);
+
+    let summary = copy(&from, &to, true).await.unwrap();
+    assert_eq!(
+        summary,
+        CopySummary {
+            listens: 1500,
+            gaps: 1,
+            playlists: 1,
+            links: 0,
+        }
+    );
+    assert_eq!(to.get_history().await.unwrap().len(), 2501);
+
+    let playlists = to.get_playlists().await.unwrap();
+    assert_eq!(playlists.len(), 2);
+    assert_eq!(playlists[0].artists, vec!["artist_1", "artist_2"]);
+    assert_eq!(playlists[1].name, "empty");
+    // the settings the target had are kept, and the missing ones are copied
+    assert!(playlists[0].allow_duplicates);
+    assert_eq!(playlists[0].rules, Some(ExclusionRules::default()));
+    assert_eq!(
+        playlists[0].retention,
+        Retention {
+            max_tracks: Some(10),
+            max_age_days: Some(7),
+        }
+    );
+    assert_eq!(
+        to.get_link_options("maman_id", "artist_1").await.unwrap(),
+        from.get_link_options("maman_id", "artist_1").await.unwrap()
+    );
+    assert_eq!(
+        to.get_checkpoint("maman_id", "artist_1").await.unwrap(),
+        from.get_checkpoint("maman_id", "artist_1").await.unwrap()
+    );
+    assert_eq!(
+        to.get_link_options("maman_id", "artist_2").await.unwrap(),
+        own_options
+    );
+
+    // merging again finds nothing missing
+    let summary = copy(&from, &to, true).await.unwrap();
+    assert_eq!(summary, CopySummary::default());
+}
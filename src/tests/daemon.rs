@@ -0,0 +1,86 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use cron::Schedule;
+
+use crate::service::daemon::{next_job, run, Job, Task};
+use crate::service::{Service, ServiceTrait};
+use crate::settings::Daemon;
+use crate::storage::mock::Mock as MockStorage;
+use crate::storage::StorageBehavior;
+use crate::tests::mock_spotify::MockSpotify;
+
+fn job(task: Task, expr: &str) -> Job {
+    Job::new(task, Schedule::from_str(expr).unwrap())
+}
+
+#[test]
+fn test_daemon_next_job() {
+    let jobs = vec![
+        job(Task::PlaylistsUpdate, "0 0 0 1 1 *"),
+        job(Task::HistoryUpdate, "* * * * * *"),
+        // never runs again
+        job(Task::HistoryUpdate, "0 0 0 1 1 * 2000"),
+    ];
+    assert_eq!(next_job(&jobs), Some(1));
+    assert_eq!(next_job(&jobs[2..]), None);
+}
+
+#[tokio::test]
+async fn test_daemon_run_shutdown() {
+    let runs = AtomicUsize::new(0);
+    let jobs = vec![job(Task::HistoryUpdate, "0 0 0 1 1 *")];
+
+    run(jobs, async { Ok(()) }, |_| async {
+        runs.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(runs.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn test_daemon_run_failing_job() {
+    let runs = AtomicUsize::new(0);
+    let jobs = vec![job(Task::HistoryUpdate, "* * * * * *")];
+
+    let shutdown = async {
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        Ok(())
+    };
+    run(jobs, shutdown, |task| {
+        assert_eq!(task, Task::HistoryUpdate);
+        runs.fetch_add(1, Ordering::SeqCst);
+        async { Err("update failed".into()) }
+    })
+    .await
+    .unwrap();
+
+    // failing runs don't stop the next ones
+    assert!(runs.load(Ordering::SeqCst) >= 2);
+}
+
+#[tokio::test]
+async fn test_daemon_no_upcoming_runs() {
+    let jobs = vec![job(Task::HistoryUpdate, "0 0 0 1 1 * 2000")];
+    run(jobs, std::future::pending(), |_| async { Ok(()) })
+        .await
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn test_daemon_wrong_schedule() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+
+    s.daemon(&Daemon {
+        history: "every half an hour".to_string(),
+        ..Daemon::default()
+    })
+    .await
+    .unwrap_err();
+}
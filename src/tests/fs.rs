@@ -0,0 +1,204 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::CoolioError;
+use crate::models::{LinkOptions, ReleaseType};
+use crate::settings::{FileFormat, LocalStorage};
+use crate::storage::fs::Fs;
+use crate::storage::Storage;
+use crate::tests::listen;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    path
+}
+
+async fn open(path: &Path, format: FileFormat) -> Result<Fs, CoolioError> {
+    Fs::new(LocalStorage {
+        path: path.to_str().unwrap().to_string(),
+        format,
+    })
+    .await
+}
+
+async fn new_storage(name: &str) -> (Fs, PathBuf) {
+    let path = temp_dir(name);
+    (open(&path, FileFormat::Csv).await.unwrap(), path)
+}
+
+#[tokio::test]
+async fn test_fs_partial_record() {
+    let (st, path) = new_storage("coolio_fs_partial").await;
+    st.add_history_batch(vec![listen("track_1", 100), listen("track_2", 200)])
+        .await
+        .unwrap();
+
+    // a crash in the middle of appending a listen
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(path.join("history"))
+        .unwrap();
+    file.write_all(b"track_3,1970-01-01T00:0").unwrap();
+
+    let history = st.get_history().await.unwrap();
+    assert_eq!(
+        history,
+        vec![listen("track_1", 100), listen("track_2", 200)]
+    );
+
+    st.add_history_batch(vec![listen("track_4", 400)])
+        .await
+        .unwrap();
+    let history = st.get_history().await.unwrap();
+    assert_eq!(
+        history,
+        vec![
+            listen("track_1", 100),
+            listen("track_2", 200),
+            listen("track_4", 400)
+        ]
+    );
+    assert_eq!(st.get_last_listen().await.unwrap(), listen("track_4", 400));
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_fs_rewrite() {
+    let (st, path) = new_storage("coolio_fs_rewrite").await;
+    st.create_playlist("maman_id", "maman").await.unwrap();
+    for artist in ["artist_1", "artist_2", "artist_3"] {
+        st.link_artist("maman_id", "maman", artist, &LinkOptions::default())
+            .await
+            .unwrap();
+    }
+    // left behind by a rewrite that crashed before renaming it
+    fs::write(path.join("links.tmp"), "garbage").unwrap();
+
+    st.unlink_artist("maman_id", "artist_2").await.unwrap();
+    let playlist = st.get_playlist("maman").await.unwrap();
+    assert_eq!(playlist.artists, vec!["artist_1", "artist_3"]);
+    assert!(!path.join("links.tmp").exists());
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_fs_concurrent_writes() {
+    let (_, path) = new_storage("coolio_fs_concurrent").await;
+    let mut handles = vec![];
+    for i in 0..8 {
+        // every writer is on its own, like separate cron jobs
+        let st = open(&path, FileFormat::Csv).await.unwrap();
+        handles.push(tokio::spawn(async move {
+            for j in 0..10 {
+                st.add_history_batch(vec![listen(&format!("track_{}", i), i * 100 + j)])
+                    .await
+                    .unwrap();
+            }
+        }));
+    }
+    for h in handles {
+        h.await.unwrap();
+    }
+
+    let st = open(&path, FileFormat::Csv).await.unwrap();
+    assert_eq!(st.get_history().await.unwrap().len(), 80);
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_fs_upgrade() {
+    let path = temp_dir("coolio_fs_upgrade");
+    // written before the files had a header, some before the metadata columns
+    fs::write(
+        path.join("history"),
+        "track_1,1970-01-01T00:01:40Z\n\
+         track_2,1970-01-01T00:03:20Z,1000,false,linux,,track 2,\"[\"\"artist_1\"\"]\",\"[\"\"kendrick lamar\"\"]\",album_1,,180000\n",
+    )
+    .unwrap();
+    fs::write(path.join("playlist"), "maman_id,maman,true,,50,\n").unwrap();
+    fs::write(path.join("links"), "maman_id,artist_1,,,album,3,false\n").unwrap();
+
+    let st = open(&path, FileFormat::Csv).await.unwrap();
+    let history = fs::read_to_string(path.join("history")).unwrap();
+    assert!(history.starts_with("#coolio history v2 csv\nsong_id,time,ms_played,"));
+    let links = fs::read_to_string(path.join("links")).unwrap();
+    assert!(links.starts_with("#coolio links v2 csv\nplaylist_id,artist_id,"));
+
+    let history = st.get_history().await.unwrap();
+    assert_eq!(history[0], listen("track_1", 100));
+    assert_eq!(history[1].ms_played, Some(1000));
+    assert_eq!(
+        history[1].track.as_ref().unwrap().artist_ids,
+        vec!["artist_1"]
+    );
+    let playlist = st.get_playlist("maman").await.unwrap();
+    assert!(playlist.allow_duplicates);
+    assert_eq!(playlist.retention.max_tracks, Some(50));
+    assert_eq!(playlist.artists, vec!["artist_1"]);
+    let options = st.get_link_options("maman_id", "artist_1").await.unwrap();
+    assert_eq!(options.release_types, vec![ReleaseType::Album]);
+    assert_eq!(options.seed, 3);
+
+    // reopening finds everything up to date
+    st.add_history_batch(vec![listen("track_3", 300)])
+        .await
+        .unwrap();
+    let st = open(&path, FileFormat::Csv).await.unwrap();
+    assert_eq!(st.get_history().await.unwrap().len(), 3);
+
+    // and files from a newer coolio are left alone
+    fs::write(path.join("gaps"), "#coolio gaps v3 csv\nfrom,to\n").unwrap();
+    assert!(open(&path, FileFormat::Csv).await.is_err());
+    // version 1 never had a header
+    fs::write(path.join("gaps"), "#coolio gaps v1 csv\nfrom,to\n").unwrap();
+    assert!(open(&path, FileFormat::Csv).await.is_err());
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_fs_jsonl() {
+    let (st, path) = new_storage("coolio_fs_jsonl").await;
+    st.add_history_batch(vec![listen("track_1", 100), listen("track_2", 200)])
+        .await
+        .unwrap();
+    st.create_playlist("maman_id", "maman").await.unwrap();
+
+    // switching the format in the settings converts the files
+    let st = open(&path, FileFormat::Jsonl).await.unwrap();
+    let history = fs::read_to_string(path.join("history")).unwrap();
+    assert!(history.starts_with("#coolio history v2 jsonl\n{\"song_id\":\"track_1\","));
+    st.add_history_batch(vec![listen("track_3", 300)])
+        .await
+        .unwrap();
+    st.link_artist("maman_id", "maman", "artist_1", &LinkOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        st.get_history().await.unwrap(),
+        vec![
+            listen("track_1", 100),
+            listen("track_2", 200),
+            listen("track_3", 300)
+        ]
+    );
+    let playlist = st.get_playlist("maman").await.unwrap();
+    assert_eq!(playlist.artists, vec!["artist_1"]);
+    assert_eq!(
+        fs::read_to_string(path.join("links"))
+            .unwrap()
+            .lines()
+            .count(),
+        2
+    );
+
+    fs::remove_dir_all(&path).unwrap();
+}
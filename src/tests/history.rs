@@ -1,8 +1,15 @@
-use crate::models::ThrowbackPeriod;
+use std::path::PathBuf;
+use std::{env, fs, str};
+
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+
+use crate::models::{Gap, HistoryWindow, Listen, ThrowbackPeriod};
+use crate::service::io::Interactor;
 use crate::service::{Service, ServiceTrait};
 use crate::storage::mock::Mock as MockStorage;
-use crate::storage::StorageBehavior;
+use crate::storage::{Storage, StorageBehavior};
 use crate::tests::mock_spotify::MockSpotify;
+use crate::tests::played;
 
 #[tokio::test]
 async fn test_history_update() {
@@ -19,6 +26,141 @@ async fn test_history_update() {
     assert_eq!(listens[1].song_id, "track_1");
 }
 
+#[tokio::test]
+async fn test_history_update_overlapping() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+
+    s.history_update().await.unwrap();
+    // an overlapping update records the same listens again
+    let recorded = st_to.get_history().await.unwrap();
+    st_to.add_history_batch(recorded.clone()).await.unwrap();
+    st_to.add_history_batch(recorded).await.unwrap();
+
+    let st = st_to.as_mock().unwrap();
+    let listens = &st.state.lock().await.listens;
+    assert_eq!(listens.len(), 9);
+}
+
+#[tokio::test]
+async fn test_history_update_gap() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    let start = Utc.with_ymd_and_hms(2022, 1, 1, 12, 0, 0).unwrap();
+    st_to
+        .add_history_batch(vec![played("track_1", start)])
+        .await
+        .unwrap();
+
+    // 60 plays since the last update, only the last 50 of them can be fetched
+    sp.state.lock().await.recently_played = Some(
+        (1..=60)
+            .map(|i| played("track_2", start + Duration::minutes(3 * i)))
+            .collect(),
+    );
+    s.history_update().await.unwrap();
+
+    {
+        let st = st_to.as_mock().unwrap();
+        let state = st.state.lock().await;
+        assert_eq!(state.listens.len(), 51);
+        assert_eq!(
+            state.gaps,
+            vec![Gap {
+                from: start,
+                to: start + Duration::minutes(33),
+            }]
+        );
+    }
+
+    // the next update picks up from the last recorded listen
+    s.history_update().await.unwrap();
+    s.history_gaps(&mut int).await.unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split.len(), 3);
+    assert_eq!(split[0], "Gaps: 1");
+    assert_eq!(split[1], "\t2022-01-01 12:00 - 2022-01-01 12:33 (0h 33m)");
+}
+
+#[tokio::test]
+async fn test_history_update_no_gap() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    let start = Utc.with_ymd_and_hms(2022, 1, 1, 12, 0, 0).unwrap();
+    st_to
+        .add_history_batch(vec![played("track_1", start)])
+        .await
+        .unwrap();
+
+    sp.state.lock().await.recently_played = Some(
+        (1..=49)
+            .map(|i| played("track_2", start + Duration::minutes(3 * i)))
+            .collect(),
+    );
+    s.history_update().await.unwrap();
+    s.history_gaps(&mut int).await.unwrap();
+
+    let st = st_to.as_mock().unwrap();
+    assert_eq!(st.state.lock().await.listens.len(), 50);
+
+    let output_str = str::from_utf8(&output).unwrap();
+    assert_eq!(output_str, "Gaps: 0\n");
+}
+
+#[tokio::test]
+async fn test_history_dedupe() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.history_update().await.unwrap();
+    {
+        // as recorded by overlapping updates before listens were deduplicated
+        let st = st_to.as_mock().unwrap();
+        let listens = &mut st.state.lock().await.listens;
+        let mut repeated = listens[..3].to_vec();
+        repeated[0].ms_played = Some(1000);
+        listens.append(&mut repeated);
+    }
+    s.history_dedupe(&mut int).await.unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Removed duplicates: 3");
+
+    {
+        let st = st_to.as_mock().unwrap();
+        let listens = &st.state.lock().await.listens;
+        assert_eq!(listens.len(), 9);
+        assert_eq!(listens[0].song_id, "track_2");
+        assert_eq!(listens[0].ms_played, Some(1000));
+    }
+
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+    s.history_dedupe(&mut int).await.unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Removed duplicates: 0");
+}
+
 #[tokio::test]
 async fn test_history_throwback_year() {
     let st_to = StorageBehavior::from(MockStorage::new());
@@ -93,3 +235,359 @@ async fn test_history_throwback_size() {
     assert_eq!(tracks[0].track.id, "track_1");
     assert_eq!(tracks[1].track.id, "track_2");
 }
+
+fn write_export(dir: &str) -> PathBuf {
+    let path = env::temp_dir().join(dir);
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    fs::write(
+        path.join("Streaming_History_Audio_2019-2020_0.json"),
+        r#"[
+            {"ts": "2020-02-05T19:28:04Z", "platform": "linux", "ms_played": 180000,
+             "spotify_track_uri": "track_1", "skipped": null},
+            {"ts": "2019-01-01T10:00:00Z", "platform": "android", "ms_played": 1000,
+             "spotify_track_uri": "track_2", "skipped": true},
+            {"ts": "2019-01-01T11:00:00Z", "platform": "android", "ms_played": 5000,
+             "spotify_track_uri": null, "skipped": null}
+        ]"#,
+    )
+    .unwrap();
+    fs::write(
+        path.join("StreamingHistory0.json"),
+        r#"[
+            {"endTime": "2019-03-01 12:00", "artistName": "kali uchis",
+             "trackName": "track_13", "msPlayed": 200000},
+            {"endTime": "2019-03-01 12:05", "artistName": "nobody",
+             "trackName": "unknown", "msPlayed": 200000}
+        ]"#,
+    )
+    .unwrap();
+    fs::write(path.join("Userdata.json"), "{}").unwrap();
+    path
+}
+
+#[tokio::test]
+async fn test_history_import() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let dir = write_export("coolio_test_history_import");
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.history_update().await.unwrap();
+    s.history_import(&mut int, dir.to_str().unwrap())
+        .await
+        .unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Imported listens: 2");
+    assert_eq!(split[1], "Already recorded: 1");
+    assert_eq!(split[2], "Tracks not found: 1");
+
+    {
+        let st = st_to.as_mock().unwrap();
+        let listens = &st.state.lock().await.listens;
+        assert_eq!(listens.len(), 11);
+        assert_eq!(listens[9].song_id, "track_2");
+        assert_eq!(listens[9].ms_played, Some(1000));
+        assert_eq!(listens[9].skipped, Some(true));
+        assert_eq!(listens[9].platform, Some("android".to_string()));
+        assert_eq!(listens[10].song_id, "track_13");
+        assert_eq!(listens[10].ms_played, Some(200000));
+    }
+
+    // importing the same export again doesn't add anything
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+    s.history_import(&mut int, dir.to_str().unwrap())
+        .await
+        .unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Imported listens: 0");
+    assert_eq!(split[1], "Already recorded: 3");
+
+    let st = st_to.as_mock().unwrap();
+    assert_eq!(st.state.lock().await.listens.len(), 11);
+}
+
+#[tokio::test]
+async fn test_history_import_overlapping() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    // the API records listens to the millisecond
+    sp.state.lock().await.recently_played = Some(vec![
+        played("track_1", Utc.timestamp_millis_opt(1580930884312).unwrap()),
+        played("track_2", Utc.timestamp_millis_opt(1580931065120).unwrap()),
+    ]);
+    let s = Service::new(&sp, &st_to);
+    s.history_update().await.unwrap();
+
+    let path = env::temp_dir().join("coolio_test_history_import_overlapping");
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    fs::write(
+        path.join("Streaming_History_Audio_2020_0.json"),
+        r#"[
+            {"ts": "2020-02-05T19:28:04Z", "platform": "linux", "ms_played": 180000,
+             "spotify_track_uri": "track_1", "skipped": null},
+            {"ts": "2020-02-05T19:40:00Z", "platform": "linux", "ms_played": 180000,
+             "spotify_track_uri": "track_1", "skipped": null}
+        ]"#,
+    )
+    .unwrap();
+    fs::write(
+        path.join("StreamingHistory0.json"),
+        r#"[
+            {"endTime": "2020-02-05 19:32", "artistName": "kendrick lamar",
+             "trackName": "track_2", "msPlayed": 150000}
+        ]"#,
+    )
+    .unwrap();
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.history_import(&mut int, path.to_str().unwrap())
+        .await
+        .unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    // only the later play of track_1 wasn't recorded through the API
+    assert_eq!(split[0], "Imported listens: 1");
+    assert_eq!(split[1], "Already recorded: 2");
+
+    let st = st_to.as_mock().unwrap();
+    let listens = &st.state.lock().await.listens;
+    assert_eq!(listens.len(), 3);
+    assert_eq!(listens[2].song_id, "track_1");
+    assert_eq!(listens[2].time, Utc.timestamp_opt(1580931600, 0).unwrap());
+}
+
+#[tokio::test]
+async fn test_history_import_search_failed() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    sp.state.lock().await.failing_searches = vec!["track_13".to_string()];
+    let s = Service::new(&sp, &st_to);
+    let dir = write_export("coolio_test_history_import_search_failed");
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.history_import(&mut int, dir.to_str().unwrap())
+        .await
+        .unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Imported listens: 2");
+    assert_eq!(split[1], "Already recorded: 0");
+    assert_eq!(split[2], "Tracks not found: 2");
+}
+
+#[tokio::test]
+async fn test_history_import_no_export() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let path = env::temp_dir().join("coolio_test_history_import_empty");
+    fs::create_dir_all(&path).unwrap();
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.history_import(&mut int, path.to_str().unwrap())
+        .await
+        .unwrap_err();
+    assert_eq!(output.len(), 0);
+}
+
+#[tokio::test]
+async fn test_history_stats() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.history_update().await.unwrap();
+    s.history_stats(&mut int, HistoryWindow::default(), None)
+        .await
+        .unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split.len(), 14);
+    assert_eq!(split[0], "Listens: 9");
+    assert_eq!(split[1], "Distinct tracks: 4");
+    assert_eq!(split[2], "Listening time: 0h 27m");
+    assert_eq!(split[3], "Top tracks:");
+    assert_eq!(split[4], "\t1. track_1 name - kendrick lamar (3 listens)");
+    assert_eq!(split[5], "\t2. track_3 name - kendrick lamar (3 listens)");
+    assert_eq!(split[6], "\t3. track_2 name - kendrick lamar (2 listens)");
+    assert_eq!(split[7], "\t4. track_4 name - kendrick lamar (1 listens)");
+    assert_eq!(split[8], "Top artists:");
+    assert_eq!(split[9], "\t1. kendrick lamar (9 listens)");
+    assert_eq!(split[10], "Top albums:");
+    assert_eq!(split[11], "\t1. album_1_1 name (8 listens)");
+    assert_eq!(split[12], "\t2. album_1_2 name (1 listens)");
+    assert_eq!(split[13], "");
+}
+
+#[tokio::test]
+async fn test_history_stats_window() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.history_update().await.unwrap();
+    let window = HistoryWindow {
+        period: Some(ThrowbackPeriod::Weeks(1)),
+        ..Default::default()
+    };
+    s.history_stats(&mut int, window, Some(1)).await.unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split.len(), 10);
+    assert_eq!(split[0], "Listens: 3");
+    assert_eq!(split[1], "Distinct tracks: 2");
+    assert_eq!(split[4], "\t1. track_3 name - kendrick lamar (2 listens)");
+    assert_eq!(split[8], "\t1. album_1_1 name (2 listens)");
+
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+    let window = HistoryWindow {
+        period: None,
+        from: NaiveDate::from_ymd_opt(2020, 2, 5),
+        to: NaiveDate::from_ymd_opt(2020, 2, 5),
+    };
+    s.history_stats(&mut int, window, None).await.unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Listens: 6");
+    assert_eq!(split[1], "Distinct tracks: 3");
+}
+
+#[tokio::test]
+async fn test_history_stats_wrong_window() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    let window = HistoryWindow {
+        period: None,
+        from: NaiveDate::from_ymd_opt(2021, 2, 5),
+        to: NaiveDate::from_ymd_opt(2020, 2, 5),
+    };
+    s.history_stats(&mut int, window, None).await.unwrap_err();
+    assert_eq!(output.len(), 0);
+}
+
+#[tokio::test]
+async fn test_history_backfill() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.history_update().await.unwrap();
+    st_to
+        .add_history_batch(vec![Listen {
+            song_id: "unknown".to_string(),
+            time: Utc::now(),
+            ms_played: None,
+            skipped: None,
+            platform: None,
+            context: None,
+            track: None,
+        }])
+        .await
+        .unwrap();
+    s.history_backfill(&mut int).await.unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Backfilled tracks: 4");
+    assert_eq!(split[1], "Backfilled listens: 9");
+    assert_eq!(split[2], "Tracks not found: 1");
+
+    {
+        let st = st_to.as_mock().unwrap();
+        let listens = &st.state.lock().await.listens;
+        let track = listens[0].track.as_ref().unwrap();
+        assert_eq!(track.name, "track_2 name");
+        assert_eq!(track.artist_names, vec!["kendrick lamar"]);
+        assert_eq!(track.album_id, Some("album_1_1".to_string()));
+        assert_eq!(track.duration_ms, 180000);
+        assert!(listens[9].track.is_none());
+    }
+
+    // nothing left to fill in except the unknown track
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+    s.history_backfill(&mut int).await.unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Backfilled tracks: 0");
+    assert_eq!(split[2], "Tracks not found: 1");
+}
+
+#[tokio::test]
+async fn test_history_backfill_rejected() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.history_update().await.unwrap();
+    st_to
+        .add_history_batch(vec![Listen {
+            song_id: "spotify:episode:1".to_string(),
+            time: Utc::now(),
+            ms_played: None,
+            skipped: None,
+            platform: None,
+            context: None,
+            track: None,
+        }])
+        .await
+        .unwrap();
+    sp.state
+        .lock()
+        .await
+        .rejected_tracks
+        .push("spotify:episode:1".to_string());
+    s.history_backfill(&mut int).await.unwrap();
+
+    // the rest of the batch is still filled in
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Backfilled tracks: 4");
+    assert_eq!(split[1], "Backfilled listens: 9");
+    assert_eq!(split[2], "Tracks not found: 0");
+    assert_eq!(split[3], "Rejected by Spotify: 1");
+    assert_eq!(
+        split[4],
+        "\tspotify:episode:1: invalid id: spotify:episode:1"
+    );
+}
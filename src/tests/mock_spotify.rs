@@ -7,13 +7,28 @@ use crate::{
     error::CoolioError,
     models::Listen,
     service::spotify::{
-        SimpleAlbum, SimpleArtist, SimplePlayable, SimplePlaylist, SimpleTrack, Spotify,
+        DatePrecision, SimpleAlbum, SimpleArtist, SimplePage, SimplePlayable, SimplePlaylist,
+        SimpleTrack, Spotify, PLAYLIST_ADD_LIMIT, PLAYLIST_ITEMS_LIMIT, PLAYLIST_REMOVE_LIMIT,
     },
 };
 
 #[derive(Clone, Default)]
 pub struct SpotifyState {
     pub playlists: Vec<SimplePlaylist>,
+    // replaces the default recently played tracks when set
+    pub recently_played: Option<Vec<Listen>>,
+    pub playlist_items_calls: usize,
+    // the size and position of every batch of added tracks
+    pub added_batches: Vec<(usize, u32)>,
+    // the number of the batch that fails to be added, counting from 1
+    pub failing_batch: Option<usize>,
+    // the size of every batch of removed tracks
+    pub removed_batches: Vec<usize>,
+    // track ids that fail any request for tracks they are part of
+    pub rejected_tracks: Vec<String>,
+    // track names that fail to be searched for
+    pub failing_searches: Vec<String>,
+    pub artist_calls: usize,
 }
 
 struct TestAlbum {
@@ -26,6 +41,19 @@ struct TestArtist {
     pub albums: Vec<TestAlbum>,
 }
 
+fn test_track(art: &TestArtist, alb: &TestAlbum, id: &str) -> SimpleTrack {
+    SimpleTrack {
+        id: id.to_string(),
+        name: format!("{} name", id),
+        artists: vec![art.artist.clone()],
+        album_id: Some(alb.album.id.clone()),
+        album_name: Some(format!("{} name", alb.album.id)),
+        duration_ms: 180000,
+        isrc: Some(format!("{} isrc", id)),
+        explicit: id == "track_3",
+    }
+}
+
 pub struct MockSpotify {
     pub state: Mutex<SpotifyState>,
     artists: Vec<TestArtist>,
@@ -47,7 +75,9 @@ impl MockSpotify {
                         TestAlbum {
                             album: SimpleAlbum {
                                 id: "album_1_1".to_string(),
-                                release_date: Utc.timestamp(1431648000, 0),
+                                name: "album_1_1 name".to_string(),
+                                release_date: Utc.timestamp_opt(1431648000, 0).unwrap(),
+                                release_precision: DatePrecision::Day,
                             },
                             tracks: vec![
                                 "track_1".to_string(),
@@ -58,7 +88,9 @@ impl MockSpotify {
                         TestAlbum {
                             album: SimpleAlbum {
                                 id: "album_1_2".to_string(),
+                                name: "album_1_2 name".to_string(),
                                 release_date: Utc::now() + Duration::days(1),
+                                release_precision: DatePrecision::Day,
                             },
                             tracks: vec![
                                 "track_4".to_string(),
@@ -79,7 +111,9 @@ impl MockSpotify {
                         TestAlbum {
                             album: SimpleAlbum {
                                 id: "album_2_1".to_string(),
-                                release_date: Utc.timestamp(1431648000, 0),
+                                name: "album_2_1 name".to_string(),
+                                release_date: Utc.timestamp_opt(1431648000, 0).unwrap(),
+                                release_precision: DatePrecision::Day,
                             },
                             tracks: vec![
                                 "track_7".to_string(),
@@ -90,7 +124,9 @@ impl MockSpotify {
                         TestAlbum {
                             album: SimpleAlbum {
                                 id: "album_2_2".to_string(),
-                                release_date: Utc.timestamp(1432648000, 0),
+                                name: "album_2_2 name".to_string(),
+                                release_date: Utc.timestamp_opt(1432648000, 0).unwrap(),
+                                release_precision: DatePrecision::Day,
                             },
                             tracks: vec![
                                 "track_10".to_string(),
@@ -111,7 +147,9 @@ impl MockSpotify {
                         TestAlbum {
                             album: SimpleAlbum {
                                 id: "album_3_1".to_string(),
-                                release_date: Utc.timestamp(1431648000, 0),
+                                name: "album_3_1 name".to_string(),
+                                release_date: Utc.timestamp_opt(1431648000, 0).unwrap(),
+                                release_precision: DatePrecision::Day,
                             },
                             tracks: vec![
                                 "track_13".to_string(),
@@ -122,7 +160,9 @@ impl MockSpotify {
                         TestAlbum {
                             album: SimpleAlbum {
                                 id: "album_3_2".to_string(),
-                                release_date: Utc.timestamp(1432648000, 0),
+                                name: "album_3_2 name".to_string(),
+                                release_date: Utc.timestamp_opt(1432648000, 0).unwrap(),
+                                release_precision: DatePrecision::Day,
                             },
                             tracks: vec![
                                 "track_16".to_string(),
@@ -143,7 +183,9 @@ impl MockSpotify {
                         TestAlbum {
                             album: SimpleAlbum {
                                 id: "album_4_1".to_string(),
-                                release_date: Utc.timestamp(1431648000, 0),
+                                name: "album_4_1 name".to_string(),
+                                release_date: Utc.timestamp_opt(1431648000, 0).unwrap(),
+                                release_precision: DatePrecision::Day,
                             },
                             tracks: vec![
                                 "track_19".to_string(),
@@ -154,7 +196,9 @@ impl MockSpotify {
                         TestAlbum {
                             album: SimpleAlbum {
                                 id: "album_4_2".to_string(),
-                                release_date: Utc.timestamp(1432648000, 0),
+                                name: "album_4_2 name".to_string(),
+                                release_date: Utc.timestamp_opt(1432648000, 0).unwrap(),
+                                release_precision: DatePrecision::Day,
                             },
                             tracks: vec![
                                 "track_22".to_string(),
@@ -175,7 +219,9 @@ impl MockSpotify {
                         TestAlbum {
                             album: SimpleAlbum {
                                 id: "album_5_1".to_string(),
-                                release_date: Utc.timestamp(1431648000, 0),
+                                name: "album_5_1 name".to_string(),
+                                release_date: Utc.timestamp_opt(1431648000, 0).unwrap(),
+                                release_precision: DatePrecision::Day,
                             },
                             tracks: vec![
                                 "track_25".to_string(),
@@ -186,7 +232,9 @@ impl MockSpotify {
                         TestAlbum {
                             album: SimpleAlbum {
                                 id: "album_5_2".to_string(),
-                                release_date: Utc.timestamp(1432648000, 0),
+                                name: "album_5_2 name".to_string(),
+                                release_date: Utc.timestamp_opt(1432648000, 0).unwrap(),
+                                release_precision: DatePrecision::Day,
                             },
                             tracks: vec![
                                 "track_28".to_string(),
@@ -196,54 +244,167 @@ impl MockSpotify {
                         },
                     ],
                 },
+                TestArtist {
+                    artist: SimpleArtist {
+                        id: "artist_6".to_string(),
+                        name: "bonobo".to_string(),
+                        num_followers: 87261,
+                        popularity: 65,
+                    },
+                    // only the year or month of these releases is known
+                    albums: vec![
+                        TestAlbum {
+                            album: SimpleAlbum {
+                                id: "album_6_1".to_string(),
+                                name: "album_6_1 name".to_string(),
+                                release_date: Utc.with_ymd_and_hms(2016, 1, 1, 0, 0, 0).unwrap(),
+                                release_precision: DatePrecision::Year,
+                            },
+                            tracks: vec!["track_31".to_string(), "track_32".to_string()],
+                        },
+                        TestAlbum {
+                            album: SimpleAlbum {
+                                id: "album_6_2".to_string(),
+                                name: "album_6_2 name".to_string(),
+                                release_date: Utc.with_ymd_and_hms(2016, 7, 1, 0, 0, 0).unwrap(),
+                                release_precision: DatePrecision::Month,
+                            },
+                            tracks: vec!["track_33".to_string(), "track_34".to_string()],
+                        },
+                        TestAlbum {
+                            album: SimpleAlbum {
+                                id: "album_6_3".to_string(),
+                                name: "album_6_3 name".to_string(),
+                                release_date: Utc.with_ymd_and_hms(2017, 1, 1, 0, 0, 0).unwrap(),
+                                release_precision: DatePrecision::Year,
+                            },
+                            tracks: vec!["track_35".to_string(), "track_36".to_string()],
+                        },
+                    ],
+                },
             ],
         }
     }
+
+    /// The playlist with all its tracks, numbered by their positions.
+    async fn playlist(&self, id: &str) -> Result<SimplePlaylist, CoolioError> {
+        let ps = self.state.lock().await.playlists.to_vec();
+        for mut p in ps {
+            if p.id == id {
+                p.num_tracks = p.tracks.len() as u32;
+                for (i, t) in p.tracks.iter_mut().enumerate() {
+                    t.position = i as u32;
+                }
+                return Ok(p);
+            }
+        }
+        Err("playlist doesnt exist".into())
+    }
 }
 
 #[async_trait]
 impl Spotify for MockSpotify {
     async fn current_user_recently_played(
         &self,
-        _limit: u32,
-        _time_limit: Option<DateTime<Utc>>,
+        limit: u32,
+        time_limit: Option<DateTime<Utc>>,
     ) -> Result<Vec<Listen>, CoolioError> {
+        if let Some(played) = &self.state.lock().await.recently_played {
+            let mut played = played
+                .iter()
+                .filter(|l| match time_limit {
+                    Some(t) => l.time > t,
+                    None => true,
+                })
+                .cloned()
+                .collect::<Vec<Listen>>();
+            // only the most recent plays are available
+            played.sort_by_key(|l| std::cmp::Reverse(l.time));
+            played.truncate(limit as usize);
+            return Ok(played);
+        }
+
         Ok(vec![
             Listen {
                 song_id: "track_2".to_string(),
-                time: Utc.timestamp(1580930644, 0),
+                time: Utc.timestamp_opt(1580930644, 0).unwrap(),
+                ms_played: None,
+                skipped: None,
+                platform: None,
+                context: None,
+                track: None,
             },
             Listen {
                 song_id: "track_1".to_string(),
-                time: Utc.timestamp(1580930644, 0),
+                time: Utc.timestamp_opt(1580930884, 0).unwrap(),
+                ms_played: None,
+                skipped: None,
+                platform: None,
+                context: None,
+                track: None,
             },
             Listen {
                 song_id: "track_1".to_string(),
-                time: Utc.timestamp(1580930644, 0),
+                time: Utc.timestamp_opt(1580931124, 0).unwrap(),
+                ms_played: None,
+                skipped: None,
+                platform: None,
+                context: None,
+                track: None,
             },
             Listen {
                 song_id: "track_1".to_string(),
-                time: Utc.timestamp(1580930644, 0),
+                time: Utc.timestamp_opt(1580931364, 0).unwrap(),
+                ms_played: None,
+                skipped: None,
+                platform: None,
+                context: None,
+                track: None,
             },
             Listen {
                 song_id: "track_2".to_string(),
-                time: Utc.timestamp(1580930644, 0),
+                time: Utc.timestamp_opt(1580931604, 0).unwrap(),
+                ms_played: None,
+                skipped: None,
+                platform: None,
+                context: None,
+                track: None,
             },
             Listen {
                 song_id: "track_4".to_string(),
                 time: Utc::now() - Duration::days(2),
+                ms_played: None,
+                skipped: None,
+                platform: None,
+                context: None,
+                track: None,
             },
             Listen {
                 song_id: "track_3".to_string(),
-                time: Utc.timestamp(1580930644, 0),
+                time: Utc.timestamp_opt(1580931844, 0).unwrap(),
+                ms_played: None,
+                skipped: None,
+                platform: None,
+                context: None,
+                track: None,
             },
             Listen {
                 song_id: "track_3".to_string(),
                 time: Utc::now() - Duration::days(3),
+                ms_played: None,
+                skipped: None,
+                platform: None,
+                context: None,
+                track: None,
             },
             Listen {
                 song_id: "track_3".to_string(),
                 time: Utc::now(),
+                ms_played: None,
+                skipped: None,
+                platform: None,
+                context: None,
+                track: None,
             },
         ])
     }
@@ -252,27 +413,41 @@ impl Spotify for MockSpotify {
         let mut p = SimplePlaylist::default();
         p.name = name.to_string();
         p.id = format!("{}_id", p.name);
+        p.snapshot_id = format!("{}_snapshot", p.name);
         self.state.lock().await.playlists.push(p.clone());
         Ok(p)
     }
 
-    async fn playlist_add_items<'a>(
+    async fn playlist_add_batch(
         &self,
         playlist_id: &str,
-        items: impl IntoIterator<Item = String> + Send + 'a,
+        items: &[String],
+        position: u32,
     ) -> Result<(), CoolioError> {
-        let ps = &mut self.state.lock().await.playlists;
-        for p in ps {
+        let state = &mut *self.state.lock().await;
+        if items.len() > PLAYLIST_ADD_LIMIT {
+            return Err("too many tracks in one request".into());
+        }
+        if state.failing_batch == Some(state.added_batches.len() + 1) {
+            return Err("batch failed".into());
+        }
+        state.added_batches.push((items.len(), position));
+
+        // the tracks are kept in the order they were added in
+        for p in &mut state.playlists {
             if p.id == playlist_id {
                 let now = Utc::now();
                 let mut playable_items = Vec::<SimplePlayable>::new();
-                for i in items.into_iter() {
-                    let mut artists = Vec::<SimpleArtist>::new();
+                for i in items {
+                    let mut track = SimpleTrack {
+                        id: i.clone(),
+                        ..Default::default()
+                    };
                     for a in &self.artists {
                         for alb in &a.albums {
                             for t in &alb.tracks {
-                                if t == &i {
-                                    artists.push(a.artist.clone());
+                                if t == i {
+                                    track = test_track(a, alb, t);
                                 }
                             }
                         }
@@ -280,10 +455,8 @@ impl Spotify for MockSpotify {
 
                     playable_items.push(SimplePlayable {
                         added_at: Some(now),
-                        track: SimpleTrack {
-                            id: i.clone(),
-                            artists,
-                        },
+                        track,
+                        ..Default::default()
                     })
                 }
                 p.tracks.append(&mut playable_items);
@@ -293,6 +466,39 @@ impl Spotify for MockSpotify {
         Err("playlist doesnt exist".into())
     }
 
+    async fn playlist_remove_batch(
+        &self,
+        playlist_id: &str,
+        snapshot_id: &str,
+        items: &[(String, u32)],
+    ) -> Result<(), CoolioError> {
+        let state = &mut *self.state.lock().await;
+        if items.len() > PLAYLIST_REMOVE_LIMIT {
+            return Err("too many tracks in one request".into());
+        }
+        state.removed_batches.push(items.len());
+
+        let p = state
+            .playlists
+            .iter_mut()
+            .find(|p| p.id == playlist_id)
+            .ok_or("playlist doesnt exist")?;
+        if p.snapshot_id != snapshot_id {
+            return Err("snapshot doesnt match".into());
+        }
+        let mut positions = items.to_vec();
+        positions.sort_by_key(|p| std::cmp::Reverse(p.1));
+        for (id, position) in positions {
+            match p.tracks.get(position as usize) {
+                Some(t) if t.track.id == id => {
+                    p.tracks.remove(position as usize);
+                }
+                _ => return Err("no such track at the position".into()),
+            }
+        }
+        Ok(())
+    }
+
     async fn current_user_playlists(&self) -> Result<Vec<SimplePlaylist>, CoolioError> {
         Ok(self.state.lock().await.playlists.to_vec())
     }
@@ -303,10 +509,7 @@ impl Spotify for MockSpotify {
                 let mut tracks = Vec::<SimpleTrack>::new();
                 for alb in &art.albums {
                     for t in &alb.tracks {
-                        tracks.push(SimpleTrack {
-                            id: t.clone(),
-                            artists: vec![art.artist.clone()],
-                        })
+                        tracks.push(test_track(art, alb, t))
                     }
                 }
                 return Ok(tracks);
@@ -320,9 +523,10 @@ impl Spotify for MockSpotify {
                 if alb.album.id == id {
                     let mut tracks = Vec::<SimpleTrack>::new();
                     for t in &alb.tracks {
+                        // album tracks are simplified, so they don't have an ISRC
                         tracks.push(SimpleTrack {
-                            id: t.clone(),
-                            artists: vec![art.artist.clone()],
+                            isrc: None,
+                            ..test_track(art, alb, t)
                         })
                     }
                     return Ok(tracks);
@@ -348,16 +552,56 @@ impl Spotify for MockSpotify {
         Err("artist doesnt exist".into())
     }
 
-    async fn playlist(&self, id: &str) -> Result<SimplePlaylist, CoolioError> {
-        let ps = self.state.lock().await.playlists.to_vec();
-        for p in ps {
-            if p.id == id {
-                return Ok(p);
+    async fn playlist_info(&self, id: &str) -> Result<SimplePlaylist, CoolioError> {
+        let mut p = self.playlist(id).await?;
+        p.tracks.truncate(PLAYLIST_ITEMS_LIMIT as usize);
+        if p.num_tracks > PLAYLIST_ITEMS_LIMIT {
+            p.next_tracks = Some(PLAYLIST_ITEMS_LIMIT);
+        }
+        Ok(p)
+    }
+
+    async fn playlist_items(
+        &self,
+        id: &str,
+        offset: u32,
+    ) -> Result<SimplePage<SimplePlayable>, CoolioError> {
+        self.state.lock().await.playlist_items_calls += 1;
+        let p = self.playlist(id).await?;
+        let end = offset + PLAYLIST_ITEMS_LIMIT;
+        Ok(SimplePage {
+            items: p
+                .tracks
+                .into_iter()
+                .skip(offset as usize)
+                .take(PLAYLIST_ITEMS_LIMIT as usize)
+                .collect(),
+            next: if end < p.num_tracks { Some(end) } else { None },
+        })
+    }
+    async fn tracks(&self, ids: &[String]) -> Result<Vec<SimpleTrack>, CoolioError> {
+        let rejected = self.state.lock().await.rejected_tracks.clone();
+        if let Some(id) = ids.iter().find(|id| rejected.contains(id)) {
+            return Err(format!("invalid id: {}", id).into());
+        }
+        let mut tracks = Vec::<SimpleTrack>::new();
+        for id in ids {
+            for a in &self.artists {
+                for alb in &a.albums {
+                    for t in &alb.tracks {
+                        if t == id {
+                            tracks.push(test_track(a, alb, t))
+                        }
+                    }
+                }
             }
         }
-        Err("playlist doesnt exist".into())
+        Ok(tracks)
     }
     async fn artist(&self, id: &str) -> Result<SimpleArtist, CoolioError> {
+        self.state.lock().await.artist_calls += 1;
+        // the test artists' ids aren't URIs
+        let id = id.strip_prefix("spotify:artist:").unwrap_or(id);
         for a in &self.artists {
             if a.artist.id == id {
                 return Ok(a.artist.clone());
@@ -374,4 +618,33 @@ impl Spotify for MockSpotify {
         }
         Ok(artists)
     }
+    async fn search_tracks(
+        &self,
+        name: &str,
+        artist: &str,
+    ) -> Result<Vec<SimpleTrack>, CoolioError> {
+        if self
+            .state
+            .lock()
+            .await
+            .failing_searches
+            .iter()
+            .any(|n| n == name)
+        {
+            return Err("search failed".into());
+        }
+        let mut tracks = Vec::<SimpleTrack>::new();
+        for a in &self.artists {
+            if a.artist.name.contains(artist) {
+                for alb in &a.albums {
+                    for t in &alb.tracks {
+                        if t == name {
+                            tracks.push(test_track(a, alb, t))
+                        }
+                    }
+                }
+            }
+        }
+        Ok(tracks)
+    }
 }
@@ -1,4 +1,35 @@
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::models::Listen;
+
+mod auth;
+mod copy;
+mod daemon;
+mod fs;
 mod history;
 mod mock_spotify;
 mod parser;
 mod playlists;
+mod psql;
+mod retry;
+mod spotify;
+mod sqlite;
+mod sync;
+
+/// A listen of the track without any of the details of how it was played.
+fn played(song_id: &str, time: DateTime<Utc>) -> Listen {
+    Listen {
+        song_id: song_id.to_string(),
+        time,
+        ms_played: None,
+        skipped: None,
+        platform: None,
+        context: None,
+        track: None,
+    }
+}
+
+/// A listen of the track the given seconds after the epoch.
+fn listen(song_id: &str, secs: i64) -> Listen {
+    played(song_id, Utc.timestamp_opt(secs, 0).unwrap())
+}
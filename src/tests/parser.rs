@@ -7,23 +7,40 @@ use async_trait::async_trait;
 use tokio::sync::Mutex;
 
 use crate::{
+    auth::AuthCommand,
     error::CoolioError,
-    models::ThrowbackPeriod,
+    models::{HistoryWindow, LinkEdit, LinkRequest, RetentionEdit, RulesEdit, ThrowbackPeriod},
     parser::Parser,
     service::{io::Interactor, ServiceTrait},
+    settings::Daemon,
+    storage::copy::StorageCommand,
 };
 
 #[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
 struct Calls {
     history_update: u32,
+    history_import: u32,
+    history_backfill: u32,
+    history_gaps: u32,
+    history_dedupe: u32,
+    history_stats: u32,
     throwback: u32,
     playlists_list: u32,
     playlists_show: u32,
     playlists_create: u32,
     playlists_automate: u32,
+    playlists_duplicates: u32,
+    playlists_rules: u32,
+    playlists_retention: u32,
     link_playlist_to_artist: u32,
+    playlists_link_file: u32,
+    playlists_link_edit: u32,
     unlink_artist_from_playlist: u32,
+    playlists_unlink_file: u32,
     playlists_update: u32,
+    sync: u32,
+    sync_apply: u32,
+    daemon: u32,
 }
 
 #[derive(Default)]
@@ -38,6 +55,49 @@ impl ServiceTrait for MockService {
         Ok(())
     }
 
+    async fn history_import<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        _int: &mut Interactor<'b, R, W>,
+        _dir: &str,
+    ) -> Result<(), CoolioError> {
+        self.calls.lock().await.history_import += 1;
+        Ok(())
+    }
+
+    async fn history_backfill<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        _int: &mut Interactor<'b, R, W>,
+    ) -> Result<(), CoolioError> {
+        self.calls.lock().await.history_backfill += 1;
+        Ok(())
+    }
+
+    async fn history_gaps<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        _int: &mut Interactor<'b, R, W>,
+    ) -> Result<(), CoolioError> {
+        self.calls.lock().await.history_gaps += 1;
+        Ok(())
+    }
+
+    async fn history_dedupe<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        _int: &mut Interactor<'b, R, W>,
+    ) -> Result<(), CoolioError> {
+        self.calls.lock().await.history_dedupe += 1;
+        Ok(())
+    }
+
+    async fn history_stats<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        _int: &mut Interactor<'b, R, W>,
+        _window: HistoryWindow,
+        _size: Option<usize>,
+    ) -> Result<(), CoolioError> {
+        self.calls.lock().await.history_stats += 1;
+        Ok(())
+    }
+
     async fn throwback(
         &self,
         _name: Option<&str>,
@@ -75,17 +135,63 @@ impl ServiceTrait for MockService {
         Ok(())
     }
 
+    async fn playlists_duplicates(&self, _name: &str, _allow: bool) -> Result<(), CoolioError> {
+        self.calls.lock().await.playlists_duplicates += 1;
+        Ok(())
+    }
+
+    async fn playlists_rules<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        _int: &mut Interactor<'b, R, W>,
+        _name: &str,
+        _edit: RulesEdit,
+    ) -> Result<(), CoolioError> {
+        self.calls.lock().await.playlists_rules += 1;
+        Ok(())
+    }
+
+    async fn playlists_retention<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        _int: &mut Interactor<'b, R, W>,
+        _name: &str,
+        _edit: RetentionEdit,
+    ) -> Result<(), CoolioError> {
+        self.calls.lock().await.playlists_retention += 1;
+        Ok(())
+    }
+
     async fn link_playlist_to_artist<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
         &self,
         _int: &mut Interactor<'b, R, W>,
         _playlist: &str,
         _artist: &str,
-        _seed: Option<usize>,
+        _request: LinkRequest,
     ) -> Result<(), CoolioError> {
         self.calls.lock().await.link_playlist_to_artist += 1;
         Ok(())
     }
 
+    async fn playlists_link_file<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        _int: &mut Interactor<'b, R, W>,
+        _playlist: &str,
+        _file: &str,
+        _request: LinkRequest,
+    ) -> Result<(), CoolioError> {
+        self.calls.lock().await.playlists_link_file += 1;
+        Ok(())
+    }
+
+    async fn playlists_link_edit(
+        &self,
+        _playlist: &str,
+        _artist: &str,
+        _edit: LinkEdit,
+    ) -> Result<(), CoolioError> {
+        self.calls.lock().await.playlists_link_edit += 1;
+        Ok(())
+    }
+
     async fn unlink_artist_from_playlist(
         &self,
         _playlist: &str,
@@ -95,17 +201,50 @@ impl ServiceTrait for MockService {
         Ok(())
     }
 
-    async fn playlists_update(&self) -> Result<(), CoolioError> {
+    async fn playlists_unlink_file<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        _int: &mut Interactor<'b, R, W>,
+        _playlist: &str,
+        _file: &str,
+    ) -> Result<(), CoolioError> {
+        self.calls.lock().await.playlists_unlink_file += 1;
+        Ok(())
+    }
+
+    async fn playlists_update<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        _int: &mut Interactor<'b, R, W>,
+        _dry_run: bool,
+    ) -> Result<(), CoolioError> {
         self.calls.lock().await.playlists_update += 1;
         Ok(())
     }
+
+    async fn sync<'b, R: BufRead + Send + Sync, W: Write + Send + Sync>(
+        &self,
+        _int: &mut Interactor<'b, R, W>,
+        _file: &str,
+        apply: bool,
+    ) -> Result<(), CoolioError> {
+        let calls = &mut *self.calls.lock().await;
+        calls.sync += 1;
+        if apply {
+            calls.sync_apply += 1;
+        }
+        Ok(())
+    }
+
+    async fn daemon(&self, _conf: &Daemon) -> Result<(), CoolioError> {
+        self.calls.lock().await.daemon += 1;
+        Ok(())
+    }
 }
 
 #[tokio::test]
 async fn test_parser_history_update() {
     let s = MockService::default();
     let parser = Parser::new(vec!["coolio", "history", "update"]).unwrap();
-    parser.parse(&s).await.unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
     let mut expected = Calls::default();
     expected.history_update += 1;
     assert_eq!(&expected, s.calls.lock().await.deref());
@@ -117,11 +256,125 @@ fn test_parser_incorrect_history_update() {
     Parser::new(vec!["coolio", "history", "update", "--whatever"]).unwrap_err();
 }
 
+#[tokio::test]
+async fn test_parser_history_import() {
+    let s = MockService::default();
+    let parser = Parser::new(vec!["coolio", "history", "import", "./export"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.history_import += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[test]
+fn test_parser_incorrect_history_import() {
+    Parser::new(vec!["coolio", "history", "import"]).unwrap_err();
+    Parser::new(vec!["coolio", "history", "import", "one", "two"]).unwrap_err();
+    Parser::new(vec!["coolio", "history", "import", "one", "--notok"]).unwrap_err();
+}
+
+#[tokio::test]
+async fn test_parser_history_backfill() {
+    let s = MockService::default();
+    let parser = Parser::new(vec!["coolio", "history", "backfill"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.history_backfill += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[test]
+fn test_parser_incorrect_history_backfill() {
+    Parser::new(vec!["coolio", "history", "backfill", "whatever"]).unwrap_err();
+    Parser::new(vec!["coolio", "history", "backfill", "--whatever"]).unwrap_err();
+}
+
+#[tokio::test]
+async fn test_parser_history_gaps() {
+    let s = MockService::default();
+    let parser = Parser::new(vec!["coolio", "history", "gaps"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.history_gaps += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[test]
+fn test_parser_incorrect_history_gaps() {
+    Parser::new(vec!["coolio", "history", "gaps", "whatever"]).unwrap_err();
+    Parser::new(vec!["coolio", "history", "gaps", "--whatever"]).unwrap_err();
+}
+
+#[tokio::test]
+async fn test_parser_history_dedupe() {
+    let s = MockService::default();
+    let parser = Parser::new(vec!["coolio", "history", "dedupe"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.history_dedupe += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[test]
+fn test_parser_incorrect_history_dedupe() {
+    Parser::new(vec!["coolio", "history", "dedupe", "whatever"]).unwrap_err();
+    Parser::new(vec!["coolio", "history", "dedupe", "--whatever"]).unwrap_err();
+}
+
+#[tokio::test]
+async fn test_parser_history_stats() {
+    let s = MockService::default();
+    let parser = Parser::new(vec!["coolio", "history", "stats"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.history_stats += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+
+    let parser = Parser::new(vec!["coolio", "history", "stats", "--period", "3m"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    expected.history_stats += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+
+    let parser = Parser::new(vec![
+        "coolio",
+        "history",
+        "stats",
+        "--from",
+        "2021-01-01",
+        "--to",
+        "2021-12-31",
+        "--size",
+        "5",
+    ])
+    .unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    expected.history_stats += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[test]
+fn test_parser_incorrect_history_stats() {
+    Parser::new(vec!["coolio", "history", "stats", "whatever"]).unwrap_err();
+    Parser::new(vec!["coolio", "history", "stats", "--period", "10p"]).unwrap_err();
+    Parser::new(vec!["coolio", "history", "stats", "--from", "2021-13-01"]).unwrap_err();
+    Parser::new(vec!["coolio", "history", "stats", "--size", "many"]).unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "history",
+        "stats",
+        "--period",
+        "1y",
+        "--from",
+        "2021-01-01",
+    ])
+    .unwrap_err();
+}
+
 #[tokio::test]
 async fn test_parser_throwback() {
     let s = MockService::default();
     let parser = Parser::new(vec!["coolio", "history", "throwback"]).unwrap();
-    parser.parse(&s).await.unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
     let mut expected = Calls::default();
     expected.throwback += 1;
     assert_eq!(&expected, s.calls.lock().await.deref());
@@ -134,17 +387,17 @@ async fn test_parser_throwback() {
         "playlist_name",
     ])
     .unwrap();
-    parser.parse(&s).await.unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
     expected.throwback += 1;
     assert_eq!(&expected, s.calls.lock().await.deref());
 
     let parser = Parser::new(vec!["coolio", "history", "throwback", "--period", "5m"]).unwrap();
-    parser.parse(&s).await.unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
     expected.throwback += 1;
     assert_eq!(&expected, s.calls.lock().await.deref());
 
     let parser = Parser::new(vec!["coolio", "history", "throwback", "--size", "2"]).unwrap();
-    parser.parse(&s).await.unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
     expected.throwback += 1;
     assert_eq!(&expected, s.calls.lock().await.deref());
 }
@@ -161,7 +414,7 @@ fn test_parser_incorrect_throwback() {
 async fn test_parser_playlists_list() {
     let s = MockService::default();
     let parser = Parser::new(vec!["coolio", "playlists", "list"]).unwrap();
-    parser.parse(&s).await.unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
     let mut expected = Calls::default();
     expected.playlists_list += 1;
     assert_eq!(&expected, s.calls.lock().await.deref());
@@ -184,7 +437,7 @@ async fn test_parser_playlists_link() {
         "artist_name",
     ])
     .unwrap();
-    parser.parse(&s).await.unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
     let mut expected = Calls::default();
     expected.link_playlist_to_artist += 1;
     assert_eq!(&expected, s.calls.lock().await.deref());
@@ -199,9 +452,39 @@ async fn test_parser_playlists_link() {
         "3",
     ])
     .unwrap();
-    parser.parse(&s).await.unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    expected.link_playlist_to_artist += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+
+    let parser = Parser::new(vec![
+        "coolio",
+        "playlists",
+        "link",
+        "playlist_name",
+        "artist_name",
+        "--types",
+        "album,appears_on",
+        "--no-features",
+    ])
+    .unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
     expected.link_playlist_to_artist += 1;
     assert_eq!(&expected, s.calls.lock().await.deref());
+
+    for flag in ["--first", "--non-interactive"] {
+        let parser = Parser::new(vec![
+            "coolio",
+            "playlists",
+            "link",
+            "playlist_name",
+            "spotify:artist:2YZyLoL8N0Wb9xBt1NhZWg",
+            flag,
+        ])
+        .unwrap();
+        parser.parse(&s, &Daemon::default()).await.unwrap();
+        expected.link_playlist_to_artist += 1;
+    }
+    assert_eq!(&expected, s.calls.lock().await.deref());
 }
 
 #[test]
@@ -235,6 +518,73 @@ fn test_parser_incorrect_playlists_link() {
         "notanumber",
     ])
     .unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "link",
+        "playlist_name",
+        "artist_name",
+        "--types",
+        "album,remix",
+    ])
+    .unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "link",
+        "playlist_name",
+        "artist_name",
+        "--features",
+        "--no-features",
+    ])
+    .unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "link",
+        "playlist_name",
+        "artist_name",
+        "--first",
+        "--non-interactive",
+    ])
+    .unwrap_err();
+}
+
+#[tokio::test]
+async fn test_parser_playlists_link_edit() {
+    let s = MockService::default();
+    let parser = Parser::new(vec![
+        "coolio",
+        "playlists",
+        "link-edit",
+        "playlist_name",
+        "artist_name",
+        "--types",
+        "single",
+        "--seed",
+        "2",
+        "--features",
+    ])
+    .unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.playlists_link_edit += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[test]
+fn test_parser_incorrect_playlists_link_edit() {
+    Parser::new(vec!["coolio", "playlists", "link-edit", "whatever"]).unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "link-edit",
+        "playlist_name",
+        "artist_name",
+        "--seed",
+        "notanumber",
+    ])
+    .unwrap_err();
 }
 
 #[tokio::test]
@@ -248,12 +598,86 @@ async fn test_parser_playlists_unlink() {
         "artist_name",
     ])
     .unwrap();
-    parser.parse(&s).await.unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
     let mut expected = Calls::default();
     expected.unlink_artist_from_playlist += 1;
     assert_eq!(&expected, s.calls.lock().await.deref());
 }
 
+#[tokio::test]
+async fn test_parser_playlists_link_file() {
+    let s = MockService::default();
+    let parser = Parser::new(vec![
+        "coolio",
+        "playlists",
+        "link-file",
+        "playlist_name",
+        "artists.txt",
+    ])
+    .unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let parser = Parser::new(vec![
+        "coolio",
+        "playlists",
+        "link-file",
+        "playlist_name",
+        "artists.csv",
+        "--first",
+        "--seed",
+        "3",
+    ])
+    .unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let parser = Parser::new(vec![
+        "coolio",
+        "playlists",
+        "link-file",
+        "playlist_name",
+        "artists.txt",
+        "--non-interactive",
+    ])
+    .unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let parser = Parser::new(vec![
+        "coolio",
+        "playlists",
+        "unlink-file",
+        "playlist_name",
+        "artists.txt",
+    ])
+    .unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.playlists_link_file += 3;
+    expected.playlists_unlink_file += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[test]
+fn test_parser_incorrect_playlists_link_file() {
+    Parser::new(vec!["coolio", "playlists", "link-file", "playlist_name"]).unwrap_err();
+    Parser::new(vec!["coolio", "playlists", "unlink-file", "playlist_name"]).unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "link-file",
+        "playlist_name",
+        "artists.txt",
+        "--first",
+        "--non-interactive",
+    ])
+    .unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "unlink-file",
+        "playlist_name",
+        "artists.txt",
+        "--first",
+    ])
+    .unwrap_err();
+}
+
 #[test]
 fn test_parser_incorrect_playlists_unlink() {
     Parser::new(vec!["coolio", "playlists", "unlink"]).unwrap_err();
@@ -291,7 +715,17 @@ fn test_parser_incorrect_playlists_unlink() {
 async fn test_parser_playlists_update() {
     let s = MockService::default();
     let parser = Parser::new(vec!["coolio", "playlists", "update"]).unwrap();
-    parser.parse(&s).await.unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.playlists_update += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[tokio::test]
+async fn test_parser_playlists_update_dry_run() {
+    let s = MockService::default();
+    let parser = Parser::new(vec!["coolio", "playlists", "update", "--dry-run"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
     let mut expected = Calls::default();
     expected.playlists_update += 1;
     assert_eq!(&expected, s.calls.lock().await.deref());
@@ -307,7 +741,7 @@ fn test_parser_incorrect_playlists_update() {
 async fn test_parser_playlists_automate() {
     let s = MockService::default();
     let parser = Parser::new(vec!["coolio", "playlists", "automate", "playlist_name"]).unwrap();
-    parser.parse(&s).await.unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
     let mut expected = Calls::default();
     expected.playlists_automate += 1;
     assert_eq!(&expected, s.calls.lock().await.deref());
@@ -320,11 +754,176 @@ fn test_parser_incorrect_playlists_automate() {
     Parser::new(vec!["coolio", "playlists", "automate", "one", "--notok"]).unwrap_err();
 }
 
+#[tokio::test]
+async fn test_parser_playlists_duplicates() {
+    let s = MockService::default();
+    let parser = Parser::new(vec![
+        "coolio",
+        "playlists",
+        "duplicates",
+        "playlist_name",
+        "allow",
+    ])
+    .unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let parser = Parser::new(vec![
+        "coolio",
+        "playlists",
+        "duplicates",
+        "playlist_name",
+        "skip",
+    ])
+    .unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.playlists_duplicates += 2;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[test]
+fn test_parser_incorrect_playlists_duplicates() {
+    Parser::new(vec!["coolio", "playlists", "duplicates", "one"]).unwrap_err();
+    Parser::new(vec!["coolio", "playlists", "duplicates", "one", "maybe"]).unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "duplicates",
+        "one",
+        "allow",
+        "two",
+    ])
+    .unwrap_err();
+}
+
+#[tokio::test]
+async fn test_parser_playlists_rules() {
+    let s = MockService::default();
+    let parser = Parser::new(vec!["coolio", "playlists", "rules", "playlist_name"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let parser = Parser::new(vec![
+        "coolio",
+        "playlists",
+        "rules",
+        "playlist_name",
+        "--exclude",
+        "remix",
+        "--exclude",
+        "\\blive\\b",
+        "--include",
+        "karaoke",
+        "--min-duration",
+        "60",
+        "--explicit",
+        "skip",
+    ])
+    .unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let parser = Parser::new(vec![
+        "coolio",
+        "playlists",
+        "rules",
+        "playlist_name",
+        "--reset",
+    ])
+    .unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.playlists_rules += 3;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[test]
+fn test_parser_incorrect_playlists_rules() {
+    Parser::new(vec!["coolio", "playlists", "rules"]).unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "rules",
+        "playlist_name",
+        "--exclude",
+        "(live",
+    ])
+    .unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "rules",
+        "playlist_name",
+        "--max-duration",
+        "long",
+    ])
+    .unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "rules",
+        "playlist_name",
+        "--explicit",
+        "maybe",
+    ])
+    .unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "rules",
+        "playlist_name",
+        "--reset",
+        "--exclude",
+        "remix",
+    ])
+    .unwrap_err();
+}
+
+#[tokio::test]
+async fn test_parser_playlists_retention() {
+    let s = MockService::default();
+    let parser = Parser::new(vec!["coolio", "playlists", "retention", "playlist_name"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let parser = Parser::new(vec![
+        "coolio",
+        "playlists",
+        "retention",
+        "playlist_name",
+        "--max-tracks",
+        "50",
+        "--max-age",
+        "30",
+    ])
+    .unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.playlists_retention += 2;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[test]
+fn test_parser_incorrect_playlists_retention() {
+    Parser::new(vec!["coolio", "playlists", "retention"]).unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "retention",
+        "playlist_name",
+        "--max-tracks",
+        "-1",
+    ])
+    .unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "playlists",
+        "retention",
+        "playlist_name",
+        "--max-age",
+        "month",
+    ])
+    .unwrap_err();
+}
+
 #[tokio::test]
 async fn test_parser_playlists_show() {
     let s = MockService::default();
     let parser = Parser::new(vec!["coolio", "playlists", "show", "playlist_name"]).unwrap();
-    parser.parse(&s).await.unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
     let mut expected = Calls::default();
     expected.playlists_show += 1;
     assert_eq!(&expected, s.calls.lock().await.deref());
@@ -343,3 +942,109 @@ fn test_parser_incorrect_overall_usage() {
     Parser::new(vec!["coolio", "playlists"]).unwrap_err();
     Parser::new(vec!["coolio", "history"]).unwrap_err();
 }
+
+#[tokio::test]
+async fn test_parser_daemon() {
+    let s = MockService::default();
+    let parser = Parser::new(vec!["coolio", "daemon"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.daemon += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[test]
+fn test_parser_incorrect_daemon() {
+    Parser::new(vec!["coolio", "daemon", "whatever"]).unwrap_err();
+    Parser::new(vec!["coolio", "daemon", "--whatever"]).unwrap_err();
+}
+
+#[test]
+fn test_parser_auth() {
+    for (cmd, expected) in [
+        ("login", AuthCommand::Login),
+        ("status", AuthCommand::Status),
+        ("logout", AuthCommand::Logout),
+    ] {
+        let parser = Parser::new(vec!["coolio", "auth", cmd]).unwrap();
+        assert_eq!(parser.auth_command(), Some(expected));
+    }
+
+    let parser = Parser::new(vec!["coolio", "history", "update"]).unwrap();
+    assert_eq!(parser.auth_command(), None);
+}
+
+#[test]
+fn test_parser_incorrect_auth() {
+    Parser::new(vec!["coolio", "auth"]).unwrap_err();
+    Parser::new(vec!["coolio", "auth", "whatever"]).unwrap_err();
+    Parser::new(vec!["coolio", "auth", "login", "--whatever"]).unwrap_err();
+}
+
+#[test]
+fn test_parser_storage_copy() {
+    let parser = Parser::new(vec![
+        "coolio", "storage", "copy", "--from", "storage", "--to", "psql",
+    ])
+    .unwrap();
+    assert_eq!(
+        parser.storage_command(),
+        Some(StorageCommand::Copy {
+            from: "storage".to_string(),
+            to: "psql".to_string(),
+            merge: false,
+        })
+    );
+    let parser = Parser::new(vec![
+        "coolio", "storage", "copy", "--to", "psql", "--from", "fs", "--merge",
+    ])
+    .unwrap();
+    assert_eq!(
+        parser.storage_command(),
+        Some(StorageCommand::Copy {
+            from: "fs".to_string(),
+            to: "psql".to_string(),
+            merge: true,
+        })
+    );
+
+    let parser = Parser::new(vec!["coolio", "history", "update"]).unwrap();
+    assert_eq!(parser.storage_command(), None);
+}
+
+#[test]
+fn test_parser_incorrect_storage() {
+    Parser::new(vec!["coolio", "storage"]).unwrap_err();
+    Parser::new(vec!["coolio", "storage", "copy"]).unwrap_err();
+    Parser::new(vec!["coolio", "storage", "copy", "--from", "fs"]).unwrap_err();
+    Parser::new(vec!["coolio", "storage", "copy", "--to", "psql"]).unwrap_err();
+    Parser::new(vec!["coolio", "storage", "copy", "fs", "psql"]).unwrap_err();
+}
+
+#[tokio::test]
+async fn test_parser_sync() {
+    let s = MockService::default();
+    let parser = Parser::new(vec!["coolio", "sync", "playlists.toml"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let parser = Parser::new(vec!["coolio", "sync", "playlists.toml", "--plan"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let parser = Parser::new(vec!["coolio", "sync", "playlists.toml", "--apply"]).unwrap();
+    parser.parse(&s, &Daemon::default()).await.unwrap();
+    let mut expected = Calls::default();
+    expected.sync += 3;
+    expected.sync_apply += 1;
+    assert_eq!(&expected, s.calls.lock().await.deref());
+}
+
+#[test]
+fn test_parser_incorrect_sync() {
+    Parser::new(vec!["coolio", "sync"]).unwrap_err();
+    Parser::new(vec![
+        "coolio",
+        "sync",
+        "playlists.toml",
+        "--plan",
+        "--apply",
+    ])
+    .unwrap_err();
+}
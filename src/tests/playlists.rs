@@ -1,12 +1,31 @@
+use std::env;
+use std::fs;
 use std::str;
 
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::models::{
+    ArtistChoice, ExclusionRules, LinkEdit, LinkOptions, LinkRequest, ReleaseType, Retention,
+    RetentionEdit, RulesEdit,
+};
 use crate::service::io::Interactor;
-use crate::service::spotify::Spotify;
+use crate::service::playlists::artist_tracks;
+use crate::service::spotify::{SimpleArtist, SimplePlayable, SimpleTrack, Spotify};
 use crate::service::{Service, ServiceTrait};
 use crate::storage::mock::Mock as MockStorage;
-use crate::storage::StorageBehavior;
+use crate::storage::{Storage, StorageBehavior};
 use crate::tests::mock_spotify::MockSpotify;
 
+fn seeded(seed: usize) -> LinkRequest {
+    LinkRequest {
+        edit: LinkEdit {
+            seed: Some(seed),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
 #[tokio::test]
 async fn test_playlists_create() {
     let st_to = StorageBehavior::from(MockStorage::new());
@@ -89,7 +108,7 @@ async fn test_playlists_link() {
     let mut int = Interactor::new(input, &mut output);
 
     s.playlists_create("maman").await.unwrap();
-    s.link_playlist_to_artist(&mut int, "maman", "ken", None)
+    s.link_playlist_to_artist(&mut int, "maman", "ken", LinkRequest::default())
         .await
         .unwrap();
 
@@ -119,7 +138,7 @@ async fn test_playlists_link() {
     let input: &[u8] = "2\n".as_bytes();
     let mut output = Vec::new();
     let mut int = Interactor::new(input, &mut output);
-    s.link_playlist_to_artist(&mut int, "maman", "rick", Some(3))
+    s.link_playlist_to_artist(&mut int, "maman", "rick", seeded(3))
         .await
         .unwrap();
     let output_str = str::from_utf8(&output).unwrap();
@@ -155,14 +174,14 @@ async fn test_playlists_link_error_on_existing() {
     let mut int = Interactor::new(input, &mut output);
 
     s.playlists_create("maman").await.unwrap();
-    s.link_playlist_to_artist(&mut int, "maman", "ken", None)
+    s.link_playlist_to_artist(&mut int, "maman", "ken", LinkRequest::default())
         .await
         .unwrap();
 
     let input: &[u8] = "1\n".as_bytes();
     let mut output = Vec::new();
     let mut int = Interactor::new(input, &mut output);
-    s.link_playlist_to_artist(&mut int, "maman", "ken", None)
+    s.link_playlist_to_artist(&mut int, "maman", "ken", LinkRequest::default())
         .await
         .unwrap_err();
 }
@@ -177,7 +196,7 @@ async fn test_playlists_link_retry_wrong_input() {
     let mut int = Interactor::new(input, &mut output);
 
     s.playlists_create("maman").await.unwrap();
-    s.link_playlist_to_artist(&mut int, "maman", "ken", None)
+    s.link_playlist_to_artist(&mut int, "maman", "ken", LinkRequest::default())
         .await
         .unwrap();
 
@@ -197,7 +216,7 @@ async fn test_playlists_link_wrong_playlist() {
     let mut output = Vec::new();
     let mut int = Interactor::new(input, &mut output);
 
-    s.link_playlist_to_artist(&mut int, "notthere", "kendrick", None)
+    s.link_playlist_to_artist(&mut int, "notthere", "kendrick", LinkRequest::default())
         .await
         .unwrap_err();
     assert_eq!(output.len(), 0);
@@ -212,12 +231,163 @@ async fn test_playlists_link_nonexisting_artist() {
     let mut output = Vec::new();
     let mut int = Interactor::new(input, &mut output);
 
-    s.link_playlist_to_artist(&mut int, "notthere", "idontexist", None)
+    s.link_playlist_to_artist(&mut int, "notthere", "idontexist", LinkRequest::default())
         .await
         .unwrap_err();
     assert_eq!(output.len(), 0);
 }
 
+#[tokio::test]
+async fn test_playlists_link_options() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "1\n".as_bytes();
+    let mut output = Vec::new();
+
+    s.playlists_create("maman").await.unwrap();
+    let mut int = Interactor::new(input, &mut output);
+    s.link_playlist_to_artist(&mut int, "maman", "kendrick", LinkRequest::default())
+        .await
+        .unwrap();
+    let mut int = Interactor::new(input, &mut output);
+    s.link_playlist_to_artist(
+        &mut int,
+        "maman",
+        "kali",
+        LinkRequest {
+            edit: LinkEdit {
+                release_types: Some(vec![ReleaseType::Album, ReleaseType::AppearsOn]),
+                include_features: Some(false),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let st = st_to.as_mock().unwrap();
+    let options = &st.state.lock().await.link_options;
+    assert_eq!(
+        options[&("maman_id".to_string(), "artist_1".to_string())],
+        LinkOptions::default()
+    );
+    assert_eq!(
+        options[&("maman_id".to_string(), "artist_3".to_string())],
+        LinkOptions {
+            release_types: vec![ReleaseType::Album, ReleaseType::AppearsOn],
+            seed: 5,
+            include_features: false,
+        }
+    );
+    // no seed was given, so nothing is added until the next update
+    assert_eq!(sp.state.lock().await.playlists[0].tracks.len(), 0);
+}
+
+fn credited(id: &str, artist_ids: &[&str]) -> SimpleTrack {
+    SimpleTrack {
+        id: id.to_string(),
+        name: format!("{} name", id),
+        artists: artist_ids
+            .iter()
+            .map(|a| SimpleArtist {
+                id: a.to_string(),
+                name: format!("{} name", a),
+                num_followers: 0,
+                popularity: 0,
+            })
+            .collect(),
+        album_id: None,
+        album_name: None,
+        duration_ms: 180000,
+        isrc: None,
+        explicit: false,
+    }
+}
+
+#[test]
+fn test_playlists_artist_tracks() {
+    let tracks = vec![
+        credited("led", &["artist_1", "artist_2"]),
+        credited("featured", &["artist_2", "artist_1"]),
+        // other tracks of a release the artist is on
+        credited("uncredited", &["artist_2"]),
+    ];
+
+    let kept = artist_tracks(tracks.clone(), "artist_1", &LinkOptions::default());
+    assert_eq!(kept.len(), 3);
+
+    let options = LinkOptions {
+        include_features: false,
+        ..Default::default()
+    };
+    let kept = artist_tracks(tracks, "artist_1", &options);
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0].id, "led");
+}
+
+#[tokio::test]
+async fn test_playlists_link_edit() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "1\n".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    s.link_playlist_to_artist(&mut int, "maman", "kendrick", LinkRequest::default())
+        .await
+        .unwrap();
+    s.playlists_link_edit(
+        "maman",
+        "kendrick",
+        LinkEdit {
+            release_types: Some(vec![ReleaseType::Single]),
+            seed: Some(2),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    s.playlists_link_edit(
+        "maman",
+        "kendrick",
+        LinkEdit {
+            include_features: Some(false),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let st = st_to.as_mock().unwrap();
+        let options = &st.state.lock().await.link_options;
+        assert_eq!(
+            options[&("maman_id".to_string(), "artist_1".to_string())],
+            LinkOptions {
+                release_types: vec![ReleaseType::Single],
+                seed: 2,
+                include_features: false,
+            }
+        );
+    }
+
+    // the updated seed is used when the artist gets seeded
+    let mut int = Interactor::new(input, &mut output);
+    s.playlists_update(&mut int, false).await.unwrap();
+    assert_eq!(sp.state.lock().await.playlists[0].tracks.len(), 2);
+
+    s.playlists_link_edit("maman", "kali", LinkEdit::default())
+        .await
+        .unwrap_err();
+    s.playlists_link_edit("doesnt exist", "kendrick", LinkEdit::default())
+        .await
+        .unwrap_err();
+}
+
 #[tokio::test]
 async fn test_playlists_automate() {
     let st_to = StorageBehavior::from(MockStorage::new());
@@ -232,7 +402,7 @@ async fn test_playlists_automate() {
         let st = st_to.as_mock().unwrap();
         let stored_playlists = &st.state.lock().await.playlists;
         assert_eq!(stored_playlists.len(), 1);
-        assert_eq!(stored_playlists[0].automated, true);
+        assert!(stored_playlists[0].automated);
         assert_eq!(stored_playlists[0].name, "later_automated");
     }
 }
@@ -248,6 +418,325 @@ async fn test_playlists_automate_error_on_not_exists() {
     s.playlists_automate("doesntexist").await.unwrap_err();
 }
 
+#[tokio::test]
+async fn test_playlists_link_by_uri() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let mut output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    s.link_playlist_to_artist(
+        &mut int,
+        "maman",
+        "spotify:artist:artist_1",
+        LinkRequest::default(),
+    )
+    .await
+    .unwrap();
+    s.link_playlist_to_artist(
+        &mut int,
+        "maman",
+        "https://open.spotify.com/artist/artist_3?si=abc",
+        LinkRequest::default(),
+    )
+    .await
+    .unwrap();
+    s.link_playlist_to_artist(
+        &mut int,
+        "maman",
+        "spotify:artist:idontexist",
+        LinkRequest::default(),
+    )
+    .await
+    .unwrap_err();
+    // nothing was asked
+    assert_eq!(output.len(), 0);
+
+    {
+        let st = st_to.as_mock().unwrap();
+        let playlists = &st.state.lock().await.playlists;
+        assert_eq!(playlists[0].artists, vec!["artist_1", "artist_3"]);
+    }
+
+    s.unlink_artist_from_playlist("maman", "spotify:artist:artist_2")
+        .await
+        .unwrap_err();
+    s.unlink_artist_from_playlist("maman", "spotify:artist:artist_1")
+        .await
+        .unwrap();
+    let st = st_to.as_mock().unwrap();
+    assert_eq!(st.state.lock().await.playlists[0].artists, vec!["artist_3"]);
+}
+
+#[tokio::test]
+async fn test_playlists_link_without_prompt() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let mut output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    // both kendrick lamar and rick ross are found
+    let err = s
+        .link_playlist_to_artist(
+            &mut int,
+            "maman",
+            "rick",
+            LinkRequest {
+                choice: ArtistChoice::Unique,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "ambiguous artists found for rick, use the artist's URI instead"
+    );
+    s.link_playlist_to_artist(
+        &mut int,
+        "maman",
+        "rick ross",
+        LinkRequest {
+            choice: ArtistChoice::Unique,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    s.link_playlist_to_artist(
+        &mut int,
+        "maman",
+        "rick",
+        LinkRequest {
+            choice: ArtistChoice::First,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let err = s
+        .link_playlist_to_artist(
+            &mut int,
+            "maman",
+            "idontexist",
+            LinkRequest {
+                choice: ArtistChoice::First,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "no artists found for idontexist");
+    assert_eq!(output.len(), 0);
+
+    let st = st_to.as_mock().unwrap();
+    assert_eq!(
+        st.state.lock().await.playlists[0].artists,
+        vec!["artist_2", "artist_1"]
+    );
+}
+
+#[tokio::test]
+async fn test_playlists_link_file() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    s.link_playlist_to_artist(
+        &mut int,
+        "maman",
+        "rick ross",
+        LinkRequest {
+            choice: ArtistChoice::Unique,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let path = env::temp_dir().join("coolio_test_playlists_link_file.txt");
+    fs::write(
+        &path,
+        "kendrick lamar\n\
+         # linked by its uri\n\
+         spotify:artist:artist_3\n\
+         \n\
+         rick\n\
+         rick ross\n\
+         idontexist\n\
+         bonobo\n\
+         kendrick lamar\n",
+    )
+    .unwrap();
+    s.playlists_link_file(
+        &mut int,
+        "maman",
+        path.to_str().unwrap(),
+        LinkRequest {
+            choice: ArtistChoice::Unique,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Linked artists: 3");
+    assert_eq!(split[1], "Already linked: 1");
+    assert_eq!(split[2], "Not resolved: 2");
+    assert_eq!(
+        split[3],
+        "\trick: ambiguous artists found for rick, use the artist's URI instead"
+    );
+    assert_eq!(split[4], "\tidontexist: no artists found for idontexist");
+    assert_eq!(split[5], "Failed to link: 0");
+    {
+        let st = st_to.as_mock().unwrap();
+        assert_eq!(
+            st.state.lock().await.playlists[0].artists,
+            vec!["artist_2", "artist_1", "artist_3", "artist_6"]
+        );
+    }
+
+    let path = env::temp_dir().join("coolio_test_playlists_unlink_file.csv");
+    fs::write(
+        &path,
+        "artist,comment\n\
+         kendrick lamar,\n\
+         \"spotify:artist:artist_3\",\"the one, from colombia\"\n\
+         arctic monkeys,not linked\n",
+    )
+    .unwrap();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+    s.playlists_unlink_file(&mut int, "maman", path.to_str().unwrap())
+        .await
+        .unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Unlinked artists: 2");
+    assert_eq!(split[1], "Not resolved: 1");
+    assert_eq!(
+        split[2],
+        "\tarctic monkeys: no artists in the playlist matched your search"
+    );
+    assert_eq!(split[3], "Failed to unlink: 0");
+    {
+        let st = st_to.as_mock().unwrap();
+        assert_eq!(
+            st.state.lock().await.playlists[0].artists,
+            vec!["artist_2", "artist_6"]
+        );
+    }
+
+    let path = env::temp_dir().join("coolio_test_playlists_link_file_missing.txt");
+    let _ = fs::remove_file(&path);
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+    s.playlists_link_file(
+        &mut int,
+        "maman",
+        path.to_str().unwrap(),
+        LinkRequest {
+            choice: ArtistChoice::Unique,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap_err();
+}
+
+#[tokio::test]
+async fn test_playlists_link_file_failed() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    sp.state.lock().await.failing_batch = Some(2);
+
+    let path = env::temp_dir().join("coolio_test_playlists_link_file_failed.txt");
+    fs::write(
+        &path,
+        "bonobo
+kendrick lamar
+",
+    )
+    .unwrap();
+    s.playlists_link_file(
+        &mut int,
+        "maman",
+        path.to_str().unwrap(),
+        LinkRequest {
+            choice: ArtistChoice::Unique,
+            ..seeded(1)
+        },
+    )
+    .await
+    .unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Linked artists: 1");
+    assert_eq!(split[1], "Already linked: 0");
+    assert_eq!(split[2], "Not resolved: 0");
+    assert_eq!(split[3], "Failed to link: 1");
+    assert_eq!(
+        split[4],
+        "\tkendrick lamar: adding batch 1 of 1 to the playlist failed, 0 of 1 tracks were added: batch failed"
+    );
+    assert_eq!(sp.state.lock().await.added_batches.len(), 1);
+}
+
+#[tokio::test]
+async fn test_playlists_unlink_file_failed() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    for artist in ["spotify:artist:artist_1", "spotify:artist:artist_3"] {
+        s.link_playlist_to_artist(&mut int, "maman", artist, LinkRequest::default())
+            .await
+            .unwrap();
+    }
+    st_to.as_mock().unwrap().state.lock().await.failing_unlinks = vec!["artist_1".to_string()];
+
+    let path = env::temp_dir().join("coolio_test_playlists_unlink_file_failed.txt");
+    fs::write(&path, "kendrick lamar\nkali uchis\n").unwrap();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+    s.playlists_unlink_file(&mut int, "maman", path.to_str().unwrap())
+        .await
+        .unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Unlinked artists: 1");
+    assert_eq!(split[1], "Not resolved: 0");
+    assert_eq!(split[2], "Failed to unlink: 1");
+    assert_eq!(split[3], "\tkendrick lamar: unlink failed");
+
+    let st = st_to.as_mock().unwrap();
+    assert_eq!(st.state.lock().await.playlists[0].artists, vec!["artist_1"]);
+}
+
 #[tokio::test]
 async fn test_playlists_unlink() {
     let st_to = StorageBehavior::from(MockStorage::new());
@@ -258,7 +747,7 @@ async fn test_playlists_unlink() {
     let mut int = Interactor::new(input, &mut output);
 
     s.playlists_create("maman").await.unwrap();
-    s.link_playlist_to_artist(&mut int, "maman", "kali", None)
+    s.link_playlist_to_artist(&mut int, "maman", "kali", LinkRequest::default())
         .await
         .unwrap();
     s.unlink_artist_from_playlist("maman", "kali")
@@ -269,7 +758,7 @@ async fn test_playlists_unlink() {
         let st = st_to.as_mock().unwrap();
         let stored_playlists = &st.state.lock().await.playlists;
         assert_eq!(stored_playlists.len(), 1);
-        assert_eq!(stored_playlists[0].automated, true);
+        assert!(stored_playlists[0].automated);
         assert_eq!(stored_playlists[0].name, "maman");
         assert_eq!(stored_playlists[0].artists.len(), 0);
     }
@@ -288,19 +777,19 @@ async fn test_playlists_update() {
     let input: &[u8] = "1\n".as_bytes();
     let mut output = Vec::new();
     let mut int = Interactor::new(input, &mut output);
-    s.link_playlist_to_artist(&mut int, "maman", "kali", None)
+    s.link_playlist_to_artist(&mut int, "maman", "kali", LinkRequest::default())
         .await
         .unwrap();
 
     let input: &[u8] = "1\n".as_bytes();
     let mut int = Interactor::new(input, &mut output);
-    s.link_playlist_to_artist(&mut int, "maman", "kendrick", Some(1))
+    s.link_playlist_to_artist(&mut int, "maman", "kendrick", seeded(1))
         .await
         .unwrap();
 
     let input: &[u8] = "1\n".as_bytes();
     let mut int = Interactor::new(input, &mut output);
-    s.link_playlist_to_artist(&mut int, "maman", "arctic", Some(1))
+    s.link_playlist_to_artist(&mut int, "maman", "arctic", seeded(1))
         .await
         .unwrap();
 
@@ -310,13 +799,13 @@ async fn test_playlists_update() {
 
     let input: &[u8] = "1\n".as_bytes();
     let mut int = Interactor::new(input, &mut output);
-    s.link_playlist_to_artist(&mut int, "smaller", "kendrick", Some(1))
+    s.link_playlist_to_artist(&mut int, "smaller", "kendrick", seeded(1))
         .await
         .unwrap();
 
     let input: &[u8] = "1\n".as_bytes();
     let mut int = Interactor::new(input, &mut output);
-    s.link_playlist_to_artist(&mut int, "smaller", "dua", Some(1))
+    s.link_playlist_to_artist(&mut int, "smaller", "dua", seeded(1))
         .await
         .unwrap();
 
@@ -338,7 +827,10 @@ async fn test_playlists_update() {
         assert_eq!(playlists[1].tracks[1].track.id, "track_25");
     }
 
-    s.playlists_update().await.unwrap();
+    let mut update_output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut update_output);
+    s.playlists_update(&mut int, false).await.unwrap();
+    assert_eq!(update_output.len(), 0);
 
     // assert songs in maman playlist
     {
@@ -367,3 +859,705 @@ async fn test_playlists_update() {
         assert_eq!(playlists[1].tracks[1].track.id, "track_25");
     }
 }
+
+#[tokio::test]
+async fn test_playlists_update_paginated() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "1\n".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("big").await.unwrap();
+    sp.playlist_add_items("big_id", (0..250).map(|i| format!("filler_{}", i)))
+        .await
+        .unwrap();
+    // the seeded track ends up on the third page
+    s.link_playlist_to_artist(&mut int, "big", "ross", seeded(1))
+        .await
+        .unwrap();
+    sp.state.lock().await.playlist_items_calls = 0;
+
+    let mut update_output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut update_output);
+    s.playlists_update(&mut int, false).await.unwrap();
+    assert_eq!(update_output.len(), 0);
+
+    // rick ross has nothing newer than the seeded track
+    let state = sp.state.lock().await;
+    assert_eq!(state.playlists[0].tracks.len(), 251);
+    assert_eq!(state.playlist_items_calls, 3);
+}
+
+#[tokio::test]
+async fn test_playlists_show_paginated() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("big").await.unwrap();
+    sp.playlist_add_items("big_id", (0..250).map(|i| format!("filler_{}", i)))
+        .await
+        .unwrap();
+    s.playlists_show(&mut int, "big").await.unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    assert!(output_str.contains("Number of tracks: 250\n"));
+    assert_eq!(sp.state.lock().await.playlist_items_calls, 0);
+}
+
+#[tokio::test]
+async fn test_playlists_add_batches() {
+    let sp = MockSpotify::new();
+    sp.create_playlist("big").await.unwrap();
+
+    let tracks = (0..250).map(|i| format!("track_{}", i));
+    sp.playlist_add_items("big_id", tracks).await.unwrap();
+    sp.playlist_add_items("big_id", (0..100).map(|i| format!("full_{}", i)))
+        .await
+        .unwrap();
+    sp.playlist_add_items("big_id", (0..101).map(|i| format!("over_{}", i)))
+        .await
+        .unwrap();
+
+    let state = sp.state.lock().await;
+    assert_eq!(
+        state.added_batches,
+        vec![
+            (100, 0),
+            (100, 100),
+            (50, 200),
+            (100, 0),
+            (100, 0),
+            (1, 100)
+        ]
+    );
+
+    let added = &state.playlists[0].tracks;
+    assert_eq!(added.len(), 451);
+    assert_eq!(added[99].track.id, "track_99");
+    assert_eq!(added[100].track.id, "track_100");
+    assert_eq!(added[249].track.id, "track_249");
+    assert_eq!(added[349].track.id, "full_99");
+    assert_eq!(added[450].track.id, "over_100");
+}
+
+#[tokio::test]
+async fn test_playlists_add_batch_failed() {
+    let sp = MockSpotify::new();
+    sp.create_playlist("big").await.unwrap();
+    sp.state.lock().await.failing_batch = Some(2);
+
+    let tracks = (0..250).map(|i| format!("track_{}", i));
+    let err = sp.playlist_add_items("big_id", tracks).await.unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "adding batch 2 of 3 to the playlist failed, 100 of 250 tracks were added: batch failed"
+    );
+    let state = sp.state.lock().await;
+    assert_eq!(state.added_batches, vec![(100, 0)]);
+    assert_eq!(state.playlists[0].tracks.len(), 100);
+}
+
+#[tokio::test]
+async fn test_playlists_remove_batches() {
+    let sp = MockSpotify::new();
+    sp.create_playlist("big").await.unwrap();
+    let tracks = (0..250).map(|i| format!("track_{}", i));
+    sp.playlist_add_items("big_id", tracks).await.unwrap();
+
+    // every other track, in no particular order
+    let removed = (0..125)
+        .rev()
+        .map(|i| (format!("track_{}", i * 2), i * 2))
+        .collect();
+    sp.playlist_remove_items("big_id", "big_snapshot", removed)
+        .await
+        .unwrap();
+
+    let state = sp.state.lock().await;
+    assert_eq!(state.removed_batches, vec![100, 25]);
+    let left = &state.playlists[0].tracks;
+    assert_eq!(left.len(), 125);
+    assert_eq!(left[0].track.id, "track_1");
+    assert_eq!(left[124].track.id, "track_249");
+}
+
+// a track of kendrick lamar added before any of his albums were released
+fn old_kendrick_track(id: &str, isrc: &str) -> SimplePlayable {
+    SimplePlayable {
+        position: 0,
+        added_at: Some(Utc.timestamp_opt(1420070400, 0).unwrap()),
+        track: SimpleTrack {
+            id: id.to_string(),
+            name: format!("{} name", id),
+            artists: vec![SimpleArtist {
+                id: "artist_1".to_string(),
+                name: "kendrick lamar".to_string(),
+                ..Default::default()
+            }],
+            isrc: Some(isrc.to_string()),
+            ..Default::default()
+        },
+    }
+}
+
+async fn playlist_with_kendrick(s: &Service<'_, MockSpotify>, sp: &MockSpotify) {
+    let input: &[u8] = "1\n".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    s.link_playlist_to_artist(&mut int, "maman", "kendrick", LinkRequest::default())
+        .await
+        .unwrap();
+
+    // a re-release of track_4 and track_2 itself
+    let tracks = &mut sp.state.lock().await.playlists[0].tracks;
+    tracks.push(old_kendrick_track("deluxe_4", "track_4 isrc"));
+    tracks.push(old_kendrick_track("track_2", "track_2 isrc"));
+}
+
+#[tokio::test]
+async fn test_playlists_update_skips_duplicates() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    playlist_with_kendrick(&s, &sp).await;
+
+    let mut update_output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut update_output);
+    s.playlists_update(&mut int, false).await.unwrap();
+    assert_eq!(update_output.len(), 0);
+
+    let playlists = &sp.state.lock().await.playlists;
+    let ids = playlists[0]
+        .tracks
+        .iter()
+        .map(|t| t.track.id.as_str())
+        .collect::<Vec<&str>>();
+    assert_eq!(
+        ids,
+        vec!["deluxe_4", "track_2", "track_1", "track_3", "track_5", "track_6"]
+    );
+}
+
+#[tokio::test]
+async fn test_playlists_update_allows_duplicates() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    playlist_with_kendrick(&s, &sp).await;
+
+    s.playlists_duplicates("maman", true).await.unwrap();
+    let mut update_output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut update_output);
+    s.playlists_update(&mut int, false).await.unwrap();
+    assert_eq!(update_output.len(), 0);
+    assert_eq!(sp.state.lock().await.playlists[0].tracks.len(), 8);
+
+    s.playlists_duplicates("doesnt exist", true)
+        .await
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn test_playlists_seed_skips_duplicates() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "1\n".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    sp.playlist_add_items("maman_id", vec!["track_1".to_string()])
+        .await
+        .unwrap();
+    s.link_playlist_to_artist(&mut int, "maman", "kendrick", seeded(2))
+        .await
+        .unwrap();
+
+    let tracks = &sp.state.lock().await.playlists[0].tracks;
+    assert_eq!(tracks.len(), 3);
+    assert_eq!(tracks[1].track.id, "track_2");
+    assert_eq!(tracks[2].track.id, "track_3");
+}
+
+#[tokio::test]
+async fn test_playlists_update_dry_run() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    playlist_with_kendrick(&s, &sp).await;
+
+    let input: &[u8] = "1\n".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+    s.link_playlist_to_artist(&mut int, "maman", "kali", LinkRequest::default())
+        .await
+        .unwrap();
+
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+    s.playlists_update(&mut int, true).await.unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split.len(), 12);
+    assert_eq!(split[0], "Planned additions: 9");
+    assert_eq!(
+        split[1],
+        "\tmaman: kendrick lamar - track_1 name (album_1_1 name) [new release]"
+    );
+    assert_eq!(
+        split[4],
+        "\tmaman: kendrick lamar - track_6 name (album_1_2 name) [new release]"
+    );
+    assert_eq!(
+        split[5],
+        "\tmaman: kali uchis - track_13 name (album_3_1 name) [popular track]"
+    );
+    assert_eq!(
+        split[9],
+        "\tmaman: kali uchis - track_17 name (album_3_2 name) [popular track]"
+    );
+    assert_eq!(split[10], "Planned removals: 0");
+
+    // nothing was added
+    let state = sp.state.lock().await;
+    assert_eq!(state.playlists[0].tracks.len(), 2);
+    assert_eq!(state.added_batches.len(), 0);
+}
+
+#[tokio::test]
+async fn test_playlists_update_release_precision() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "1\n".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    s.link_playlist_to_artist(&mut int, "maman", "bonobo", LinkRequest::default())
+        .await
+        .unwrap();
+    sp.state.lock().await.playlists[0]
+        .tracks
+        .push(SimplePlayable {
+            position: 0,
+            added_at: Some(Utc.with_ymd_and_hms(2016, 3, 10, 0, 0, 0).unwrap()),
+            track: SimpleTrack {
+                id: "track_31".to_string(),
+                artists: vec![SimpleArtist {
+                    id: "artist_6".to_string(),
+                    ..Default::default()
+                }],
+                album_id: Some("album_6_1".to_string()),
+                ..Default::default()
+            },
+        });
+
+    let mut int = Interactor::new(input, &mut output);
+    s.playlists_update(&mut int, false).await.unwrap();
+
+    // the album from 2016 may be newer than a track added in 2016, but the
+    // playlist already has it
+    let playlists = &sp.state.lock().await.playlists;
+    let ids = playlists[0]
+        .tracks
+        .iter()
+        .map(|t| t.track.id.as_str())
+        .collect::<Vec<&str>>();
+    assert_eq!(
+        ids,
+        vec!["track_31", "track_33", "track_34", "track_35", "track_36"]
+    );
+}
+
+/// Updates a playlist linked to bonobo without a checkpoint, where a track of
+/// one of bonobo's albums was added at the given time, and returns the tracks
+/// in it.
+async fn update_without_checkpoint(
+    added_at: DateTime<Utc>,
+    track: &str,
+    album: &str,
+) -> Vec<String> {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "1\n".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    st_to
+        .link_artist("maman_id", "maman", "artist_6", &LinkOptions::default())
+        .await
+        .unwrap();
+    sp.state.lock().await.playlists[0]
+        .tracks
+        .push(SimplePlayable {
+            position: 0,
+            added_at: Some(added_at),
+            track: SimpleTrack {
+                id: track.to_string(),
+                artists: vec![SimpleArtist {
+                    id: "artist_6".to_string(),
+                    ..Default::default()
+                }],
+                album_id: Some(album.to_string()),
+                ..Default::default()
+            },
+        });
+
+    s.playlists_update(&mut int, false).await.unwrap();
+    // the next update finds nothing new
+    s.playlists_update(&mut int, false).await.unwrap();
+
+    let playlists = &sp.state.lock().await.playlists;
+    playlists[0]
+        .tracks
+        .iter()
+        .map(|t| t.track.id.clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_playlists_update_same_period() {
+    // the album from July 2016 may have come out after a track added in July
+    assert_eq!(
+        update_without_checkpoint(
+            Utc.with_ymd_and_hms(2016, 7, 20, 0, 0, 0).unwrap(),
+            "track_31",
+            "album_6_1"
+        )
+        .await,
+        vec!["track_31", "track_33", "track_34", "track_35", "track_36"]
+    );
+    // and the album from 2017 after a track added in 2017
+    assert_eq!(
+        update_without_checkpoint(
+            Utc.with_ymd_and_hms(2017, 3, 1, 0, 0, 0).unwrap(),
+            "track_33",
+            "album_6_2"
+        )
+        .await,
+        vec!["track_33", "track_35", "track_36"]
+    );
+}
+
+#[tokio::test]
+async fn test_playlists_update_checkpoints() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "1\n".as_bytes();
+    let mut output = Vec::new();
+
+    s.playlists_create("maman").await.unwrap();
+    let mut int = Interactor::new(input, &mut output);
+    s.link_playlist_to_artist(&mut int, "maman", "kali", LinkRequest::default())
+        .await
+        .unwrap();
+    let mut int = Interactor::new(input, &mut output);
+    s.link_playlist_to_artist(&mut int, "maman", "kendrick", seeded(1))
+        .await
+        .unwrap();
+    // duplicates are allowed, so only the checkpoints keep tracks from being added again
+    s.playlists_duplicates("maman", true).await.unwrap();
+
+    // seeding on link starts the checkpoint
+    let before = Utc::now();
+    let st = st_to.as_mock().unwrap();
+    {
+        let checkpoints = &st.state.lock().await.checkpoints;
+        assert_eq!(checkpoints.len(), 1);
+        let kendrick = &checkpoints[&("maman_id".to_string(), "artist_1".to_string())];
+        assert!(kendrick.albums.is_empty());
+    }
+
+    // a dry run doesn't move the checkpoints
+    let mut int = Interactor::new(input, &mut output);
+    s.playlists_update(&mut int, true).await.unwrap();
+    assert_eq!(st.state.lock().await.checkpoints.len(), 1);
+
+    let mut int = Interactor::new(input, &mut output);
+    s.playlists_update(&mut int, false).await.unwrap();
+    assert_eq!(sp.state.lock().await.playlists[0].tracks.len(), 9);
+    {
+        let checkpoints = &st.state.lock().await.checkpoints;
+        assert_eq!(checkpoints.len(), 2);
+        let kali = &checkpoints[&("maman_id".to_string(), "artist_3".to_string())];
+        assert!(kali.last_checked >= before);
+        assert!(kali.albums.is_empty());
+        // released tomorrow, but already added
+        let kendrick = &checkpoints[&("maman_id".to_string(), "artist_1".to_string())];
+        assert_eq!(kendrick.albums, vec!["album_1_2".to_string()]);
+    }
+
+    // removing the tracks doesn't get the artists seeded or their albums added again
+    sp.state.lock().await.playlists[0].tracks.clear();
+    let mut int = Interactor::new(input, &mut output);
+    s.playlists_update(&mut int, false).await.unwrap();
+    assert_eq!(sp.state.lock().await.playlists[0].tracks.len(), 0);
+
+    // unlinking drops the checkpoint
+    s.unlink_artist_from_playlist("maman", "kali")
+        .await
+        .unwrap();
+    assert_eq!(st.state.lock().await.checkpoints.len(), 1);
+}
+
+#[tokio::test]
+async fn test_playlists_update_rules() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to).with_default_rules(ExclusionRules {
+        patterns: vec!["TRACK_2".to_string(), "album_1_2".to_string()],
+        skip_explicit: true,
+        ..Default::default()
+    });
+    let input: &[u8] = "1\n".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    s.link_playlist_to_artist(&mut int, "maman", "kendrick", LinkRequest::default())
+        .await
+        .unwrap();
+    let mut int = Interactor::new(input, &mut output);
+    s.playlists_update(&mut int, false).await.unwrap();
+
+    // track_2 by its name, track_3 for being explicit and the rest by their album
+    let tracks = &sp.state.lock().await.playlists[0].tracks;
+    assert_eq!(tracks.len(), 1);
+    assert_eq!(tracks[0].track.id, "track_1");
+}
+
+#[tokio::test]
+async fn test_playlists_update_long_durations() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    // more seconds than there are milliseconds in a u32
+    let s = Service::new(&sp, &st_to).with_default_rules(ExclusionRules {
+        min_duration_secs: Some(5_000_000),
+        max_duration_secs: Some(u32::MAX),
+        ..Default::default()
+    });
+    let input: &[u8] = "1\n".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    s.link_playlist_to_artist(&mut int, "maman", "kendrick", LinkRequest::default())
+        .await
+        .unwrap();
+    let mut int = Interactor::new(input, &mut output);
+    s.playlists_update(&mut int, false).await.unwrap();
+
+    // every track is shorter than the minimum
+    assert_eq!(sp.state.lock().await.playlists[0].tracks.len(), 0);
+}
+
+#[tokio::test]
+async fn test_playlists_rules() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to).with_default_rules(ExclusionRules {
+        patterns: vec!["remix".to_string()],
+        ..Default::default()
+    });
+    s.playlists_create("maman").await.unwrap();
+
+    let mut output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+    s.playlists_rules(&mut int, "maman", RulesEdit::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        str::from_utf8(&output).unwrap(),
+        "Rules (default):\n\tExcluded pattern: remix\n\tSkip explicit: false\n"
+    );
+
+    // the changes start from the default rules
+    let mut output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+    s.playlists_rules(
+        &mut int,
+        "maman",
+        RulesEdit {
+            exclude: vec!["\\blive\\b".to_string()],
+            min_duration_secs: Some(60),
+            skip_explicit: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        str::from_utf8(&output).unwrap(),
+        "Rules:\n\tExcluded pattern: remix\n\tExcluded pattern: \\blive\\b\n\tMin duration: 60s\n\tSkip explicit: true\n"
+    );
+
+    let mut output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+    s.playlists_rules(
+        &mut int,
+        "maman",
+        RulesEdit {
+            include: vec!["remix".to_string()],
+            min_duration_secs: Some(0),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let st = st_to.as_mock().unwrap();
+    assert_eq!(
+        st.state.lock().await.playlists[0].rules,
+        Some(ExclusionRules {
+            patterns: vec!["\\blive\\b".to_string()],
+            skip_explicit: true,
+            ..Default::default()
+        })
+    );
+
+    // invalid patterns aren't stored
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+    s.playlists_rules(
+        &mut int,
+        "maman",
+        RulesEdit {
+            exclude: vec!["(live".to_string()],
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        st.state.lock().await.playlists[0]
+            .rules
+            .as_ref()
+            .unwrap()
+            .patterns
+            .len(),
+        1
+    );
+
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+    s.playlists_rules(
+        &mut int,
+        "maman",
+        RulesEdit {
+            reset: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(st.state.lock().await.playlists[0].rules, None);
+
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+    s.playlists_rules(&mut int, "doesnt exist", RulesEdit::default())
+        .await
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn test_playlists_update_retention() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    playlist_with_kendrick(&s, &sp).await;
+
+    let mut output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+    s.playlists_retention(
+        &mut int,
+        "maman",
+        RetentionEdit {
+            max_tracks: Some(3),
+            max_age_days: Some(30),
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        str::from_utf8(&output).unwrap(),
+        "Max tracks: 3\nMax age: 30 days\n"
+    );
+
+    // a dry run doesn't remove anything, but counts the tracks it would add
+    let mut output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+    s.playlists_update(&mut int, true).await.unwrap();
+    assert_eq!(sp.state.lock().await.playlists[0].tracks.len(), 2);
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Planned additions: 4");
+    assert_eq!(split[5], "Planned removals: 3");
+    assert_eq!(
+        split[6],
+        "\tmaman: kendrick lamar - deluxe_4 name [past max age]"
+    );
+    assert_eq!(
+        split[7],
+        "\tmaman: kendrick lamar - track_2 name [past max age]"
+    );
+    assert_eq!(
+        split[8],
+        "\tmaman: kendrick lamar - track_1 name [over max tracks]"
+    );
+
+    // the tracks from years ago are past the age, and the oldest of the
+    // added ones is over the track count
+    let mut output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+    s.playlists_update(&mut int, false).await.unwrap();
+    {
+        let state = sp.state.lock().await;
+        let ids = state.playlists[0]
+            .tracks
+            .iter()
+            .map(|t| t.track.id.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(ids, vec!["track_3", "track_5", "track_6"]);
+        assert_eq!(state.removed_batches, vec![3]);
+    }
+
+    // zero removes the limits
+    let mut output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+    s.playlists_retention(
+        &mut int,
+        "maman",
+        RetentionEdit {
+            max_tracks: Some(0),
+            max_age_days: Some(0),
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        str::from_utf8(&output).unwrap(),
+        "Tracks are kept forever\n"
+    );
+    let st = st_to.as_mock().unwrap();
+    assert_eq!(
+        st.state.lock().await.playlists[0].retention,
+        Retention::default()
+    );
+
+    let mut output = Vec::new();
+    let mut int = Interactor::new("neverread".as_bytes(), &mut output);
+    s.playlists_retention(&mut int, "doesnt exist", RetentionEdit::default())
+        .await
+        .unwrap_err();
+}
@@ -0,0 +1,47 @@
+use crate::storage::psql::{pending_migrations, Migration};
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "baseline",
+        sql: "CREATE TABLE listen(song_id TEXT)",
+    },
+    Migration {
+        version: 2,
+        name: "second",
+        sql: "ALTER TABLE listen ADD COLUMN time TIMESTAMP WITH TIME ZONE",
+    },
+    Migration {
+        version: 3,
+        name: "third",
+        sql: "CREATE TABLE gap(from_time TIMESTAMP WITH TIME ZONE)",
+    },
+];
+
+fn versions(current: i32) -> Vec<i32> {
+    pending_migrations(current, MIGRATIONS)
+        .unwrap()
+        .iter()
+        .map(|m| m.version)
+        .collect()
+}
+
+#[test]
+fn test_psql_pending_migrations() {
+    // a new database gets all of them, in order
+    assert_eq!(versions(0), vec![1, 2, 3]);
+    // the applied ones are skipped
+    assert_eq!(versions(1), vec![2, 3]);
+    assert_eq!(versions(3), Vec::<i32>::new());
+    assert_eq!(pending_migrations(0, &[]).unwrap().len(), 0);
+}
+
+#[test]
+fn test_psql_pending_migrations_newer_database() {
+    let err = pending_migrations(4, MIGRATIONS).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "database schema version 4 is newer than 3, the latest this coolio knows"
+    );
+    pending_migrations(1, &[]).unwrap_err();
+}
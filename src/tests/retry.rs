@@ -0,0 +1,150 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use rspotify::{model::Token, AuthCodeSpotify};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::service::retry::RetrySpotify;
+use crate::service::spotify::{HTTPSpotify, Spotify};
+use crate::settings::Retry;
+
+const ARTIST: &str = "spotify:artist:0OdUWJ0sBjDrqHygGUXeCF";
+const PLAYLIST: &str = "spotify:playlist:37i9dQZF1DXcBWIGoYBM5M";
+const TRACK: &str = "spotify:track:6rqhFgbbKwnb9MLmUQDhG6";
+
+fn response(status: &str, headers: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        headers,
+        body.len(),
+        body
+    )
+}
+
+/// Serves the given responses in order, one per request, and counts the
+/// requests it received.
+async fn stand_in(responses: Vec<String>) -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let prefix = format!("http://{}/", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+
+    let counter = requests.clone();
+    tokio::spawn(async move {
+        for res in responses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // the whole request has to be read before answering it
+            let mut request = Vec::<u8>::new();
+            let mut buf = [0; 4096];
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_lowercase();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text
+                        .lines()
+                        .find_map(|l| l.strip_prefix("content-length:"))
+                        .map_or(0, |l| l.trim().parse::<usize>().unwrap());
+                    if request.len() >= end + 4 + length {
+                        break;
+                    }
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+            counter.fetch_add(1, Ordering::SeqCst);
+            stream.write_all(res.as_bytes()).await.unwrap();
+        }
+    });
+
+    (prefix, requests)
+}
+
+fn client(prefix: &str, max_time_secs: u64) -> RetrySpotify<HTTPSpotify> {
+    let mut spotify = AuthCodeSpotify::from_token(Token {
+        access_token: "token".to_string(),
+        expires_in: Duration::hours(1),
+        expires_at: Some(Utc::now() + Duration::hours(1)),
+        ..Token::default()
+    });
+    spotify.config.prefix = prefix.to_string();
+
+    RetrySpotify::new(
+        HTTPSpotify::new(spotify),
+        Retry {
+            max_time_secs,
+            initial_delay_ms: 1,
+        },
+    )
+}
+
+#[tokio::test]
+async fn test_retry_rate_limited_and_server_errors() {
+    let (prefix, requests) = stand_in(vec![
+        response("429 Too Many Requests", "Retry-After: 0\r\n", ""),
+        response("503 Service Unavailable", "", ""),
+        response("502 Bad Gateway", "", ""),
+        response("200 OK", "", r#"{"tracks": []}"#),
+    ])
+    .await;
+    let sp = client(&prefix, 10);
+
+    let tracks = sp.artist_top_tracks(ARTIST).await.unwrap();
+
+    assert!(tracks.is_empty());
+    assert_eq!(requests.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn test_retry_client_error() {
+    let (prefix, requests) = stand_in(vec![
+        response("404 Not Found", "", ""),
+        response("200 OK", "", r#"{"tracks": []}"#),
+    ])
+    .await;
+    let sp = client(&prefix, 10);
+
+    sp.artist_top_tracks(ARTIST).await.unwrap_err();
+
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_retry_max_time() {
+    let (prefix, requests) = stand_in(vec![
+        response("429 Too Many Requests", "Retry-After: 60\r\n", ""),
+        response("200 OK", "", r#"{"tracks": []}"#),
+    ])
+    .await;
+    let sp = client(&prefix, 10);
+
+    // waiting as long as asked would take over the limit
+    sp.artist_top_tracks(ARTIST).await.unwrap_err();
+
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_retry_not_idempotent() {
+    let (prefix, requests) = stand_in(vec![
+        response("429 Too Many Requests", "Retry-After: 0\r\n", ""),
+        response("500 Internal Server Error", "", ""),
+        response("200 OK", "", r#"{"snapshot_id": "snapshot"}"#),
+    ])
+    .await;
+    let sp = client(&prefix, 10);
+
+    // rate limited requests weren't carried out, failed ones might have been
+    sp.playlist_add_items(PLAYLIST, vec![TRACK.to_string()])
+        .await
+        .unwrap_err();
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    sp.playlist_add_items(PLAYLIST, vec![TRACK.to_string()])
+        .await
+        .unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
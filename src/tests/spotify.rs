@@ -0,0 +1,136 @@
+use chrono::{TimeZone, Utc};
+use rspotify::model::PlaylistItem;
+use serde_json::json;
+
+use crate::service::spotify::{artist_uri, playlist_tracks, DatePrecision, SimpleAlbum};
+
+fn album(date: &str, precision: &str) -> SimpleAlbum {
+    let precision = precision.parse::<DatePrecision>().unwrap();
+    SimpleAlbum {
+        id: "album".to_string(),
+        name: "album name".to_string(),
+        release_date: SimpleAlbum::parse_release_date(date, precision).unwrap(),
+        release_precision: precision,
+    }
+}
+
+#[test]
+fn test_parse_release_date() {
+    assert_eq!(
+        album("2021-03-05", "day").release_date,
+        Utc.with_ymd_and_hms(2021, 3, 5, 0, 0, 0).unwrap()
+    );
+    assert_eq!(
+        album("2021-03", "month").release_date,
+        Utc.with_ymd_and_hms(2021, 3, 1, 0, 0, 0).unwrap()
+    );
+    assert_eq!(
+        album("2021", "year").release_date,
+        Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap()
+    );
+
+    "week".parse::<DatePrecision>().unwrap_err();
+    SimpleAlbum::parse_release_date("2021", DatePrecision::Day).unwrap_err();
+    SimpleAlbum::parse_release_date("2021-13", DatePrecision::Month).unwrap_err();
+}
+
+#[test]
+fn test_released_after() {
+    let day = album("2021-03-05", "day");
+    assert!(day.released_after(&Utc.with_ymd_and_hms(2021, 3, 4, 23, 59, 59).unwrap()));
+    assert!(day.released_after(&Utc.with_ymd_and_hms(2021, 3, 5, 12, 0, 0).unwrap()));
+    assert!(!day.released_after(&Utc.with_ymd_and_hms(2021, 3, 6, 0, 0, 0).unwrap()));
+
+    // releases from the same month or year may have come out after the time
+    let month = album("2021-03", "month");
+    assert!(month.released_after(&Utc.with_ymd_and_hms(2021, 2, 28, 12, 0, 0).unwrap()));
+    assert!(month.released_after(&Utc.with_ymd_and_hms(2021, 3, 31, 12, 0, 0).unwrap()));
+    assert!(!month.released_after(&Utc.with_ymd_and_hms(2021, 4, 1, 0, 0, 0).unwrap()));
+
+    let year = album("2021", "year");
+    assert!(year.released_after(&Utc.with_ymd_and_hms(2020, 12, 31, 12, 0, 0).unwrap()));
+    assert!(year.released_after(&Utc.with_ymd_and_hms(2021, 6, 1, 0, 0, 0).unwrap()));
+    assert!(!year.released_after(&Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap()));
+}
+
+#[test]
+fn test_artist_uri() {
+    let uri = Some("spotify:artist:2YZyLoL8N0Wb9xBt1NhZWg".to_string());
+    assert_eq!(artist_uri("spotify:artist:2YZyLoL8N0Wb9xBt1NhZWg"), uri);
+    assert_eq!(
+        artist_uri("https://open.spotify.com/artist/2YZyLoL8N0Wb9xBt1NhZWg"),
+        uri
+    );
+    assert_eq!(
+        artist_uri("https://open.spotify.com/intl-de/artist/2YZyLoL8N0Wb9xBt1NhZWg?si=x1"),
+        uri
+    );
+    assert_eq!(
+        artist_uri(" open.spotify.com/artist/2YZyLoL8N0Wb9xBt1NhZWg/ "),
+        uri
+    );
+
+    assert_eq!(artist_uri("kendrick lamar"), None);
+    assert_eq!(artist_uri("spotify:artist:"), None);
+    assert_eq!(artist_uri("spotify:track:2YZyLoL8N0Wb9xBt1NhZWg"), None);
+    assert_eq!(
+        artist_uri("https://open.spotify.com/album/2YZyLoL8N0Wb9xBt1NhZWg"),
+        None
+    );
+}
+
+fn playlist_item(track_id: Option<&str>) -> serde_json::Value {
+    json!({
+        "added_at": "2021-03-05T12:00:00Z",
+        "added_by": null,
+        "is_local": track_id.is_none(),
+        "track": {
+            "album": {
+                "album_type": "album",
+                "artists": [],
+                "external_urls": {},
+                "href": null,
+                "id": null,
+                "images": [],
+                "name": "album name",
+            },
+            "artists": [],
+            "disc_number": 1,
+            "duration_ms": 180000,
+            "explicit": false,
+            "external_ids": {},
+            "external_urls": {},
+            "href": null,
+            "id": track_id,
+            "is_local": track_id.is_none(),
+            "name": "track name",
+            "popularity": 0,
+            "preview_url": null,
+            "track_number": 1,
+        },
+    })
+}
+
+#[test]
+fn test_playlist_tracks() {
+    let mut unavailable = playlist_item(None);
+    unavailable["track"] = json!(null);
+    // the ids are borrowed while deserializing, so this goes through a string
+    let json = json!([
+        playlist_item(Some("4iV5W9uYEdYUVa79Axb7Rh")),
+        // a local file and an item Spotify doesn't have anymore
+        playlist_item(None),
+        unavailable,
+        playlist_item(Some("1301WleyT98MSxVHPZCA6M")),
+    ])
+    .to_string();
+    let items: Vec<PlaylistItem> = serde_json::from_str(&json).unwrap();
+
+    let tracks = playlist_tracks(items, 100);
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(tracks[0].track.id, "spotify:track:4iV5W9uYEdYUVa79Axb7Rh");
+    assert_eq!(tracks[0].position, 100);
+    // the items left out still count for the positions
+    assert_eq!(tracks[1].track.id, "spotify:track:1301WleyT98MSxVHPZCA6M");
+    assert_eq!(tracks[1].position, 103);
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use chrono::{TimeZone, Utc};
+
+use crate::models::{
+    ExclusionRules, Gap, LinkCheckpoint, LinkOptions, ReleaseType, Retention, TrackMetadata,
+};
+use crate::settings::SqliteDatabase;
+use crate::storage::sqlite::Sqlite;
+use crate::storage::Storage;
+use crate::tests::listen;
+
+async fn new_storage() -> Sqlite {
+    Sqlite::new(SqliteDatabase {
+        path: ":memory:".to_string(),
+    })
+    .await
+    .unwrap()
+}
+
+fn metadata(name: &str) -> TrackMetadata {
+    TrackMetadata {
+        name: name.to_string(),
+        artist_ids: vec!["artist_1".to_string(), "artist_2".to_string()],
+        artist_names: vec!["kendrick lamar".to_string(), "rick ross".to_string()],
+        album_id: Some("album_1".to_string()),
+        album_name: None,
+        duration_ms: 180000,
+    }
+}
+
+#[tokio::test]
+async fn test_sqlite_history() {
+    let st = new_storage().await;
+    st.get_last_listen().await.unwrap_err();
+
+    let mut played = listen("track_2", 200);
+    played.ms_played = Some(1000);
+    st.add_history_batch(vec![listen("track_3", 300), listen("track_1", 100), played])
+        .await
+        .unwrap();
+
+    // recorded again, it only fills in what was missing
+    let mut again = listen("track_2", 200);
+    again.ms_played = Some(5000);
+    again.platform = Some("linux".to_string());
+    again.track = Some(metadata("track 2"));
+    st.add_history_batch(vec![again]).await.unwrap();
+    st.add_history_batch(vec![listen("track_1", 100), listen("track_1", 100)])
+        .await
+        .unwrap();
+
+    let history = st.get_history().await.unwrap();
+    assert_eq!(
+        history
+            .iter()
+            .map(|l| l.song_id.as_str())
+            .collect::<Vec<_>>(),
+        vec!["track_1", "track_2", "track_3"]
+    );
+    assert_eq!(history[1].ms_played, Some(1000));
+    assert_eq!(history[1].platform, Some("linux".to_string()));
+    assert_eq!(history[1].track, Some(metadata("track 2")));
+    assert_eq!(st.get_last_listen().await.unwrap(), listen("track_3", 300));
+    assert_eq!(st.dedupe_history().await.unwrap(), 0);
+
+    let mut tracks = HashMap::new();
+    tracks.insert("track_1".to_string(), metadata("track 1"));
+    tracks.insert("track_2".to_string(), metadata("not track 2"));
+    st.set_track_metadata(&tracks).await.unwrap();
+    let history = st.get_history().await.unwrap();
+    assert_eq!(history[0].track, Some(metadata("track 1")));
+    assert_eq!(history[1].track, Some(metadata("track 2")));
+    assert_eq!(history[2].track, None);
+
+    st.add_gap(Gap {
+        from: Utc.timestamp_opt(500, 0).unwrap(),
+        to: Utc.timestamp_opt(600, 0).unwrap(),
+    })
+    .await
+    .unwrap();
+    st.add_gap(Gap {
+        from: Utc.timestamp_opt(50, 0).unwrap(),
+        to: Utc.timestamp_opt(60, 0).unwrap(),
+    })
+    .await
+    .unwrap();
+    let gaps = st.get_gaps().await.unwrap();
+    assert_eq!(gaps.len(), 2);
+    assert_eq!(gaps[0].from, Utc.timestamp_opt(50, 0).unwrap());
+    assert_eq!(gaps[1].to, Utc.timestamp_opt(600, 0).unwrap());
+}
+
+#[tokio::test]
+async fn test_sqlite_playlists() {
+    let st = new_storage().await;
+    st.get_playlist("maman").await.unwrap_err();
+
+    st.create_playlist("maman_id", "maman").await.unwrap();
+    st.create_playlist("other_id", "other").await.unwrap();
+    let options = LinkOptions {
+        release_types: vec![ReleaseType::Album, ReleaseType::AppearsOn],
+        seed: 2,
+        include_features: false,
+    };
+    st.link_artist("maman_id", "maman", "artist_1", &LinkOptions::default())
+        .await
+        .unwrap();
+    st.link_artist("maman_id", "maman", "artist_2", &options)
+        .await
+        .unwrap();
+    // links are unique
+    st.link_artist("maman_id", "maman", "artist_1", &options)
+        .await
+        .unwrap_err();
+
+    st.set_allow_duplicates("maman_id", true).await.unwrap();
+    st.set_rules("maman_id", Some(&ExclusionRules::default()))
+        .await
+        .unwrap();
+    let retention = Retention {
+        max_tracks: Some(50),
+        max_age_days: None,
+    };
+    st.set_retention("maman_id", &retention).await.unwrap();
+    st.set_retention("nothing_id", &retention)
+        .await
+        .unwrap_err();
+
+    let playlists = st.get_playlists().await.unwrap();
+    assert_eq!(playlists.len(), 2);
+    assert_eq!(playlists[0].name, "maman");
+    assert_eq!(playlists[0].artists, vec!["artist_1", "artist_2"]);
+    assert!(playlists[0].allow_duplicates);
+    assert_eq!(playlists[0].rules, Some(ExclusionRules::default()));
+    assert_eq!(playlists[0].retention, retention);
+    assert_eq!(playlists[1].name, "other");
+    assert_eq!(playlists[1].artists.len(), 0);
+    assert!(!playlists[1].allow_duplicates);
+    assert_eq!(playlists[1].rules, None);
+
+    let playlist = st.get_playlist("maman").await.unwrap();
+    assert_eq!(playlist.id, "maman_id");
+    assert_eq!(playlist.artists, vec!["artist_1", "artist_2"]);
+    assert_eq!(playlist.retention, retention);
+
+    assert_eq!(
+        st.get_link_options("maman_id", "artist_2").await.unwrap(),
+        options
+    );
+    st.set_link_options("maman_id", "artist_1", &options)
+        .await
+        .unwrap();
+    assert_eq!(
+        st.get_link_options("maman_id", "artist_1").await.unwrap(),
+        options
+    );
+    st.get_link_options("other_id", "artist_1")
+        .await
+        .unwrap_err();
+
+    assert_eq!(
+        st.get_checkpoint("maman_id", "artist_1").await.unwrap(),
+        None
+    );
+    let checkpoint = LinkCheckpoint {
+        last_checked: Utc.timestamp_opt(1000, 0).unwrap(),
+        albums: vec!["album_1".to_string()],
+    };
+    st.set_checkpoint("maman_id", "artist_1", &checkpoint)
+        .await
+        .unwrap();
+    assert_eq!(
+        st.get_checkpoint("maman_id", "artist_1").await.unwrap(),
+        Some(checkpoint.clone())
+    );
+    st.set_checkpoint("other_id", "artist_1", &checkpoint)
+        .await
+        .unwrap_err();
+
+    st.unlink_artist("maman_id", "artist_1").await.unwrap();
+    st.unlink_artist("maman_id", "artist_1").await.unwrap_err();
+    let playlist = st.get_playlist("maman").await.unwrap();
+    assert_eq!(playlist.artists, vec!["artist_2"]);
+    assert!(playlist.allow_duplicates);
+}
@@ -0,0 +1,236 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::str;
+
+use crate::models::{ArtistChoice, LinkOptions, LinkRequest, ReleaseType};
+use crate::service::io::Interactor;
+use crate::service::spotify::SimplePlaylist;
+use crate::service::{Service, ServiceTrait};
+use crate::storage::mock::Mock as MockStorage;
+use crate::storage::{Storage, StorageBehavior};
+use crate::tests::mock_spotify::MockSpotify;
+
+fn write_sync_file(name: &str, content: &str) -> PathBuf {
+    let path = env::temp_dir().join(name);
+    fs::write(&path, content).unwrap();
+    path
+}
+
+const SYNC_FILE: &str = r#"
+[[playlists]]
+name = "maman"
+
+[[playlists.artists]]
+uri = "spotify:artist:artist_1"
+seed = 3
+
+[[playlists.artists]]
+uri = "https://open.spotify.com/artist/artist_2?si=abc"
+types = ["album"]
+features = false
+
+[[playlists]]
+name = "existing"
+artists = [{ uri = "spotify:artist:artist_6" }]
+
+[[playlists]]
+name = "new"
+"#;
+
+#[tokio::test]
+async fn test_sync() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    s.playlists_create("maman").await.unwrap();
+    s.playlists_create("untouched").await.unwrap();
+    for (playlist, artist) in [
+        ("maman", "rick ross"),
+        ("maman", "kali uchis"),
+        ("untouched", "bonobo"),
+    ] {
+        s.link_playlist_to_artist(
+            &mut int,
+            playlist,
+            artist,
+            LinkRequest {
+                choice: ArtistChoice::Unique,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    }
+    sp.state.lock().await.playlists.push(SimplePlaylist {
+        id: "existing_id".to_string(),
+        name: "existing".to_string(),
+        ..Default::default()
+    });
+
+    let path = write_sync_file("coolio_test_sync.toml", SYNC_FILE);
+    s.sync(&mut int, path.to_str().unwrap(), false)
+        .await
+        .unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[0], "Planned changes: 6");
+    assert_eq!(
+        split[1],
+        "\t+ link kendrick lamar to maman (types: album,single, seed: 3)"
+    );
+    assert_eq!(
+        split[2],
+        "\t~ change rick ross in maman (types: album, seed: 5, without features)"
+    );
+    assert_eq!(split[3], "\t- unlink kali uchis from maman");
+    assert_eq!(split[4], "\t+ automate existing");
+    assert_eq!(
+        split[5],
+        "\t+ link bonobo to existing (types: album,single, seed: 5)"
+    );
+    assert_eq!(split[6], "\t+ create new");
+    assert_eq!(split[7], "");
+
+    // planning doesn't change anything
+    {
+        let st = st_to.as_mock().unwrap();
+        let playlists = &st.state.lock().await.playlists;
+        assert_eq!(playlists.len(), 2);
+        assert_eq!(playlists[0].artists, vec!["artist_2", "artist_3"]);
+    }
+
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+    s.sync(&mut int, path.to_str().unwrap(), true)
+        .await
+        .unwrap();
+    let output_str = str::from_utf8(&output).unwrap();
+    let split: Vec<&str> = output_str.split("\n").collect();
+    assert_eq!(split[7], "Applied changes: 6");
+
+    {
+        let st = st_to.as_mock().unwrap();
+        let playlists = &st.state.lock().await.playlists;
+        assert_eq!(playlists.len(), 4);
+        assert_eq!(playlists[0].name, "maman");
+        assert_eq!(playlists[0].artists, vec!["artist_2", "artist_1"]);
+        assert_eq!(playlists[1].name, "untouched");
+        assert_eq!(playlists[1].artists, vec!["artist_6"]);
+        assert_eq!(playlists[2].name, "existing");
+        assert_eq!(playlists[2].id, "existing_id");
+        assert_eq!(playlists[2].artists, vec!["artist_6"]);
+        assert_eq!(playlists[3].name, "new");
+        assert_eq!(playlists[3].artists.len(), 0);
+    }
+    assert_eq!(
+        st_to
+            .get_link_options("maman_id", "artist_2")
+            .await
+            .unwrap(),
+        LinkOptions {
+            release_types: vec![ReleaseType::Album],
+            seed: 5,
+            include_features: false,
+        }
+    );
+    assert_eq!(
+        st_to
+            .get_link_options("maman_id", "artist_1")
+            .await
+            .unwrap()
+            .seed,
+        3
+    );
+    // linked artists are seeded right away, as with playlists link
+    for (playlist, artist) in [("maman_id", "artist_1"), ("existing_id", "artist_6")] {
+        assert!(st_to
+            .get_checkpoint(playlist, artist)
+            .await
+            .unwrap()
+            .is_some());
+    }
+    assert_eq!(sp.state.lock().await.playlists[0].tracks.len(), 3);
+
+    // once applied, there is nothing left to do
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+    s.sync(&mut int, path.to_str().unwrap(), true)
+        .await
+        .unwrap();
+    assert_eq!(str::from_utf8(&output).unwrap(), "Planned changes: 0\n");
+}
+
+#[tokio::test]
+async fn test_sync_artist_looked_up_once() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    let path = write_sync_file(
+        "coolio_test_sync_cached.toml",
+        "[[playlists]]\nname = \"maman\"\nartists = [{ uri = \"spotify:artist:artist_1\" }]\n\
+         [[playlists]]\nname = \"papa\"\nartists = [{ uri = \"spotify:artist:artist_1\" }]\n",
+    );
+    s.sync(&mut int, path.to_str().unwrap(), false)
+        .await
+        .unwrap();
+
+    let output_str = str::from_utf8(&output).unwrap();
+    assert!(output_str.starts_with("Planned changes: 4\n"));
+    assert_eq!(sp.state.lock().await.artist_calls, 1);
+}
+
+#[tokio::test]
+async fn test_sync_invalid_file() {
+    let st_to = StorageBehavior::from(MockStorage::new());
+    let sp = MockSpotify::new();
+    let s = Service::new(&sp, &st_to);
+    let input: &[u8] = "neverread".as_bytes();
+    let mut output = Vec::new();
+    let mut int = Interactor::new(input, &mut output);
+
+    for (name, content) in [
+        // not an artist uri
+        (
+            "coolio_test_sync_name.toml",
+            "[[playlists]]\nname = \"maman\"\nartists = [{ uri = \"kendrick lamar\" }]\n",
+        ),
+        // the artist doesn't exist
+        (
+            "coolio_test_sync_missing.toml",
+            "[[playlists]]\nname = \"maman\"\nartists = [{ uri = \"spotify:artist:nobody\" }]\n",
+        ),
+        (
+            "coolio_test_sync_types.toml",
+            "[[playlists]]\nname = \"maman\"\n\
+             artists = [{ uri = \"spotify:artist:artist_1\", types = [\"ep\"] }]\n",
+        ),
+        (
+            "coolio_test_sync_twice.toml",
+            "[[playlists]]\nname = \"maman\"\n\
+             artists = [{ uri = \"spotify:artist:artist_1\" }, { uri = \"spotify:artist:artist_1\" }]\n",
+        ),
+        (
+            "coolio_test_sync_playlists.toml",
+            "[[playlists]]\nname = \"maman\"\n[[playlists]]\nname = \"maman\"\n",
+        ),
+    ] {
+        let path = write_sync_file(name, content);
+        s.sync(&mut int, path.to_str().unwrap(), true)
+            .await
+            .unwrap_err();
+    }
+    assert_eq!(output.len(), 0);
+
+    let st = st_to.as_mock().unwrap();
+    assert_eq!(st.state.lock().await.playlists.len(), 0);
+}